@@ -1,33 +1,50 @@
 use crate::config::GridParams;
 use crate::error::{Error, Result};
+use crate::fft::FftContext;
 use crate::types::{ImageData, Spot};
 use ndarray::{Array2, ArrayView2};
 use num_complex::Complex;
-use rustfft::{Fft, FftPlanner};
 use std::f64::consts::PI;
-use std::sync::Arc;
+
+/// Lattice parameters estimated from the image spectrum
+#[derive(Debug, Clone, PartialEq)]
+pub struct FftGridEstimate {
+    /// Position (x, y) of one lattice point (a spot center)
+    pub lattice_point: (f64, f64),
+    pub pitch_x: f64,
+    pub pitch_y: f64,
+    /// Rotation in degrees
+    pub rotation: f64,
+    /// Dominant spectral peak power relative to the mean power
+    pub peak_ratio: f64,
+}
 
 /// FFT-based grid detection using frequency domain analysis
+/// Returns (x, y, rotation) of a lattice point near the image origin
 pub fn fft_grid_detection(
     image: &ImageData,
     params: &GridParams,
 ) -> Result<(f64, f64, f64)> {
+    let estimate = fft_grid_estimate(image, params)?;
+    Ok((
+        estimate.lattice_point.0,
+        estimate.lattice_point.1,
+        estimate.rotation,
+    ))
+}
+
+/// Estimate pitch, rotation and lattice phase from the image spectrum
+pub fn fft_grid_estimate(image: &ImageData, params: &GridParams) -> Result<FftGridEstimate> {
     tracing::info!("Starting FFT-based grid detection");
 
     let data = &image.data;
     let (height, width) = data.dim();
 
-    // Convert to complex numbers for FFT
-    let mut complex_data: Vec<Complex<f64>> = data
-        .iter()
-        .map(|&val| Complex::new(val as f64, 0.0))
-        .collect();
-
-    // Perform 2D FFT
-    let fft_result = compute_2d_fft(&mut complex_data, height, width)?;
+    // Perform 2D FFT with the shared plan cache
+    let fft_result = compute_2d_fft(FftContext::shared(), data, height, width)?;
 
     // Find dominant frequency (spot pitch)
-    let (freq_x, freq_y) = find_dominant_frequency(&fft_result, height, width, params)?;
+    let (freq_x, freq_y, peak_ratio) = find_dominant_frequency(&fft_result, height, width, params)?;
 
     // Calculate grid parameters from frequency
     let pitch_x = if freq_x > 0.0 { width as f64 / freq_x } else { params.spot_pitch };
@@ -39,58 +56,39 @@ pub fn fft_grid_detection(
     let data_f64 = data.mapv(|v| v as f64);
 
     // Find grid origin using phase correlation
-    let (origin_x, origin_y) = find_grid_origin(&data_f64, pitch_x, pitch_y)?;
+    let lattice_point = find_grid_origin(&data_f64, pitch_x, pitch_y)?;
 
     // Calculate rotation from frequency components
     let rotation = calculate_rotation(freq_x, freq_y);
 
     tracing::info!("FFT grid detection: origin=({:.2}, {:.2}), rotation={:.2}°",
-                   origin_x, origin_y, rotation);
-
-    Ok((origin_x, origin_y, rotation))
+                   lattice_point.0, lattice_point.1, rotation);
+
+    Ok(FftGridEstimate {
+        lattice_point,
+        pitch_x,
+        pitch_y,
+        rotation,
+        peak_ratio,
+    })
 }
 
-/// Compute 2D FFT of image data
+/// Compute 2D FFT of image data as a row-major buffer
 fn compute_2d_fft(
-    data: &mut [Complex<f64>],
+    ctx: &FftContext,
+    data: &Array2<u16>,
     height: usize,
     width: usize,
 ) -> Result<Vec<Complex<f64>>> {
-    let mut planner = FftPlanner::new();
-
-    // FFT along rows
-    let fft_row = planner.plan_fft_forward(width);
-    for row in 0..height {
-        let start = row * width;
-        let end = start + width;
-        fft_row.process(&mut data[start..end]);
+    let spectrum = ctx.fft2(&data.mapv(|v| v as f64));
+    if spectrum.dim() != (height, width) {
+        return Err(Error::InvalidDimensions {
+            expected: format!("{}x{}", width, height),
+            actual: format!("{}x{}", spectrum.ncols(), spectrum.nrows()),
+        });
     }
 
-    // Transpose for column FFT
-    let mut transposed = vec![Complex::new(0.0, 0.0); height * width];
-    for i in 0..height {
-        for j in 0..width {
-            transposed[j * height + i] = data[i * width + j];
-        }
-    }
-
-    // FFT along columns (now rows after transpose)
-    let fft_col = planner.plan_fft_forward(height);
-    for col in 0..width {
-        let start = col * height;
-        let end = start + height;
-        fft_col.process(&mut transposed[start..end]);
-    }
-
-    // Transpose back
-    let mut result = vec![Complex::new(0.0, 0.0); height * width];
-    for i in 0..width {
-        for j in 0..height {
-            result[j * width + i] = transposed[i * height + j];
-        }
-    }
-
-    Ok(result)
+    Ok(spectrum.into_iter().collect())
 }
 
 /// Find dominant frequency corresponding to spot pitch
@@ -99,7 +97,7 @@ fn find_dominant_frequency(
     height: usize,
     width: usize,
     params: &GridParams,
-) -> Result<(f64, f64)> {
+) -> Result<(f64, f64, f64)> {
     // Compute power spectrum
     let mut power_spectrum = Array2::zeros((height, width));
     for i in 0..height {
@@ -134,10 +132,14 @@ fn find_dominant_frequency(
 
     if max_power == 0.0 {
         tracing::warn!("No dominant frequency found, using default pitch");
-        return Ok((0.0, 0.0));
+        return Ok((0.0, 0.0, 0.0));
     }
 
-    Ok((peak_x, peak_y))
+    // Exclude the DC term, which dominates the mean for unnormalized images
+    let mean_power = (power_spectrum.sum() - power_spectrum[[0, 0]]) / (height * width - 1).max(1) as f64;
+    let peak_ratio = if mean_power > 0.0 { max_power / mean_power } else { 0.0 };
+
+    Ok((peak_x, peak_y, peak_ratio))
 }
 
 /// Find grid origin using spatial correlation
@@ -188,7 +190,8 @@ fn find_grid_origin(
         }
     }
 
-    Ok((best_x, best_y))
+    // The template is centered on a lattice point
+    Ok((best_x + center, best_y + center))
 }
 
 /// Compute local correlation between image and template
@@ -1,49 +1,35 @@
-use crate::config::GridParams;
 use crate::error::{Error, Result};
-use crate::image_processing::{compute_gradient, gaussian_blur, normalize_image};
-use crate::types::{ImageData, Spot};
+use crate::image_processing::{compute_gradient, gaussian_blur};
+use crate::segmentation::{Circle, FitQuality, SpotContext, SpotFit};
 use ndarray::Array2;
 use rayon::prelude::*;
 use std::f64::consts::PI;
 
+/// Hough accumulator peak
 #[derive(Debug, Clone)]
-pub struct Circle {
-    pub x: f64,
-    pub y: f64,
-    pub radius: f64,
-    pub votes: usize,
+struct Candidate {
+    circle: Circle,
+    votes: usize,
 }
 
 /// Advanced Hough Circle Transform implementation
-pub fn hough_circle_detection(
-    image: &ImageData,
-    spot: &Spot,
-    params: &GridParams,
-) -> Result<Option<Circle>> {
-    // Extract region around expected spot location
-    let search_radius = params.spot_pitch;
-    let x_start = (spot.grid_x - search_radius).max(0.0) as usize;
-    let x_end = (spot.grid_x + search_radius).min(image.width as f64 - 1.0) as usize;
-    let y_start = (spot.grid_y - search_radius).max(0.0) as usize;
-    let y_end = (spot.grid_y + search_radius).min(image.height as f64 - 1.0) as usize;
-
-    if x_end <= x_start || y_end <= y_start {
-        return Ok(None);
-    }
-
-    let region_height = y_end - y_start + 1;
-    let region_width = x_end - x_start + 1;
+pub fn hough_circle_detection(ctx: &SpotContext<'_>) -> Result<Option<SpotFit>> {
+    let params = ctx.params;
 
-    // Extract region
-    let mut region = Array2::zeros((region_height, region_width));
-    for y in 0..region_height {
-        for x in 0..region_width {
-            region[[y, x]] = image.data[[y_start + y, x_start + x]];
-        }
-    }
-
-    // Preprocess: normalize and blur
-    let normalized = normalize_image(&region);
+    // Extract region around expected spot location
+    let region = match ctx.region(params.spot_pitch) {
+        Some(region) => region,
+        None => return Ok(None),
+    };
+    let (region_height, region_width) = region.data.dim();
+
+    // Preprocess: normalize to the local maximum and blur
+    let local_max = region.data.iter().copied().fold(0.0, f64::max);
+    let normalized = if local_max > 0.0 {
+        region.data.mapv(|v| v / local_max)
+    } else {
+        Array2::zeros(region.data.dim())
+    };
     let blurred = gaussian_blur(&normalized, 1.5);
 
     // Compute gradients
@@ -65,18 +51,14 @@ pub fn hough_circle_detection(
 
     // Find best circle
     if let Some(best) = circles.first() {
-        // Adjust coordinates back to image space
-        let circle = Circle {
-            x: best.x + x_start as f64,
-            y: best.y + y_start as f64,
-            radius: best.radius,
-            votes: best.votes,
-        };
+        // Refine circle parameters using least squares (region coordinates)
+        let mut fit = refine_circle_parameters(&gradient, best, &edges)?;
 
-        // Refine circle parameters using least squares
-        let refined = refine_circle_parameters(&gradient, &circle, &edges)?;
+        // Adjust coordinates back to image space
+        fit.circle.x += region.x_start as f64;
+        fit.circle.y += region.y_start as f64;
 
-        Ok(Some(refined))
+        Ok(Some(fit))
     } else {
         Ok(None)
     }
@@ -112,7 +94,7 @@ fn hough_transform(
     max_radius: usize,
     width: usize,
     height: usize,
-) -> Result<Vec<Circle>> {
+) -> Result<Vec<Candidate>> {
     // Create accumulator array [x, y, radius]
     let num_radii = max_radius - min_radius + 1;
     let mut accumulator = vec![vec![vec![0usize; num_radii]; width]; height];
@@ -152,10 +134,12 @@ fn hough_transform(
             for r_idx in 0..num_radii {
                 let votes = accumulator[y][x][r_idx];
                 if votes >= min_votes {
-                    circles.push(Circle {
-                        x: x as f64,
-                        y: y as f64,
-                        radius: (min_radius + r_idx) as f64,
+                    circles.push(Candidate {
+                        circle: Circle {
+                            x: x as f64,
+                            y: y as f64,
+                            radius: (min_radius + r_idx) as f64,
+                        },
                         votes,
                     });
                 }
@@ -168,8 +152,10 @@ fn hough_transform(
 
     // Non-maximum suppression: remove overlapping circles
     let mut filtered = Vec::new();
-    for circle in circles {
-        let overlaps = filtered.iter().any(|c: &Circle| {
+    for candidate in circles {
+        let circle = &candidate.circle;
+        let overlaps = filtered.iter().any(|other: &Candidate| {
+            let c = &other.circle;
             let dx = circle.x - c.x;
             let dy = circle.y - c.y;
             let dist = (dx * dx + dy * dy).sqrt();
@@ -177,7 +163,7 @@ fn hough_transform(
         });
 
         if !overlaps {
-            filtered.push(circle);
+            filtered.push(candidate);
         }
 
         if filtered.len() >= 3 {
@@ -191,10 +177,18 @@ fn hough_transform(
 /// Refine circle parameters using least squares fitting
 fn refine_circle_parameters(
     gradient: &Array2<f64>,
-    initial: &Circle,
+    candidate: &Candidate,
     edges: &Array2<bool>,
-) -> Result<Circle> {
+) -> Result<SpotFit> {
     let (height, width) = gradient.dim();
+    let initial = &candidate.circle;
+    let unrefined = || {
+        let quality = FitQuality {
+            votes: candidate.votes,
+            ..Default::default()
+        };
+        SpotFit::new(*initial, quality)
+    };
 
     // Collect edge points near the circle
     let mut edge_points = Vec::new();
@@ -217,7 +211,7 @@ fn refine_circle_parameters(
     }
 
     if edge_points.len() < 10 {
-        return Ok(initial.clone());
+        return Ok(unrefined());
     }
 
     // Fit circle using algebraic approach
@@ -262,7 +256,7 @@ fn refine_circle_parameters(
         + a13 * (a12 * a23 - a13 * a22);
 
     if det.abs() < 1e-10 {
-        return Ok(initial.clone());
+        return Ok(unrefined());
     }
 
     let cx = (b1 * (a22 * a33 - a23 * a23)
@@ -288,14 +282,18 @@ fn refine_circle_parameters(
 
     // Validate refined parameters
     if radius > 0.0 && radius < width as f64 && radius < height as f64 {
-        Ok(Circle {
+        let circle = Circle {
             x: cx,
             y: cy,
             radius,
-            votes: initial.votes,
-        })
+        };
+        let quality = FitQuality {
+            votes: candidate.votes,
+            ..FitQuality::from_points(&edge_points, &circle)
+        };
+        Ok(SpotFit::new(circle, quality))
     } else {
-        Ok(initial.clone())
+        Ok(unrefined())
     }
 }
 
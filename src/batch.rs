@@ -1,26 +1,350 @@
-use crate::config::{GridParams, SegmentationMethod};
+use crate::checkpoint::{group_input_hash, journal_path, Checkpoint, JournalEntry};
+use crate::config::{
+    FailurePolicy, GridDetectionMethod, GridModelType, GridParams, GridQualityAction,
+    IntensityNormalization, OutputFormat, OutputOrder, SegmentationMethod,
+};
 use crate::error::{Error, Result};
-use crate::grid::process_gridding;
+use crate::grid::{detect_grid, grid_spots};
+use crate::grid_model::{apply_grid_model, fit_grid_model, GridModelFit};
+use crate::grid_quality::{assess_grid, GridQuality};
 use crate::image_processing::preprocess_images;
-use crate::io::{load_images, load_tiff_image, read_layout_file, write_progress};
-use crate::quantification::quantify_spots;
-use crate::segmentation::segment_spots;
-use crate::types::{BatchConfig, GroupConfig, ImageType, SpotResult};
-use rayon::prelude::*;
+use crate::progress::{
+    estimate_remaining, millis, JsonLinesProgress, NoProgress, ProgressEvent, ProgressObserver,
+};
+use crate::io::table::{
+    append_table_writer, create_table_writer, delimited_reader, write_table, TableRecord,
+    TableWriter,
+};
+use crate::io::{load_grid_edit, load_images, load_tiff_image, read_layout_file, write_progress};
+use crate::manual::{apply_spot_overrides, read_spot_overrides};
+use crate::quantification::{quantify_series, quantify_spots};
+use crate::segmentation::{segment_spots, SegmenterRegistry};
+use crate::types::{BatchConfig, GroupConfig, ImageData, ImageType, Spot, SpotResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Processing stage of a group, reported when the group fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupStage {
+    LoadImages,
+    Configuration,
+    Layout,
+    Gridding,
+    Segmentation,
+    Quantification,
+}
+
+impl GroupStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupStage::LoadImages => "load_images",
+            GroupStage::Configuration => "configuration",
+            GroupStage::Layout => "layout",
+            GroupStage::Gridding => "gridding",
+            GroupStage::Segmentation => "segmentation",
+            GroupStage::Quantification => "quantification",
+        }
+    }
+}
+
+/// A group that failed, with the stage it failed in
+#[derive(Debug)]
+pub struct GroupFailure {
+    pub group_id: String,
+    pub stage: GroupStage,
+    pub error: Error,
+}
+
+impl GroupFailure {
+    /// Row for the batch error report
+    pub fn record(&self) -> GroupErrorRecord {
+        GroupErrorRecord {
+            group_id: self.group_id.clone(),
+            stage: self.stage.as_str().to_string(),
+            error: self.error.kind().to_string(),
+            message: self.error.to_string(),
+        }
+    }
+}
+
+/// Row of the batch error report file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupErrorRecord {
+    #[serde(rename = "groupId")]
+    pub group_id: String,
+    pub stage: String,
+    pub error: String,
+    pub message: String,
+}
+
+/// Row of the batch grid model report: the grid model fitted for one group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridModelRecord {
+    #[serde(rename = "groupId")]
+    pub group_id: String,
+    #[serde(rename = "grdModel")]
+    pub model: String,
+    #[serde(rename = "grdModelPitchX")]
+    pub pitch_x: f64,
+    #[serde(rename = "grdModelPitchY")]
+    pub pitch_y: f64,
+    /// Shear in degrees
+    #[serde(rename = "grdModelShear")]
+    pub shear: f64,
+    /// Residuals of the fit in pixels
+    #[serde(rename = "grdModelResidualRms")]
+    pub residual_rms: f64,
+    #[serde(rename = "grdModelResidualMedian")]
+    pub residual_median: f64,
+    #[serde(rename = "grdModelResidualMax")]
+    pub residual_max: f64,
+    /// Spots with non-zero weight in the fit
+    #[serde(rename = "grdModelInliers")]
+    pub inliers: usize,
+}
+
+impl GridModelRecord {
+    fn new(group_id: &str, fit: &GridModelFit) -> Self {
+        Self {
+            group_id: group_id.to_string(),
+            model: format!("{:?}", fit.model.model_type),
+            pitch_x: fit.model.pitch_x(),
+            pitch_y: fit.model.pitch_y(),
+            shear: fit.model.shear(),
+            residual_rms: fit.residuals.rms,
+            residual_median: fit.residuals.median,
+            residual_max: fit.residuals.max,
+            inliers: fit.inliers,
+        }
+    }
+}
+
+/// Results of one group
+#[derive(Debug, Clone)]
+pub struct GroupResults {
+    pub spots: Vec<SpotResult>,
+    /// Grid model fitted to the group (`grdModel`), none for the rigid grid
+    pub grid_model: Option<GridModelRecord>,
+}
+
+/// Outcome of one group: its results or the failure
+pub type GroupOutcome = std::result::Result<GroupResults, GroupFailure>;
 
 /// Process a single image group
 pub fn process_single_group(config: &GroupConfig) -> Result<Vec<SpotResult>> {
+    process_group_staged(config)
+        .map(|results| results.spots)
+        .map_err(|failure| failure.error)
+}
+
+/// Process a single image group, tagging any error with the stage it occurred in
+pub fn process_group_staged(config: &GroupConfig) -> GroupOutcome {
+    process_group_observed(config, &NoProgress)
+}
+
+/// Emits a `StageFinished` event with the time since the previous stage
+struct StageTimer<'a> {
+    group_id: &'a str,
+    observer: &'a dyn ProgressObserver,
+    last: Instant,
+}
+
+impl StageTimer<'_> {
+    fn finish(&mut self, stage: GroupStage) {
+        let now = Instant::now();
+        self.observer.on_event(&ProgressEvent::StageFinished {
+            group_id: self.group_id.to_string(),
+            stage: stage.as_str().to_string(),
+            elapsed_ms: millis(now - self.last),
+        });
+        self.last = now;
+    }
+}
+
+/// [`process_group_staged`], reporting stage timings to `observer`
+pub fn process_group_observed(config: &GroupConfig, observer: &dyn ProgressObserver) -> GroupOutcome {
+    let mut timer = StageTimer {
+        group_id: &config.group_id,
+        observer,
+        last: Instant::now(),
+    };
+
     tracing::info!("Processing group: {}", config.group_id);
 
-    // Load images
-    let images = load_images(&config.images_list)?;
+    let images = load_group_images(config)?;
+    timer.finish(GroupStage::LoadImages);
+
+    let located = locate_spots(config, &images, &mut timer)?;
+
+    // Quantify spots: the grid image only, or every image of the series
+    let mut results = quantify_group(config, &images, &located.grid_image, &located.spots, &located.params)
+        .map_err(failure_at(config, GroupStage::Quantification))?;
+    let needs_review = located.quality.is_below(&located.params);
+    for result in &mut results {
+        result.grid_quality = located.quality.score;
+        result.needs_review = needs_review;
+    }
+    timer.finish(GroupStage::Quantification);
+
+    tracing::info!(
+        "Group {} completed: {} spots processed",
+        config.group_id,
+        results.len()
+    );
+
+    Ok(GroupResults {
+        spots: results,
+        grid_model: located.model_fit.as_ref().map(|fit| GridModelRecord::new(&config.group_id, fit)),
+    })
+}
+
+/// Converts an error into a failure of `config`'s group at `stage`
+fn failure_at(config: &GroupConfig, stage: GroupStage) -> impl Fn(Error) -> GroupFailure + '_ {
+    move |error| GroupFailure {
+        group_id: config.group_id.clone(),
+        stage,
+        error,
+    }
+}
 
+/// Load the images of a group, failing on an empty image list
+pub(crate) fn load_group_images(config: &GroupConfig) -> std::result::Result<Vec<ImageData>, GroupFailure> {
+    let at = failure_at(config, GroupStage::LoadImages);
+    let images = load_images(&config.images_list).map_err(&at)?;
     if images.is_empty() {
-        return Err(Error::InvalidParameter("No images in group".to_string()));
+        return Err(at(Error::InvalidParameter("No images in group".to_string())));
+    }
+    Ok(images)
+}
+
+/// Spots of a group after gridding and segmentation
+pub(crate) struct LocatedSpots {
+    pub params: GridParams,
+    pub grid_image: ImageData,
+    /// Grid positions before segmentation, in spot order
+    pub grid_positions: Vec<(f64, f64)>,
+    pub spots: Vec<Spot>,
+    pub quality: GridQuality,
+    /// Fit of a non-rigid `grdModel`, if one was fitted
+    pub model_fit: Option<GridModelFit>,
+}
+
+/// Grid and segment a group's spots on its grid image
+fn locate_spots(
+    config: &GroupConfig,
+    images: &[ImageData],
+    timer: &mut StageTimer,
+) -> std::result::Result<LocatedSpots, GroupFailure> {
+    let at = |stage| failure_at(config, stage);
+
+    let params = group_params(config, images).map_err(at(GroupStage::Configuration))?;
+    timer.finish(GroupStage::Configuration);
+
+    // Load array layout
+    let layout = match params.array_layout_file {
+        Some(ref layout_file) => read_layout_file(layout_file).map_err(at(GroupStage::Layout))?,
+        None => {
+            return Err(at(GroupStage::Layout)(Error::InvalidConfiguration(
+                "Array layout file required".to_string(),
+            )))
+        }
+    };
+    timer.finish(GroupStage::Layout);
+
+    // Preprocess images and process gridding
+    let grid_image =
+        preprocess_images(images, None, &config.use_image).map_err(at(GroupStage::Gridding))?;
+    let mut grid = detect_grid(std::slice::from_ref(&grid_image), &layout, &params)
+        .map_err(at(GroupStage::Gridding))?;
+    if let Some(ref path) = config.grid_edit_file {
+        let edit = load_grid_edit(path).map_err(at(GroupStage::Gridding))?;
+        tracing::info!("Group {}: applying grid edit {:?}", config.group_id, edit);
+        grid.apply_edit(&edit);
+    }
+    let mut spots = grid_spots(&grid, &layout);
+    let grid_positions: Vec<(f64, f64)> = spots.iter().map(|s| (s.grid_x, s.grid_y)).collect();
+    timer.finish(GroupStage::Gridding);
+
+    // Segment spots (re-enabled for Phase 2)
+    segment_spots(&grid_image, &mut spots, &params).map_err(at(GroupStage::Segmentation))?;
+
+    // Manual corrections are kept by the grid model refit below
+    if let Some(ref path) = config.manual_spots_file {
+        read_spot_overrides(path)
+            .and_then(|overrides| apply_spot_overrides(&mut spots, &overrides))
+            .map_err(at(GroupStage::Segmentation))?;
     }
 
+    // Refit the grid geometry to the segmented centers and re-segment; a
+    // manually edited grid is kept as the user left it
+    let model_fit = if params.grid_model == GridModelType::Rigid {
+        None
+    } else if config.grid_edit_file.is_some() {
+        tracing::info!(
+            "Group {}: grid was edited, skipping the {:?} grid model refit",
+            config.group_id,
+            params.grid_model
+        );
+        None
+    } else {
+        refine_with_grid_model(&grid_image, &mut spots, &params, &config.group_id)
+            .map_err(at(GroupStage::Segmentation))?
+    };
+    timer.finish(GroupStage::Segmentation);
+
+    let quality = assess_grid(&grid, &spots, &grid_positions, &params);
+    tracing::info!(
+        "Group {}: grid quality {:.3} (peak z-score {:?}, references found {:.2}, residual rms {:.3} pitch)",
+        config.group_id,
+        quality.score,
+        quality.peak_zscore,
+        quality.reference_fraction,
+        quality.residual_rms
+    );
+    if quality.is_below(&params) {
+        if params.grid_quality_action == GridQualityAction::Fail {
+            return Err(at(GroupStage::Gridding)(Error::GridDetectionFailed(format!(
+                "grid quality {:.3} is below grdMinQuality {}",
+                quality.score, params.min_grid_quality
+            ))));
+        }
+        tracing::warn!(
+            "Group {}: grid quality {:.3} is below {}, marked for review",
+            config.group_id,
+            quality.score,
+            params.min_grid_quality
+        );
+    }
+
+    Ok(LocatedSpots {
+        params,
+        grid_image,
+        grid_positions,
+        spots,
+        quality,
+        model_fit,
+    })
+}
+
+/// [`locate_spots`] without progress reporting
+pub(crate) fn locate_group_spots(
+    config: &GroupConfig,
+    images: &[ImageData],
+) -> std::result::Result<LocatedSpots, GroupFailure> {
+    let mut timer = StageTimer {
+        group_id: &config.group_id,
+        observer: &NoProgress,
+        last: Instant::now(),
+    };
+    locate_spots(config, images, &mut timer)
+}
+
+/// Build grid parameters for a group from its configuration and first image
+pub(crate) fn group_params(config: &GroupConfig, images: &[ImageData]) -> Result<GridParams> {
     // Detect image type and set spot pitch if needed
     let image_type = ImageType::detect(images[0].width, images[0].height);
     let mut spot_pitch = config.spot_pitch;
@@ -34,126 +358,709 @@ pub fn process_single_group(config: &GroupConfig) -> Result<Vec<SpotResult>> {
     // Parse rotation range
     let rotation_range = config.rotation.clone();
 
-    // Build parameters
-    let seg_method = config
-        .seg_method
-        .parse::<SegmentationMethod>()
-        .unwrap_or(SegmentationMethod::Edge);
+    // Build parameters; unknown segmentation methods fail here, not per spot
+    let seg_method = config.seg_method.parse::<SegmentationMethod>()?;
+    SegmenterRegistry::shared().get(seg_method.name())?;
+
+    let grid_model = match config.grid_model.as_deref() {
+        Some(name) => name.parse::<GridModelType>()?,
+        None => GridModelType::Rigid,
+    };
 
-    let params = GridParams {
+    let grid_quality_action = match config.grid_quality_action.as_deref() {
+        Some(name) => name.parse::<GridQualityAction>()?,
+        None => GridQualityAction::Review,
+    };
+
+    let grid_detection_method = match config.grid_detection_method.as_deref() {
+        Some(name) => name.parse::<GridDetectionMethod>()?,
+        None => GridDetectionMethod::Template,
+    };
+
+    let edge_sensitivity = match config.edge_sensitivity[..] {
+        [low, high] => [low, high],
+        _ => {
+            return Err(Error::InvalidParameter(format!(
+                "segEdgeSensitivity needs [low, high], got {} value(s)",
+                config.edge_sensitivity.len()
+            )))
+        }
+    };
+
+    let intensity_normalization = match config.intensity_normalization.as_deref() {
+        Some(name) => name.parse::<IntensityNormalization>()?,
+        None => IntensityNormalization::Max,
+    };
+
+    let defaults = GridParams::default();
+    let mut params = GridParams {
         min_diameter: config.min_diameter,
         max_diameter: config.max_diameter,
+        min_snr: config.min_snr,
+        max_position_offset: config.max_position_offset,
+        max_position_offset_refs: config.max_position_offset_refs,
+        min_grid_quality: config.min_grid_quality,
+        grid_quality_action,
         spot_pitch,
         spot_size: config.spot_size,
         rotation_range,
+        search_diameter: config.search_diameter,
         saturation_limit: config.saturation_limit,
         segmentation_method: seg_method,
-        grid_detection_method: crate::config::GridDetectionMethod::Template,
-        edge_sensitivity: [
-            config.edge_sensitivity[0],
-            config.edge_sensitivity[1],
-        ],
+        grid_detection_method,
+        grid_model,
+        drift_correction: config.drift_correction,
+        drift_max_rotation: config.drift_max_rotation,
+        edge_sensitivity,
+        area_size: config.area_size,
+        min_edge_pixels: config.min_edge_pixels,
+        bg_offset: config.bg_offset,
         array_layout_file: if config.array_layout_file.is_empty() {
             None
         } else {
             Some(config.array_layout_file.clone())
         },
-        // Use MATLAB defaults for new parameters
-        ..Default::default()
+        bit_depth: config.bit_depth.unwrap_or(defaults.bit_depth),
+        intensity_normalization,
+        normalization_percentile: config
+            .normalization_percentile
+            .unwrap_or(defaults.normalization_percentile),
+        empty_threshold: config.empty_threshold.unwrap_or(defaults.empty_threshold),
+        refine_threshold: config.refine_threshold.unwrap_or(defaults.refine_threshold),
+        large_disk: config.large_disk,
+        small_disk: config.small_disk,
     };
 
+    // A missing saturation limit follows the sensor range
+    if params.saturation_limit <= 0.0 {
+        params.saturation_limit = params.sensor_max();
+    }
+
     params.validate()?;
 
-    // Load array layout
-    let layout = if let Some(ref layout_file) = params.array_layout_file {
-        read_layout_file(layout_file)?
+    Ok(params)
+}
+
+/// Quantify the grid image only, or every image of the series
+fn quantify_group(
+    config: &GroupConfig,
+    images: &[ImageData],
+    grid_image: &ImageData,
+    spots: &[Spot],
+    params: &GridParams,
+) -> Result<Vec<SpotResult>> {
+    if config.series_mode != 0 {
+        let (results, shifts) =
+            quantify_series(images, grid_image, spots, &config.group_id, params)?;
+        for shift in &shifts {
+            tracing::info!(
+                "Group {}: image {} shift dx={:.2}, dy={:.2}, rotation={:.2}° (peak {:.3})",
+                config.group_id,
+                shift.image_name,
+                shift.registration.dx,
+                shift.registration.dy,
+                shift.registration.rotation,
+                shift.registration.peak
+            );
+        }
+        Ok(results)
     } else {
-        return Err(Error::InvalidConfiguration(
-            "Array layout file required".to_string(),
-        ));
+        quantify_spots(grid_image, spots, &config.group_id, params)
+    }
+}
+
+/// Fit the configured grid model to segmented spots, regenerate positions and re-segment
+///
+/// If too few spots segmented correctly the rigid grid is kept.
+fn refine_with_grid_model(
+    image: &ImageData,
+    spots: &mut [Spot],
+    params: &GridParams,
+    group_id: &str,
+) -> Result<Option<GridModelFit>> {
+    let fit = match fit_grid_model(spots, params.grid_model) {
+        Ok(fit) => fit,
+        Err(e) => {
+            tracing::warn!("Group {}: keeping rigid grid: {}", group_id, e);
+            return Ok(None);
+        }
     };
 
-    // Preprocess images
-    let grid_image = preprocess_images(&images, None, &config.use_image)?;
+    tracing::info!(
+        "Group {}: {:?} grid model pitch=({:.3}, {:.3}) rotation={:.3}° shear={:.3}°, \
+         residuals rms={:.3} median={:.3} max={:.3} px ({} spots, {} inliers)",
+        group_id,
+        params.grid_model,
+        fit.model.pitch_x(),
+        fit.model.pitch_y(),
+        fit.model.rotation(),
+        fit.model.shear(),
+        fit.residuals.rms,
+        fit.residuals.median,
+        fit.residuals.max,
+        fit.residuals.count,
+        fit.inliers
+    );
 
-    // Process gridding
-    let mut spots = process_gridding(&[grid_image.clone()], &layout, &params)?;
+    apply_grid_model(&fit.model, spots);
+    segment_spots(image, spots, params)?;
 
-    // Segment spots (re-enabled for Phase 2)
-    segment_spots(&grid_image, &mut spots, &params)?;
+    Ok(Some(fit))
+}
+
+/// Output order configured for a batch (`Input` when unset)
+pub fn output_order(config: &BatchConfig) -> Result<OutputOrder> {
+    match config.output_order.as_deref() {
+        Some(name) => name.parse::<OutputOrder>(),
+        None => Ok(OutputOrder::Input),
+    }
+}
+
+/// Process all groups, handing each group's outcome to `on_group` as it becomes writable
+///
+/// Groups are run by `num_workers` worker threads, so at most `num_workers` groups
+/// (and their images) are loaded at once; per-spot work within a group uses a shared
+/// thread pool of the same size. `on_group` runs on the calling thread with the group
+/// index, in configuration order for `OutputOrder::Input` or completion order for
+/// `OutputOrder::Completion`. With `OutputOrder::Input` a group is only started
+/// within `2 * num_workers` of the next one to deliver, which bounds the results
+/// held back behind a slow group. If `on_group` returns an error no further groups
+/// are started and that error is returned once running groups have finished.
+pub fn process_batch_streaming<F>(config: &BatchConfig, order: OutputOrder, on_group: F) -> Result<()>
+where
+    F: FnMut(usize, &GroupConfig, GroupOutcome) -> Result<()>,
+{
+    process_batch_observed(config, order, &NoProgress, on_group)
+}
 
-    // Quantify spots
-    let results = quantify_spots(&grid_image, &spots, &config.group_id, &params)?;
+/// [`process_batch_streaming`], reporting progress events to `observer`
+///
+/// `GroupStarted` and `StageFinished` are sent from the worker threads; group
+/// completion events (with the ETA) are sent from the calling thread as groups
+/// finish, before the group is handed to `on_group`.
+pub fn process_batch_observed<F>(
+    config: &BatchConfig,
+    order: OutputOrder,
+    observer: &dyn ProgressObserver,
+    mut on_group: F,
+) -> Result<()>
+where
+    F: FnMut(usize, &GroupConfig, GroupOutcome) -> Result<()>,
+{
+    let total_groups = config.image_groups.len();
+    let num_workers = config.num_workers.max(1);
 
     tracing::info!(
-        "Group {} completed: {} spots processed",
-        config.group_id,
-        results.len()
+        "Starting batch processing: {} groups with {} workers",
+        total_groups,
+        num_workers
     );
 
-    Ok(results)
+    // Initialize progress
+    let started = Instant::now();
+    write_progress(&config.progress_file, 0, total_groups, "Initializing")?;
+    observer.on_event(&ProgressEvent::BatchStarted {
+        total_groups,
+        workers: num_workers,
+    });
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_workers)
+        .build()
+        .map_err(|e| Error::ProcessingError(format!("Failed to build thread pool: {}", e)))?;
+
+    let next_group = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    // Groups delivered in input order so far; a group further than
+    // `reorder_window` ahead is not started, so at most that many finished
+    // groups wait behind a slow earlier one
+    let delivered = Mutex::new(0usize);
+    let delivered_changed = Condvar::new();
+    let reorder_window = match order {
+        OutputOrder::Input => 2 * num_workers,
+        OutputOrder::Completion => usize::MAX,
+    };
+    let (sender, receiver) = mpsc::sync_channel::<(usize, GroupOutcome, Duration)>(num_workers);
+
+    let outcome = std::thread::scope(|scope| {
+        for _ in 0..num_workers.min(total_groups) {
+            let sender = sender.clone();
+            let (pool, next_group, stop) = (&pool, &next_group, &stop);
+            let (delivered, delivered_changed) = (&delivered, &delivered_changed);
+            scope.spawn(move || loop {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let index = next_group.fetch_add(1, Ordering::SeqCst);
+                if index >= total_groups {
+                    break;
+                }
+                let mut count = delivered.lock().unwrap();
+                while index >= count.saturating_add(reorder_window) && !stop.load(Ordering::SeqCst) {
+                    count = delivered_changed.wait(count).unwrap();
+                }
+                drop(count);
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let group = &config.image_groups[index];
+                observer.on_event(&ProgressEvent::GroupStarted {
+                    group_id: group.group_id.clone(),
+                    index,
+                });
+                let group_started = Instant::now();
+                let result = pool.install(|| process_group_observed(group, observer));
+                if sender.send((index, result, group_started.elapsed())).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut completed = 0;
+        let mut failed = 0;
+        let mut pending = BTreeMap::new();
+        let mut next_to_write = 0;
+        let mut outcome = Ok(());
+
+        // Keep draining after an error so running workers can finish
+        for (index, result, elapsed) in receiver {
+            completed += 1;
+            let group_id = &config.image_groups[index].group_id;
+            let eta_seconds = estimate_remaining(started, completed, total_groups);
+            let message = match &result {
+                Ok(results) => {
+                    let rows = &results.spots;
+                    let spots: HashSet<&str> = rows.iter().map(|row| row.spot_id.as_str()).collect();
+                    observer.on_event(&ProgressEvent::GroupFinished {
+                        group_id: group_id.clone(),
+                        index,
+                        spots: spots.len(),
+                        rows: rows.len(),
+                        elapsed_ms: millis(elapsed),
+                        completed,
+                        total: total_groups,
+                        eta_seconds,
+                    });
+                    format!("Completed group {}", group_id)
+                }
+                Err(failure) => {
+                    failed += 1;
+                    let record = failure.record();
+                    observer.on_event(&ProgressEvent::GroupFailed {
+                        group_id: group_id.clone(),
+                        index,
+                        stage: record.stage,
+                        error: record.error,
+                        message: record.message,
+                        completed,
+                        total: total_groups,
+                        eta_seconds,
+                    });
+                    format!("Failed group {}", group_id)
+                }
+            };
+            let _ = write_progress(&config.progress_file, completed, total_groups, &message);
+
+            if outcome.is_err() {
+                continue;
+            }
+
+            outcome = match order {
+                OutputOrder::Completion => on_group(index, &config.image_groups[index], result),
+                OutputOrder::Input => {
+                    pending.insert(index, result);
+                    let mut written = Ok(());
+                    while let Some(result) = pending.remove(&next_to_write) {
+                        written = on_group(next_to_write, &config.image_groups[next_to_write], result);
+                        next_to_write += 1;
+                        if written.is_err() {
+                            break;
+                        }
+                    }
+                    *delivered.lock().unwrap() = next_to_write;
+                    delivered_changed.notify_all();
+                    written
+                }
+            };
+
+            if outcome.is_err() {
+                // Under the lock, so a worker cannot miss the wakeup
+                let _count = delivered.lock().unwrap();
+                stop.store(true, Ordering::SeqCst);
+                delivered_changed.notify_all();
+            }
+        }
+
+        observer.on_event(&ProgressEvent::BatchFinished {
+            succeeded: completed - failed,
+            failed,
+            elapsed_ms: millis(started.elapsed()),
+        });
+
+        outcome
+    });
+
+    outcome?;
+
+    write_progress(&config.progress_file, total_groups, total_groups, "Completed")?;
+
+    Ok(())
 }
 
 /// Process batch configuration with parallel execution
 pub fn process_batch(config: BatchConfig) -> Result<Vec<SpotResult>> {
+    let mut all_results = Vec::new();
+
+    process_batch_streaming(&config, OutputOrder::Input, |_, _, result| {
+        all_results.extend(result.map_err(|failure| failure.error)?.spots);
+        Ok(())
+    })?;
+
+    tracing::info!("Batch processing completed: {} total results", all_results.len());
+
+    Ok(all_results)
+}
+
+/// Outcome of a batch run written to file
+#[derive(Debug, Clone, Default)]
+pub struct BatchSummary {
+    /// Result rows written to the output file
+    pub rows: usize,
+    /// Groups processed successfully
+    pub succeeded: usize,
+    /// Groups skipped because a previous run already completed them (`--resume`)
+    pub skipped: usize,
+    /// Failed groups, in the order they were reported
+    pub failures: Vec<GroupErrorRecord>,
+    /// Error report file, written when at least one group failed and removed
+    /// otherwise
+    pub error_report: Option<String>,
+    /// Grid models fitted to the groups of the output file, in the order they were written
+    pub grid_models: Vec<GridModelRecord>,
+    /// Grid model report file, written when at least one group fitted a grid
+    /// model and removed otherwise
+    pub grid_model_report: Option<String>,
+}
+
+impl BatchSummary {
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Failure policy configured for a batch (`FailFast` when unset)
+pub fn failure_policy(config: &BatchConfig) -> Result<FailurePolicy> {
+    match config.on_group_error.as_deref() {
+        Some(name) => name.parse::<FailurePolicy>(),
+        None => Ok(FailurePolicy::FailFast),
+    }
+}
+
+/// Error report path: `errorReportFile`, or `<outputFile>.errors.csv`
+pub fn error_report_path(config: &BatchConfig) -> String {
+    config
+        .error_report_file
+        .clone()
+        .unwrap_or_else(|| format!("{}.errors.csv", config.output_file))
+}
+
+/// Write the failed-group report as CSV
+pub fn write_error_report<P: AsRef<Path>>(failures: &[GroupErrorRecord], path: P) -> Result<()> {
+    write_report(failures, path)
+}
+
+/// Grid model report path: `<outputFile>.gridmodel.csv`
+pub fn grid_model_report_path(config: &BatchConfig) -> String {
+    format!("{}.gridmodel.csv", config.output_file)
+}
+
+/// Write the grid model report as CSV
+pub fn write_grid_model_report<P: AsRef<Path>>(models: &[GridModelRecord], path: P) -> Result<()> {
+    write_report(models, path)
+}
+
+/// Grid model report rows of groups in `groups`; none if there is no report
+fn read_grid_model_report(config: &BatchConfig, groups: &HashSet<String>) -> Result<Vec<GridModelRecord>> {
+    let path = grid_model_report_path(config);
+    if !Path::new(&path).exists() {
+        return Ok(Vec::new());
+    }
+    let mut records = Vec::new();
+    for record in csv::Reader::from_path(path)?.deserialize::<GridModelRecord>() {
+        let record = record?;
+        if groups.contains(&record.group_id) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+fn write_report<R: Serialize, P: AsRef<Path>>(records: &[R], path: P) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write `records` to the report at `path`, or remove a report left by an
+/// earlier run when there is nothing to report
+fn update_report<R: Serialize>(records: &[R], path: String) -> Result<Option<String>> {
+    if records.is_empty() {
+        match std::fs::remove_file(&path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(None),
+        }
+    } else {
+        write_report(records, &path)?;
+        Ok(Some(path))
+    }
+}
+
+/// Output file format: `outputFormat`, else from the `outputFile` extension, else CSV
+pub fn output_format(config: &BatchConfig) -> Result<OutputFormat> {
+    match config.output_format.as_deref() {
+        Some(name) => name.parse::<OutputFormat>(),
+        None => Ok(OutputFormat::from_path(&config.output_file).unwrap_or_default()),
+    }
+}
+
+/// Process a batch, streaming results to `config.output_file` as groups complete
+///
+/// The output is flushed after every group, so for CSV and TSV results of
+/// finished groups survive a failure later in the run; Parquet and Arrow files
+/// are completed (with the groups written so far) before a failure is
+/// returned. Failed groups are written to the error report; with
+/// `FailurePolicy::FailFast` the first failure is also returned as error, with
+/// `FailurePolicy::Continue` the remaining groups are still processed.
+///
+/// Grid models fitted with `grdModel` are written to a separate report (see
+/// [`grid_model_report_path`]), one row per group.
+///
+/// Every completed group is recorded in a journal next to the output file
+/// (see [`journal_path`]), which [`resume_batch_to_file`] uses to continue an
+/// interrupted run.
+pub fn process_batch_to_file(config: &BatchConfig) -> Result<BatchSummary> {
+    process_batch_to_file_observed(config, &NoProgress)
+}
+
+/// [`process_batch_to_file`], reporting progress events to `observer`
+pub fn process_batch_to_file_observed(
+    config: &BatchConfig,
+    observer: &dyn ProgressObserver,
+) -> Result<BatchSummary> {
+    let writer = create_table_writer(&config.output_file, output_format(config)?)?;
+    let checkpoint = Checkpoint::create(journal_path(&config.output_file))?;
+    run_batch_to_file(config, observer, checkpoint, writer, BatchSummary::default())
+}
+
+/// Continue an interrupted [`process_batch_to_file`] run
+///
+/// Groups recorded in the journal whose configuration and input files (layout,
+/// manual spots, grid edit and images) still hash to the journaled value are skipped and their rows are kept in
+/// the existing output file, which is cut after them (their grid model report
+/// rows are kept as well); all other groups are processed and appended. Without a journal this behaves like a fresh run.
+/// Only CSV and TSV output can be resumed.
+pub fn resume_batch_to_file(config: &BatchConfig) -> Result<BatchSummary> {
+    resume_batch_to_file_observed(config, &NoProgress)
+}
+
+/// [`resume_batch_to_file`], reporting progress events to `observer`
+pub fn resume_batch_to_file_observed(
+    config: &BatchConfig,
+    observer: &dyn ProgressObserver,
+) -> Result<BatchSummary> {
+    let format = output_format(config)?;
+    if !format.is_delimited() {
+        return Err(Error::InvalidConfiguration(format!(
+            "{:?} output cannot be resumed, use CSV or TSV",
+            format
+        )));
+    }
+    let checkpoint = Checkpoint::open(journal_path(&config.output_file))?;
+    let (done, kept_rows, kept_len) = completed_groups(config, &checkpoint, format)?;
+    let writer = append_table_writer(&config.output_file, format, kept_len)?;
+    let kept = BatchSummary {
+        rows: kept_rows,
+        skipped: done.len(),
+        grid_models: read_grid_model_report(config, &done)?,
+        ..BatchSummary::default()
+    };
+
     tracing::info!(
-        "Starting batch processing: {} groups with {} workers",
-        config.image_groups.len(),
-        config.num_workers
+        "Resuming batch: {} of {} groups already completed",
+        done.len(),
+        config.image_groups.len()
     );
 
-    // Initialize progress
-    write_progress(&config.progress_file, 0, config.image_groups.len(), "Initializing")?;
+    let pending = BatchConfig {
+        image_groups: config
+            .image_groups
+            .iter()
+            .filter(|group| !done.contains(&group.group_id))
+            .cloned()
+            .collect(),
+        ..config.clone()
+    };
 
-    // Track progress
-    let progress_counter = Arc::new(Mutex::new(0usize));
-    let total_groups = config.image_groups.len();
+    run_batch_to_file(&pending, observer, checkpoint, writer, kept)
+}
 
-    // Process groups in parallel
-    let results: Result<Vec<Vec<SpotResult>>> = rayon::ThreadPoolBuilder::new()
-        .num_threads(config.num_workers)
-        .build()
-        .map_err(|e| Error::ProcessingError(format!("Failed to build thread pool: {}", e)))?
-        .install(|| {
-            config
-                .image_groups
-                .par_iter()
-                .map(|group_config| {
-                    let result = process_single_group(group_config);
-
-                    // Update progress
-                    let mut counter = progress_counter.lock().unwrap();
-                    *counter += 1;
-                    let current = *counter;
-                    drop(counter);
-
-                    let _ = write_progress(
-                        &config.progress_file,
-                        current,
-                        total_groups,
-                        &format!("Processing group {}", group_config.group_id),
-                    );
-
-                    result
-                })
-                .collect()
-        });
+/// Journaled groups whose inputs are unchanged and whose rows are all present
+/// in the output file, with their row count and the length of the output file
+/// that holds them
+///
+/// Each group is written as one block of rows, so completed groups are the
+/// leading blocks of the file. Everything after them (a changed group, one
+/// that lost rows or was not journaled yet, a torn last row) is processed again.
+fn completed_groups(
+    config: &BatchConfig,
+    checkpoint: &Checkpoint,
+    format: OutputFormat,
+) -> Result<(HashSet<String>, usize, u64)> {
+    let mut expected = HashMap::new();
+    if !checkpoint.is_empty() {
+        for group in &config.image_groups {
+            // Unreadable inputs just mean the group is processed (and fails) again
+            let Ok(hash) = group_input_hash(group) else {
+                continue;
+            };
+            if let Some(entry) = checkpoint.completed(&group.group_id, &hash) {
+                expected.insert(group.group_id.clone(), entry.rows);
+            }
+        }
+    }
 
-    let results = results?;
+    let mut done = HashSet::new();
+    let mut rows = 0;
+    let mut len = 0;
+    if expected.is_empty() || !Path::new(&config.output_file).exists() {
+        return Ok((done, rows, len));
+    }
 
-    // Flatten results
-    let all_results: Vec<SpotResult> = results.into_iter().flatten().collect();
+    let mut reader = delimited_reader(&config.output_file, format)?;
+    let headers = reader.headers()?.clone();
+    if !headers.iter().eq(SpotResult::columns().into_iter().map(|(name, _)| name)) {
+        // Written by a version with other columns, start over
+        return Ok((done, rows, len));
+    }
 
-    tracing::info!("Batch processing completed: {} total results", all_results.len());
+    let mut record = csv::StringRecord::new();
+    // Group id, row count and end offset of the block being read
+    let mut block: Option<(String, usize, u64)> = None;
+    loop {
+        // An unreadable row ends the usable part of the file
+        let row = match reader.read_record(&mut record) {
+            Ok(true) => record.deserialize::<SpotResult>(Some(&headers)).ok(),
+            _ => None,
+        };
+        let end = reader.position().byte();
+        if let (Some(row), Some((id, count, block_end))) = (&row, &mut block) {
+            if row.group_id == *id {
+                *count += 1;
+                *block_end = end;
+                continue;
+            }
+        }
+        if let Some((id, count, block_end)) = block.take() {
+            if expected.get(&id) != Some(&count) || !done.insert(id) {
+                break;
+            }
+            rows += count;
+            len = block_end;
+        }
+        match row {
+            Some(row) => block = Some((row.group_id, 1, end)),
+            None => break,
+        }
+    }
+    Ok((done, rows, len))
+}
 
-    write_progress(
-        &config.progress_file,
-        config.image_groups.len(),
-        config.image_groups.len(),
-        "Completed",
-    )?;
+/// Run `config`, continuing `summary` (rows, skipped groups and grid models
+/// kept from a previous run)
+fn run_batch_to_file(
+    config: &BatchConfig,
+    observer: &dyn ProgressObserver,
+    mut checkpoint: Checkpoint,
+    mut writer: Box<dyn TableWriter<SpotResult>>,
+    mut summary: BatchSummary,
+) -> Result<BatchSummary> {
+    let order = output_order(config)?;
+    let policy = failure_policy(config)?;
+    let mut first_error = None;
 
-    Ok(all_results)
+    // Events go to the caller's observer and the configured JSON-lines file
+    let events_file = match config.progress_events_file.as_deref() {
+        Some(path) => Some(JsonLinesProgress::create(path)?),
+        None => None,
+    };
+    let observer = |event: &ProgressEvent| {
+        observer.on_event(event);
+        if let Some(events_file) = &events_file {
+            events_file.on_event(event);
+        }
+    };
+
+    let streamed = process_batch_observed(config, order, &observer, |_, group, result| {
+        match result {
+            Ok(results) => {
+                let rows = &results.spots;
+                writer.write(rows)?;
+                // Journal only after the rows are on disk
+                checkpoint.record(JournalEntry {
+                    group_id: group.group_id.clone(),
+                    input_hash: group_input_hash(group)?,
+                    rows: rows.len(),
+                })?;
+                summary.rows += rows.len();
+                summary.succeeded += 1;
+                summary.grid_models.extend(results.grid_model);
+                tracing::info!("Group {}: wrote {} results", group.group_id, rows.len());
+                Ok(())
+            }
+            Err(failure) => {
+                tracing::error!(
+                    "Group {} failed during {}: {}",
+                    failure.group_id,
+                    failure.stage.as_str(),
+                    failure.error
+                );
+                summary.failures.push(failure.record());
+                match policy {
+                    FailurePolicy::Continue => Ok(()),
+                    FailurePolicy::FailFast => {
+                        first_error = Some(failure.error);
+                        Err(Error::ProcessingError("Batch stopped after group failure".to_string()))
+                    }
+                }
+            }
+        }
+    });
+
+    writer.finish()?;
+
+    // Reports left by an earlier run would describe groups that changed since
+    summary.error_report = update_report(&summary.failures, error_report_path(config))?;
+    if let Some(path) = &summary.error_report {
+        tracing::warn!("{} group(s) failed, see {}", summary.failures.len(), path);
+    }
+    summary.grid_model_report = update_report(&summary.grid_models, grid_model_report_path(config))?;
+
+    if let Some(error) = first_error {
+        return Err(error);
+    }
+    streamed?;
+
+    tracing::info!(
+        "Batch processing completed: {} total results, {} groups failed",
+        summary.rows,
+        summary.failures.len()
+    );
+
+    Ok(summary)
 }
 
 /// Write results to CSV file
@@ -161,14 +1068,7 @@ pub fn write_results_csv<P: AsRef<Path>>(
     results: &[SpotResult],
     output_path: P,
 ) -> Result<()> {
-    let mut writer = csv::Writer::from_path(output_path)?;
-
-    for result in results {
-        writer.serialize(result)?;
-    }
-
-    writer.flush()?;
-    Ok(())
+    write_table(results, output_path, OutputFormat::Csv)
 }
 
 #[cfg(test)]
@@ -178,11 +1078,10 @@ mod tests {
     #[test]
     fn test_process_batch_empty() {
         let config = BatchConfig {
-            mode: "batch".to_string(),
             num_workers: 2,
             progress_file: "/tmp/progress_test.txt".to_string(),
             output_file: "/tmp/output_test.csv".to_string(),
-            image_groups: vec![],
+            ..BatchConfig::default()
         };
 
         let result = process_batch(config);
@@ -190,4 +1089,400 @@ mod tests {
         let results = result.unwrap();
         assert_eq!(results.len(), 0);
     }
+
+    fn missing_image_group(group_id: &str) -> GroupConfig {
+        let json = format!(
+            r#"{{"groupId": "{}", "sqcMinDiameter": 0.45, "sqcMaxDiameter": 0.85,
+                "segEdgeSensitivity": [0, 0.01], "qntSeriesMode": 0, "qntShowPamGridViewer": 0,
+                "grdSpotPitch": 21.5, "grdSpotSize": 0.66, "grdRotation": [0],
+                "qntSaturationLimit": 4095, "segMethod": "Edge", "grdUseImage": "First",
+                "pgMode": "grid", "dbgShowPresenter": 0, "arraylayoutfile": "",
+                "imageslist": ["/nonexistent/{}.tif"]}}"#,
+            group_id, group_id
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    /// Group on a 5x5 grid with five reference spots, laid out in `dir`
+    fn synthetic_group(dir: &Path, group_id: &str) -> GroupConfig {
+        let mut layout = String::from("Row\tCol\tID\n");
+        for row in 1..=5 {
+            for col in 1..=5 {
+                layout.push_str(&format!("{}\t{}\tS{}{}\n", row, col, row, col));
+            }
+        }
+        for (row, col) in [(-1, -1), (-1, -5), (-5, -1), (-5, -5), (-3, -3)] {
+            layout.push_str(&format!("{}\t{}\t#REF\n", row, col));
+        }
+        let layout_file = dir.join("layout.txt");
+        std::fs::write(&layout_file, layout).unwrap();
+
+        let mut config = missing_image_group(group_id);
+        config.spot_pitch = 20.0;
+        config.array_layout_file = layout_file.to_string_lossy().into_owned();
+        config
+    }
+
+    /// 200x200 image with bright spots of a grid centered at `center`
+    fn synthetic_image(layout: &[(String, bool, i32, i32)], center: (f64, f64)) -> ImageData {
+        let spots = crate::grid::generate_grid_coordinates(center, 0.0, 20.0, layout);
+        let data = ndarray::Array2::from_shape_fn((200, 200), |(y, x)| {
+            let peak = spots
+                .iter()
+                .map(|s| (-((x as f64 - s.grid_x).powi(2) + (y as f64 - s.grid_y).powi(2)) / 18.0).exp())
+                .fold(0.0, f64::max);
+            (50.0 + 950.0 * peak) as u16
+        });
+        ImageData::new(data, format!("grid_{}_{}", center.0, center.1))
+    }
+
+    #[test]
+    fn test_grid_edit_moves_the_detected_grid() {
+        let dir = std::env::temp_dir().join("pamsoft_grid_edit_detected");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = synthetic_group(&dir, "edit");
+        let layout = read_layout_file(&config.array_layout_file).unwrap();
+
+        // Grid well away from the image center
+        let center = (113.0, 88.0);
+        let images = [synthetic_image(&layout, center)];
+        let truth = crate::grid::generate_grid_coordinates(center, 0.0, 20.0, &layout);
+        let mut timer = StageTimer {
+            group_id: &config.group_id,
+            observer: &NoProgress,
+            last: Instant::now(),
+        };
+
+        // The detected grid follows the image, within MATLAB's pixel conventions
+        let detected = locate_spots(&config, &images, &mut timer).unwrap();
+        assert_eq!(detected.grid_positions.len(), truth.len());
+        for (&(x, y), spot) in detected.grid_positions.iter().zip(&truth) {
+            assert!((x - spot.grid_x).abs() <= 4.0 && (y - spot.grid_y).abs() <= 4.0);
+        }
+
+        let edit_file = dir.join("edit.json");
+        std::fs::write(&edit_file, r#"{"translate": [3, -2]}"#).unwrap();
+        config.grid_edit_file = Some(edit_file.to_string_lossy().into_owned());
+        let edited = locate_spots(&config, &images, &mut timer).unwrap();
+        for (&(x, y), &(dx, dy)) in edited.grid_positions.iter().zip(&detected.grid_positions) {
+            assert!((x - dx - 3.0).abs() < 1e-9 && (y - dy + 2.0).abs() < 1e-9);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_grid_models_go_to_their_own_report() {
+        let dir = std::env::temp_dir().join("pamsoft_grid_model_report");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        let mut groups = Vec::new();
+        for id in ["rigid", "affine"] {
+            let mut group = synthetic_group(&dir, id);
+            let layout = read_layout_file(&group.array_layout_file).unwrap();
+            let (pixels, _) = synthetic_image(&layout, (98.0, 104.0)).data.into_raw_vec_and_offset();
+            image::ImageBuffer::<image::Luma<u16>, _>::from_raw(200, 200, pixels)
+                .unwrap()
+                .save(path(&format!("{}.tif", id)))
+                .unwrap();
+            group.images_list = vec![path(&format!("{}.tif", id))];
+            groups.push(group);
+        }
+        groups[1].grid_model = Some("Affine".to_string());
+        let config = BatchConfig {
+            num_workers: 1,
+            progress_file: path("progress.txt"),
+            output_file: path("results.csv"),
+            image_groups: groups,
+            ..BatchConfig::default()
+        };
+
+        let summary = process_batch_to_file(&config).unwrap();
+        assert_eq!(summary.grid_model_report, Some(path("results.csv.gridmodel.csv")));
+        let mut reader = csv::Reader::from_path(path("results.csv.gridmodel.csv")).unwrap();
+        let models: Vec<GridModelRecord> =
+            reader.deserialize().collect::<std::result::Result<_, _>>().unwrap();
+        assert_eq!(models, summary.grid_models);
+        assert_eq!(models.len(), 1);
+        assert_eq!((models[0].group_id.as_str(), models[0].model.as_str()), ("affine", "Affine"));
+        assert!((models[0].pitch_x - 20.0).abs() < 0.5 && (models[0].pitch_y - 20.0).abs() < 0.5);
+        assert!(!std::fs::read_to_string(path("results.csv")).unwrap().contains("grdModel"));
+
+        // Without a fitted model there is no report
+        let config = BatchConfig {
+            image_groups: config.image_groups[..1].to_vec(),
+            ..config
+        };
+        assert!(process_batch_to_file(&config).unwrap().grid_model_report.is_none());
+        assert!(!Path::new(&path("results.csv.gridmodel.csv")).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_blank_image_fails_the_grid_quality_check() {
+        let dir = std::env::temp_dir().join("pamsoft_grid_blank_image");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = synthetic_group(&dir, "blank");
+        config.grid_quality_action = Some("Fail".to_string());
+        let layout = read_layout_file(&config.array_layout_file).unwrap();
+        let mut timer = StageTimer {
+            group_id: &config.group_id,
+            observer: &NoProgress,
+            last: Instant::now(),
+        };
+
+        let grid = [synthetic_image(&layout, (113.0, 88.0))];
+        assert!(locate_spots(&config, &grid, &mut timer).is_ok());
+
+        let blank = [ImageData::new(ndarray::Array2::from_elem((200, 200), 50), "blank".to_string())];
+        let failure = locate_spots(&config, &blank, &mut timer).err().unwrap();
+        assert_eq!(failure.stage, GroupStage::Gridding);
+        assert!(matches!(failure.error, Error::GridDetectionFailed(_)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_streaming_delivers_groups_in_input_order() {
+        let config = BatchConfig {
+            num_workers: 3,
+            progress_file: std::env::temp_dir()
+                .join("pamsoft_streaming_progress.txt")
+                .to_string_lossy()
+                .into_owned(),
+            image_groups: (0..5).map(|i| missing_image_group(&i.to_string())).collect(),
+            ..BatchConfig::default()
+        };
+
+        let mut seen = Vec::new();
+        process_batch_streaming(&config, OutputOrder::Input, |index, group, result| {
+            assert!(result.is_err());
+            seen.push((index, group.group_id.clone()));
+            Ok(())
+        })
+        .unwrap();
+
+        let expected: Vec<(usize, String)> = (0..5).map(|i| (i, i.to_string())).collect();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn test_observer_receives_group_events() {
+        let config = BatchConfig {
+            num_workers: 2,
+            progress_file: std::env::temp_dir()
+                .join("pamsoft_events_progress.txt")
+                .to_string_lossy()
+                .into_owned(),
+            image_groups: vec![missing_image_group("a"), missing_image_group("b")],
+            ..BatchConfig::default()
+        };
+
+        let events = std::sync::Mutex::new(Vec::new());
+        let observer = |event: &ProgressEvent| events.lock().unwrap().push(event.clone());
+        process_batch_observed(&config, OutputOrder::Input, &observer, |_, _, _| Ok(())).unwrap();
+
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.len(), 6);
+        assert!(matches!(events[0], ProgressEvent::BatchStarted { total_groups: 2, .. }));
+        let started = events
+            .iter()
+            .filter(|e| matches!(e, ProgressEvent::GroupStarted { .. }))
+            .count();
+        assert_eq!(started, 2);
+        let failed: Vec<&str> = events
+            .iter()
+            .filter_map(|e| match e {
+                ProgressEvent::GroupFailed { stage, .. } => Some(stage.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(failed, ["load_images", "load_images"]);
+        assert!(matches!(
+            events[5],
+            ProgressEvent::BatchFinished { succeeded: 0, failed: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn test_unknown_segmentation_method_is_rejected() {
+        let images = [ImageData::new(ndarray::Array2::zeros((4, 4)), "img".to_string())];
+        let config = GroupConfig {
+            spot_pitch: 21.5,
+            seg_method: "Threshold".to_string(),
+            ..GroupConfig::default()
+        };
+        let err = group_params(&config, &images).unwrap_err();
+        assert!(err.to_string().contains("Threshold"), "{}", err);
+
+        let config = GroupConfig {
+            seg_method: "hough".to_string(),
+            ..config
+        };
+        let params = group_params(&config, &images).unwrap();
+        assert_eq!(params.segmentation_method, SegmentationMethod::Hough);
+    }
+
+    #[test]
+    fn test_continue_policy_writes_error_report() {
+        let dir = std::env::temp_dir().join("pamsoft_continue_policy");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        let config = BatchConfig {
+            num_workers: 2,
+            progress_file: path("progress.txt"),
+            output_file: path("results.csv"),
+            output_order: Some("Completion".to_string()),
+            on_group_error: Some("Continue".to_string()),
+            image_groups: vec![missing_image_group("a"), missing_image_group("b")],
+            ..BatchConfig::default()
+        };
+
+        let summary = process_batch_to_file(&config).unwrap();
+        assert!(!summary.is_complete());
+        assert_eq!(summary.succeeded, 0);
+        assert_eq!(summary.error_report.as_deref(), Some(path("results.csv.errors.csv").as_str()));
+
+        let mut reader = csv::Reader::from_path(path("results.csv.errors.csv")).unwrap();
+        let mut records: Vec<GroupErrorRecord> =
+            reader.deserialize().collect::<std::result::Result<_, _>>().unwrap();
+        records.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].group_id, "a");
+        assert_eq!(records[0].stage, "load_images");
+        assert_eq!(records[0].error, "Image");
+
+        // Fail-fast reports the failure and returns the group's error
+        let config = BatchConfig {
+            on_group_error: None,
+            ..config
+        };
+        assert!(process_batch_to_file(&config).is_err());
+
+        // A clean rerun removes the stale report
+        let config = BatchConfig {
+            image_groups: Vec::new(),
+            ..config
+        };
+        let summary = process_batch_to_file(&config).unwrap();
+        assert!(summary.error_report.is_none());
+        assert!(!Path::new(&path("results.csv.errors.csv")).exists());
+    }
+
+    #[test]
+    fn test_resume_skips_only_fully_written_groups() {
+        let dir = std::env::temp_dir().join("pamsoft_resume");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        let mut groups = Vec::new();
+        for id in ["a", "b", "c"] {
+            std::fs::write(path(&format!("{}.tif", id)), id.repeat(16)).unwrap();
+            let mut group = missing_image_group(id);
+            group.images_list = vec![path(&format!("{}.tif", id))];
+            groups.push(group);
+        }
+        let config = BatchConfig {
+            num_workers: 1,
+            progress_file: path("progress.txt"),
+            output_file: path("results.csv"),
+            image_groups: groups,
+            ..BatchConfig::default()
+        };
+
+        let row = |group_id: &str| SpotResult::example(group_id, "#1");
+        // "b" lost a row, "c" changed after it was journaled
+        write_results_csv(&[row("a"), row("a"), row("b")], path("results.csv")).unwrap();
+
+        let mut checkpoint = Checkpoint::create(journal_path(&config.output_file)).unwrap();
+        for group in &config.image_groups {
+            checkpoint
+                .record(JournalEntry {
+                    group_id: group.group_id.clone(),
+                    input_hash: group_input_hash(group).unwrap(),
+                    rows: 2,
+                })
+                .unwrap();
+        }
+        std::fs::write(path("c.tif"), "changed").unwrap();
+        drop(checkpoint);
+
+        let written = std::fs::read_to_string(path("results.csv")).unwrap();
+        let a_end = written.match_indices('\n').nth(2).unwrap().0 + 1;
+        let journal = Checkpoint::open(journal_path(&config.output_file)).unwrap();
+        let (done, rows, len) = completed_groups(&config, &journal, OutputFormat::Csv).unwrap();
+        assert_eq!(done, HashSet::from(["a".to_string()]));
+        assert_eq!((rows, len), (2, a_end as u64));
+        drop(journal);
+
+        // "b" and "c" fail again; the file is cut after "a" and not rewritten
+        let config = BatchConfig {
+            on_group_error: Some("Continue".to_string()),
+            ..config
+        };
+        let summary = resume_batch_to_file(&config).unwrap();
+        assert_eq!((summary.skipped, summary.rows, summary.failures.len()), (1, 2, 2));
+        assert_eq!(std::fs::read_to_string(path("results.csv")).unwrap(), written[..a_end]);
+    }
+
+    #[test]
+    fn test_resume_reprocesses_group_after_override_edit() {
+        let dir = std::env::temp_dir().join("pamsoft_resume_overrides");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        std::fs::write(path("a.csv"), "qntSpotID,gridX,gridY\ns1,10,12\n").unwrap();
+        std::fs::write(path("b.json"), r#"{"translate": [1, 0]}"#).unwrap();
+        let mut groups = Vec::new();
+        for id in ["a", "b"] {
+            std::fs::write(path(&format!("{}.tif", id)), id.repeat(16)).unwrap();
+            let mut group = missing_image_group(id);
+            group.images_list = vec![path(&format!("{}.tif", id))];
+            groups.push(group);
+        }
+        groups[0].manual_spots_file = Some(path("a.csv"));
+        groups[1].grid_edit_file = Some(path("b.json"));
+        let config = BatchConfig {
+            num_workers: 1,
+            progress_file: path("progress.txt"),
+            output_file: path("results.csv"),
+            on_group_error: Some("Continue".to_string()),
+            image_groups: groups,
+            ..BatchConfig::default()
+        };
+
+        let rows = ["a", "a", "b"].map(|id| SpotResult::example(id, "#1"));
+        write_results_csv(&rows, path("results.csv")).unwrap();
+        let mut checkpoint = Checkpoint::create(journal_path(&config.output_file)).unwrap();
+        for (group, rows) in config.image_groups.iter().zip([2, 1]) {
+            checkpoint
+                .record(JournalEntry {
+                    group_id: group.group_id.clone(),
+                    input_hash: group_input_hash(group).unwrap(),
+                    rows,
+                })
+                .unwrap();
+        }
+        drop(checkpoint);
+
+        let done = |config: &BatchConfig| {
+            let journal = Checkpoint::open(journal_path(&config.output_file)).unwrap();
+            completed_groups(config, &journal, OutputFormat::Csv).unwrap().0
+        };
+        assert_eq!(done(&config), HashSet::from(["a".to_string(), "b".to_string()]));
+
+        // The grid edit changed between runs, so "b" is processed again
+        std::fs::write(path("b.json"), r#"{"translate": [2, 0]}"#).unwrap();
+        assert_eq!(done(&config), HashSet::from(["a".to_string()]));
+        let summary = resume_batch_to_file(&config).unwrap();
+        assert_eq!((summary.skipped, summary.failures.len()), (1, 1));
+        assert_eq!(summary.failures[0].group_id, "b");
+
+        // So is "a" after its manual spots changed
+        std::fs::write(path("a.csv"), "qntSpotID,gridX,gridY\ns1,11,12\n").unwrap();
+        assert!(done(&config).is_empty());
+    }
 }
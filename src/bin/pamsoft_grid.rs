@@ -0,0 +1,90 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use pamsoft_grid::io::load_single_run_config;
+use pamsoft_grid::server::{run_server, ServerConfig};
+use pamsoft_grid::single_run::run_single;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+#[derive(Parser, Debug)]
+#[command(name = "pamsoft_grid")]
+#[command(version = "2.0.0")]
+#[command(about = "PamSoft Grid single-run gridding and quantification", long_about = None)]
+#[command(subcommand_negates_reqs = true)]
+struct Args {
+    /// Path to parameter JSON file (input_params_gridding.json / input_params_quantification.json)
+    #[arg(long = "param-file", required = true)]
+    param_file: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve gridding jobs over HTTP/JSON
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+
+        /// Jobs processed at the same time
+        #[arg(long, default_value_t = 1)]
+        workers: usize,
+
+        /// Directory for uploaded images
+        #[arg(long = "upload-dir", default_value = "pamsoft_grid_uploads")]
+        upload_dir: String,
+    },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    if let Some(Command::Serve {
+        address,
+        workers,
+        upload_dir,
+    }) = args.command
+    {
+        tracing_subscriber::registry()
+            .with(fmt::layer())
+            .with(EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into()))
+            .init();
+        tracing::info!("PamSoft Grid v{} service", pamsoft_grid::VERSION);
+        run_server(ServerConfig {
+            address,
+            workers,
+            upload_dir: upload_dir.into(),
+        })?;
+        return Ok(());
+    }
+
+    // Load configuration first so "verbose" can raise the log level
+    let param_file = args.param_file.expect("required without a subcommand");
+    let config = load_single_run_config(&param_file)?;
+    let level = if config.verbose {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::INFO
+    };
+
+    // Initialize logging
+    tracing_subscriber::registry()
+        .with(fmt::layer())
+        .with(EnvFilter::from_default_env().add_directive(level.into()))
+        .init();
+
+    tracing::info!("PamSoft Grid v{}", pamsoft_grid::VERSION);
+    tracing::info!(
+        "Mode {}: {} images, writing {}",
+        config.pg_mode,
+        config.images_list.len(),
+        config.output_file
+    );
+
+    run_single(&config)?;
+
+    tracing::info!("Processing completed successfully");
+
+    Ok(())
+}
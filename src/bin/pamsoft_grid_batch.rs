@@ -1,7 +1,13 @@
 use anyhow::Result;
 use clap::Parser;
-use pamsoft_grid::batch::{process_batch, write_results_csv};
+use std::process::ExitCode;
+use pamsoft_grid::batch::{process_batch_to_file, resume_batch_to_file};
 use pamsoft_grid::io::load_batch_config;
+use pamsoft_grid::io::tercen::expand_tercen_input;
+use pamsoft_grid::schema::{
+    apply_overrides, effective_config, parse_override, validate_batch_config, Severity,
+};
+use pamsoft_grid::sweep::{run_sweep, write_sweep_summary};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 #[derive(Parser, Debug)]
@@ -12,24 +18,95 @@ struct Args {
     /// Path to batch configuration JSON file
     #[arg(long = "param-file")]
     param_file: String,
+
+    /// Skip groups completed by a previous run of the same batch and append the rest
+    #[arg(long)]
+    resume: bool,
+
+    /// Write JSON-lines progress events to this file (`-` for stdout)
+    #[arg(long = "progress-events")]
+    progress_events: Option<String>,
+
+    /// Result format: csv, tsv, parquet, arrow or tercen (default: from the output file extension).
+    /// Tercen tables are assembled at the end of the run from per-column files next to the output
+    #[arg(long = "output-format")]
+    output_format: Option<String>,
+
+    /// Override a parameter, e.g. `--set segEdgeSensitivity=[0,0.02]` (repeatable);
+    /// batch keys are set on the batch, all others on every image group
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    set: Vec<String>,
+
+    /// Apply a grid edit file to one group, e.g. `--grid-edit W1=w1_edit.json` (repeatable)
+    #[arg(long = "grid-edit", value_name = "GROUP=FILE")]
+    grid_edit: Vec<String>,
+
+    /// Print the configuration with all defaults filled in and exit
+    #[arg(long = "print-config")]
+    print_config: bool,
 }
 
-fn main() -> Result<()> {
-    // Initialize logging
+/// Exit code when some groups failed under the "Continue" policy
+const EXIT_PARTIAL_FAILURE: u8 = 2;
+
+fn main() -> Result<ExitCode> {
+    // Parse command line arguments
+    let args = Args::parse();
+
+    // Load configuration
+    let mut config = load_batch_config(&args.param_file)?;
+    expand_tercen_input(&mut config)?;
+    if args.progress_events.is_some() {
+        config.progress_events_file = args.progress_events;
+    }
+    if args.output_format.is_some() {
+        config.output_format = args.output_format;
+    }
+    let overrides = args
+        .set
+        .iter()
+        .map(|text| parse_override(text))
+        .collect::<pamsoft_grid::Result<Vec<_>>>()?;
+    apply_overrides(&mut config, &overrides)?;
+    for text in &args.grid_edit {
+        let (group_id, file) = text
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected GROUP=FILE for --grid-edit, got {:?}", text))?;
+        let group = config
+            .image_groups
+            .iter_mut()
+            .find(|group| group.group_id == group_id)
+            .ok_or_else(|| anyhow::anyhow!("--grid-edit: no image group {:?}", group_id))?;
+        group.grid_edit_file = Some(file.to_string());
+    }
+
+    // Keep stdout free of log lines when printing the configuration
+    if args.print_config {
+        println!("{}", effective_config(&config)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Initialize logging; stdout may carry progress events (`--progress-events -`)
     tracing_subscriber::registry()
-        .with(fmt::layer())
+        .with(fmt::layer().with_writer(std::io::stderr))
         .with(EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into()))
         .init();
 
     tracing::info!("PamSoft Grid Batch Processor v{}", pamsoft_grid::VERSION);
+    tracing::info!("Loaded batch configuration from: {}", args.param_file);
 
-    // Parse command line arguments
-    let args = Args::parse();
-
-    tracing::info!("Loading batch configuration from: {}", args.param_file);
-
-    // Load configuration
-    let config = load_batch_config(&args.param_file)?;
+    // Report every configuration problem before starting
+    let issues = validate_batch_config(&config);
+    for issue in &issues {
+        match issue.severity {
+            Severity::Error => tracing::error!("{}", issue),
+            Severity::Warning => tracing::warn!("{}", issue),
+        }
+    }
+    let errors = issues.iter().filter(|i| i.severity == Severity::Error).count();
+    if errors > 0 {
+        anyhow::bail!("Invalid batch configuration: {} error(s)", errors);
+    }
 
     tracing::info!(
         "Batch configuration loaded: {} groups, {} workers",
@@ -37,15 +114,45 @@ fn main() -> Result<()> {
         config.num_workers
     );
 
-    // Process batch
-    let results = process_batch(config.clone())?;
+    // Parameter sweep: QC summary per combination instead of spot results
+    if config.mode.eq_ignore_ascii_case("sweep") {
+        let summary_file = config
+            .sweep
+            .as_ref()
+            .and_then(|sweep| sweep.summary_file.clone())
+            .unwrap_or_else(|| config.output_file.clone());
+        let rows = run_sweep(&config)?;
+        write_sweep_summary(&rows, &summary_file)?;
+        tracing::info!("Wrote QC summary of {} combination(s) to: {}", rows.len(), summary_file);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Process batch, streaming results to the output file as groups complete
+    tracing::info!("Writing results to: {}", config.output_file);
+    let summary = if args.resume {
+        resume_batch_to_file(&config)?
+    } else {
+        process_batch_to_file(&config)?
+    };
 
-    tracing::info!("Writing {} results to: {}", results.len(), config.output_file);
+    if summary.skipped > 0 {
+        tracing::info!("Skipped {} group(s) completed by a previous run", summary.skipped);
+    }
+    tracing::info!("Wrote {} results", summary.rows);
+    if let Some(report) = &summary.grid_model_report {
+        tracing::info!("Wrote {} grid model(s) to: {}", summary.grid_models.len(), report);
+    }
 
-    // Write results
-    write_results_csv(&results, &config.output_file)?;
+    if !summary.is_complete() {
+        tracing::warn!(
+            "Batch finished with {} failed group(s); report: {}",
+            summary.failures.len(),
+            summary.error_report.as_deref().unwrap_or("-")
+        );
+        return Ok(ExitCode::from(EXIT_PARTIAL_FAILURE));
+    }
 
     tracing::info!("Batch processing completed successfully");
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
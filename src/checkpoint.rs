@@ -0,0 +1,206 @@
+use crate::error::Result;
+use crate::types::GroupConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// Journal path for a batch output file
+pub fn journal_path(output_file: &str) -> String {
+    format!("{}.journal", output_file)
+}
+
+/// One completed group, appended to the journal after its rows were flushed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    #[serde(rename = "groupId")]
+    pub group_id: String,
+    /// Hash of the group configuration and input files (see [`group_input_hash`])
+    #[serde(rename = "inputHash")]
+    pub input_hash: String,
+    pub rows: usize,
+}
+
+/// 64-bit FNV-1a, stable across platforms and Rust versions
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn update_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let mut file = File::open(path)?;
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.update(&buffer[..n]);
+        }
+    }
+}
+
+/// Hash of everything that determines a group's results
+///
+/// Covers the group configuration, the array layout file, the manual spot and
+/// grid edit files and every image, so results are not reused after any input
+/// changed.
+pub fn group_input_hash(group: &GroupConfig) -> Result<String> {
+    let mut hasher = Fnv1a::new();
+    hasher.update(&serde_json::to_vec(group)?);
+
+    if !group.array_layout_file.is_empty() {
+        hasher.update_file(&group.array_layout_file)?;
+    }
+    for overrides in [&group.manual_spots_file, &group.grid_edit_file].into_iter().flatten() {
+        hasher.update(&[0xff]);
+        hasher.update_file(overrides)?;
+    }
+    for image in &group.images_list {
+        // Separator so moving bytes between files changes the hash
+        hasher.update(&[0xff]);
+        hasher.update_file(image)?;
+    }
+
+    Ok(format!("{:016x}", hasher.0))
+}
+
+/// Append-only record of completed groups
+pub struct Checkpoint {
+    file: File,
+    entries: HashMap<String, JournalEntry>,
+}
+
+impl Checkpoint {
+    /// Start a new, empty journal (truncating any previous one)
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self {
+            file,
+            entries: HashMap::new(),
+        })
+    }
+
+    /// Open an existing journal for resuming, or start a new one if none exists
+    ///
+    /// A torn last line (the run was killed while writing it) is ignored.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut entries = HashMap::new();
+        let mut terminated = true;
+
+        if path.exists() {
+            let reader = BufReader::new(File::open(path)?);
+            for line in reader.split(b'\n') {
+                let line = line?;
+                match serde_json::from_slice::<JournalEntry>(&line) {
+                    Ok(entry) => {
+                        entries.insert(entry.group_id.clone(), entry);
+                        terminated = true;
+                    }
+                    Err(_) if line.is_empty() => {}
+                    Err(e) => {
+                        tracing::warn!("Ignoring journal line {:?}: {}", String::from_utf8_lossy(&line), e);
+                        terminated = false;
+                    }
+                }
+            }
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if !terminated {
+            // Start new entries on a fresh line after a torn one
+            file.write_all(b"\n")?;
+        }
+        Ok(Self { file, entries })
+    }
+
+    /// Completed entry for a group whose inputs still hash to `input_hash`
+    pub fn completed(&self, group_id: &str, input_hash: &str) -> Option<&JournalEntry> {
+        self.entries
+            .get(group_id)
+            .filter(|entry| entry.input_hash == input_hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Durably append a completed group
+    pub fn record(&mut self, entry: JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.entries.insert(entry.group_id.clone(), entry);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_roundtrip_and_torn_line() {
+        let path = std::env::temp_dir().join("pamsoft_checkpoint_test.journal");
+        {
+            let mut checkpoint = Checkpoint::create(&path).unwrap();
+            for (id, hash) in [("1", "aa"), ("2", "bb")] {
+                checkpoint
+                    .record(JournalEntry {
+                        group_id: id.to_string(),
+                        input_hash: hash.to_string(),
+                        rows: 10,
+                    })
+                    .unwrap();
+            }
+        }
+        // Simulate a kill while writing the next entry
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"groupId\":\"3\",\"inp")
+            .unwrap();
+
+        let mut checkpoint = Checkpoint::open(&path).unwrap();
+        assert_eq!(checkpoint.len(), 2);
+        assert!(checkpoint.completed("1", "aa").is_some());
+        assert!(checkpoint.completed("2", "changed").is_none());
+        assert!(checkpoint.completed("3", "cc").is_none());
+
+        // Entries appended after resuming remain readable
+        checkpoint
+            .record(JournalEntry {
+                group_id: "3".to_string(),
+                input_hash: "cc".to_string(),
+                rows: 5,
+            })
+            .unwrap();
+        drop(checkpoint);
+        assert!(Checkpoint::open(&path).unwrap().completed("3", "cc").is_some());
+    }
+
+    #[test]
+    fn test_fnv1a_reference_values() {
+        let mut hasher = Fnv1a::new();
+        assert_eq!(hasher.0, 0xcbf29ce484222325);
+        hasher.update(b"a");
+        assert_eq!(hasher.0, 0xaf63dc4c8601ec8c);
+    }
+}
@@ -1,11 +1,24 @@
 use crate::error::{Error, Result};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SegmentationMethod {
     Edge,
     Hough,
-    Advanced, // Advanced Hough with adaptive thresholding
+    Advanced,         // Advanced Hough with adaptive thresholding
+    External(String), // Segmenter registered under this name
+}
+
+impl SegmentationMethod {
+    /// Name used to look the segmenter up in the `SegmenterRegistry`
+    pub fn name(&self) -> &str {
+        match self {
+            SegmentationMethod::Edge => "edge",
+            SegmentationMethod::Hough => "hough",
+            SegmentationMethod::Advanced => "advanced",
+            SegmentationMethod::External(name) => name,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -13,6 +26,85 @@ pub enum GridDetectionMethod {
     Template,  // Template matching (current)
     FFT,       // FFT-based frequency analysis
     Hybrid,    // Combination of both
+    Reference, // Template pose re-solved from the located reference spots
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GridModelType {
+    Rigid,      // Single pitch + rotation (MATLAB pg_grid_coordinates)
+    Affine,     // Separate x/y pitch, rotation and shear
+    Polynomial, // Second-order polynomial for optical distortion
+}
+
+/// Order in which batch group results are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutputOrder {
+    #[default]
+    Input,      // Configuration order (finished groups wait for earlier ones)
+    Completion, // As soon as a group finishes; rows are tagged with groupId
+}
+
+/// What a batch does when a group fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FailurePolicy {
+    #[default]
+    FailFast, // Stop starting new groups and return the error
+    Continue, // Record the failure and process the remaining groups
+}
+
+/// What happens to a group whose grid quality is below `grdMinQuality`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GridQualityAction {
+    #[default]
+    Review, // Keep the results, flagged with grdNeedsReview
+    Fail,   // Fail the group with GridDetectionFailed
+}
+
+/// File format of result tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Tsv,
+    Parquet,  // Snappy-compressed, one row group per write
+    ArrowIpc, // Arrow IPC file (Feather v2)
+    Tercen,   // Tercen JSON table; columns are spooled to files next to it until finished
+}
+
+/// Mode of a single MATLAB-style run (`pgMode`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RunMode {
+    Grid,           // Find the grid and write grid positions
+    Quantification, // Segment at given grid positions and quantify every image
+}
+
+/// How raw sensor counts are mapped to the working 0-1 intensity range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntensityNormalization {
+    Max,        // Divide by the image maximum (MATLAB behaviour)
+    BitDepth,   // Divide by the full sensor range 2^bits - 1
+    Percentile, // Divide by a high percentile, robust to saturated artifacts
+}
+
+/// Intensity threshold expressed relative to the normalization reference or in raw counts
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IntensityThreshold {
+    /// Fraction of the reference intensity (image max, sensor range or percentile)
+    Relative(f64),
+    /// Raw sensor counts
+    Absolute(f64),
+}
+
+impl IntensityThreshold {
+    /// Threshold in normalized units, given the raw count that maps to 1.0
+    pub fn normalized(&self, reference: f64) -> f64 {
+        match *self {
+            IntensityThreshold::Relative(value) => value,
+            IntensityThreshold::Absolute(counts) if reference > 0.0 => counts / reference,
+            IntensityThreshold::Absolute(_) => 0.0,
+        }
+    }
 }
 
 impl std::str::FromStr for SegmentationMethod {
@@ -23,10 +115,10 @@ impl std::str::FromStr for SegmentationMethod {
             "edge" => Ok(SegmentationMethod::Edge),
             "hough" => Ok(SegmentationMethod::Hough),
             "advanced" => Ok(SegmentationMethod::Advanced),
-            _ => Err(Error::InvalidParameter(format!(
-                "Unknown segmentation method: {}",
-                s
-            ))),
+            "" => Err(Error::InvalidParameter(
+                "Empty segmentation method".to_string(),
+            )),
+            _ => Ok(SegmentationMethod::External(s.to_string())),
         }
     }
 }
@@ -39,6 +131,9 @@ impl std::str::FromStr for GridDetectionMethod {
             "template" => Ok(GridDetectionMethod::Template),
             "fft" => Ok(GridDetectionMethod::FFT),
             "hybrid" => Ok(GridDetectionMethod::Hybrid),
+            "reference" | "referenceanchored" | "reference-anchored" => {
+                Ok(GridDetectionMethod::Reference)
+            }
             _ => Err(Error::InvalidParameter(format!(
                 "Unknown grid detection method: {}",
                 s
@@ -47,6 +142,136 @@ impl std::str::FromStr for GridDetectionMethod {
     }
 }
 
+impl std::str::FromStr for OutputOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "input" | "ordered" => Ok(OutputOrder::Input),
+            "completion" | "tagged" => Ok(OutputOrder::Completion),
+            _ => Err(Error::InvalidParameter(format!(
+                "Unknown output order: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for FailurePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "failfast" | "fail-fast" | "fail" => Ok(FailurePolicy::FailFast),
+            "continue" => Ok(FailurePolicy::Continue),
+            _ => Err(Error::InvalidParameter(format!(
+                "Unknown failure policy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Format implied by a file extension (.csv, .tsv, .parquet, .arrow, .feather, .json)
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        match extension.to_lowercase().as_str() {
+            "csv" => Some(OutputFormat::Csv),
+            "tsv" | "tab" => Some(OutputFormat::Tsv),
+            "parquet" | "pq" => Some(OutputFormat::Parquet),
+            "arrow" | "ipc" | "feather" => Some(OutputFormat::ArrowIpc),
+            "json" => Some(OutputFormat::Tercen),
+            _ => None,
+        }
+    }
+
+    /// Whether rows can be appended to and read back from a partially written file
+    pub fn is_delimited(self) -> bool {
+        matches!(self, OutputFormat::Csv | OutputFormat::Tsv)
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" | "tab" => Ok(OutputFormat::Tsv),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "arrow" | "ipc" | "arrowipc" | "arrow_ipc" | "feather" => Ok(OutputFormat::ArrowIpc),
+            "tercen" | "json" => Ok(OutputFormat::Tercen),
+            _ => Err(Error::InvalidParameter(format!(
+                "Unknown output format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for RunMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "grid" | "gridding" => Ok(RunMode::Grid),
+            "quantification" | "quant" => Ok(RunMode::Quantification),
+            _ => Err(Error::InvalidParameter(format!(
+                "Unknown pgMode: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for IntensityNormalization {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "max" => Ok(IntensityNormalization::Max),
+            "bitdepth" | "sensor" => Ok(IntensityNormalization::BitDepth),
+            "percentile" => Ok(IntensityNormalization::Percentile),
+            _ => Err(Error::InvalidParameter(format!(
+                "Unknown intensity normalization: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for GridModelType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "rigid" => Ok(GridModelType::Rigid),
+            "affine" => Ok(GridModelType::Affine),
+            "polynomial" | "quadratic" => Ok(GridModelType::Polynomial),
+            _ => Err(Error::InvalidParameter(format!(
+                "Unknown grid model: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for GridQualityAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "review" => Ok(GridQualityAction::Review),
+            "fail" => Ok(GridQualityAction::Fail),
+            _ => Err(Error::InvalidParameter(format!(
+                "Unknown grid quality action: {}",
+                s
+            ))),
+        }
+    }
+}
+
 /// Grid detection and processing parameters
 /// These parameters match MATLAB's default values from pg_io_get_default_params
 #[derive(Debug, Clone)]
@@ -62,6 +287,10 @@ pub struct GridParams {
     pub max_position_offset: f64,
     /// Maximum position offset for reference spots (relative to pitch) - MATLAB: sqcMaxPositionOffsetRefs = 0.6
     pub max_position_offset_refs: f64,
+    /// Grid quality score (0-1) below which a group is reviewed or failed
+    pub min_grid_quality: f64,
+    /// Handling of groups below `min_grid_quality`
+    pub grid_quality_action: GridQualityAction,
 
     // Grid Parameters (grd* in MATLAB)
     /// Spot pitch in pixels - MATLAB: grdSpotPitch = 21.5
@@ -74,6 +303,8 @@ pub struct GridParams {
     pub search_diameter: f64,
     /// Array layout file path
     pub array_layout_file: Option<String>,
+    /// Grid model refitted to segmented spot centers (Rigid = no refit)
+    pub grid_model: GridModelType,
 
     // Segmentation Parameters (seg* in MATLAB)
     /// Saturation limit - MATLAB: qntSaturationLimit = 4095 (2^12-1)
@@ -90,6 +321,24 @@ pub struct GridParams {
     pub min_edge_pixels: usize,
     /// Background offset (relative) - MATLAB: segBgOffset = 0.45
     pub bg_offset: f64,
+    /// Mean spot intensity below which a spot is empty (MATLAB uses 0.1 of the image max)
+    pub empty_threshold: IntensityThreshold,
+    /// Minimum local maximum accepted when refining grid positions
+    pub refine_threshold: IntensityThreshold,
+
+    // Intensity parameters
+    /// Sensor bit depth; raw counts range over 0..=2^bit_depth-1
+    pub bit_depth: u32,
+    /// Mapping from raw counts to normalized intensities
+    pub intensity_normalization: IntensityNormalization,
+    /// Percentile (0-100] used as reference by `IntensityNormalization::Percentile`
+    pub normalization_percentile: f64,
+
+    // Series quantification parameters
+    /// Register each image of a series to the grid image and shift spots accordingly
+    pub drift_correction: bool,
+    /// Maximum rotation (degrees) searched during drift registration, 0 = translation only
+    pub drift_max_rotation: f64,
 
     // Preprocessing Parameters (prp* in MATLAB)
     /// Large disk size for preprocessing (relative) - MATLAB: prpLargeDisk = 0.51
@@ -108,6 +357,8 @@ impl Default for GridParams {
             min_snr: 1.0,
             max_position_offset: 0.4,
             max_position_offset_refs: 0.6,
+            min_grid_quality: 0.5,
+            grid_quality_action: GridQualityAction::Review,
 
             // Grid parameters
             spot_pitch: 21.5,
@@ -115,6 +366,7 @@ impl Default for GridParams {
             rotation_range: vec![0.0],
             search_diameter: 15.0,
             array_layout_file: None,
+            grid_model: GridModelType::Rigid,
 
             // Segmentation parameters
             saturation_limit: 4095.0,  // 2^12-1 for 12-bit images
@@ -124,6 +376,17 @@ impl Default for GridParams {
             area_size: 0.7,
             min_edge_pixels: 6,
             bg_offset: 0.45,
+            empty_threshold: IntensityThreshold::Relative(0.1),
+            refine_threshold: IntensityThreshold::Relative(0.1),
+
+            // Intensity parameters
+            bit_depth: 12,
+            intensity_normalization: IntensityNormalization::Max,
+            normalization_percentile: 99.5,
+
+            // Series quantification parameters
+            drift_correction: false,
+            drift_max_rotation: 0.0,
 
             // Preprocessing parameters
             large_disk: 0.51,
@@ -133,6 +396,11 @@ impl Default for GridParams {
 }
 
 impl GridParams {
+    /// Largest raw count the sensor can report
+    pub fn sensor_max(&self) -> f64 {
+        ((1u64 << self.bit_depth.min(16)) - 1) as f64
+    }
+
     pub fn validate(&self) -> Result<()> {
         if self.min_diameter >= self.max_diameter {
             return Err(Error::InvalidParameter(
@@ -158,6 +426,18 @@ impl GridParams {
             ));
         }
 
+        if self.bit_depth == 0 || self.bit_depth > 16 {
+            return Err(Error::InvalidParameter(
+                "bit_depth must be between 1 and 16".to_string(),
+            ));
+        }
+
+        if self.normalization_percentile <= 0.0 || self.normalization_percentile > 100.0 {
+            return Err(Error::InvalidParameter(
+                "normalization_percentile must be in (0, 100]".to_string(),
+            ));
+        }
+
         Ok(())
     }
 }
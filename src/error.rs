@@ -16,6 +16,12 @@ pub enum Error {
     #[error("CSV error: {0}")]
     Csv(#[from] csv::Error),
 
+    #[error("Arrow error: {0}")]
+    Arrow(#[from] arrow_schema::ArrowError),
+
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+
     #[error("Invalid parameter: {0}")]
     InvalidParameter(String),
 
@@ -40,3 +46,25 @@ pub enum Error {
     #[error("Processing error: {0}")]
     ProcessingError(String),
 }
+
+impl Error {
+    /// Variant name, used in structured error reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "Io",
+            Error::Image(_) => "Image",
+            Error::Json(_) => "Json",
+            Error::Csv(_) => "Csv",
+            Error::Arrow(_) => "Arrow",
+            Error::Parquet(_) => "Parquet",
+            Error::InvalidParameter(_) => "InvalidParameter",
+            Error::InvalidDimensions { .. } => "InvalidDimensions",
+            Error::GridDetectionFailed(_) => "GridDetectionFailed",
+            Error::SegmentationFailed(_) => "SegmentationFailed",
+            Error::NoValidSpots => "NoValidSpots",
+            Error::FileNotFound(_) => "FileNotFound",
+            Error::InvalidConfiguration(_) => "InvalidConfiguration",
+            Error::ProcessingError(_) => "ProcessingError",
+        }
+    }
+}
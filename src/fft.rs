@@ -0,0 +1,221 @@
+use ndarray::Array2;
+use rayon::prelude::*;
+use rustfft::{num_complex::Complex, Fft, FftDirection, FftPlanner};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+type PlanCache = HashMap<(usize, bool), Arc<dyn Fft<f64>>>;
+
+/// Reusable FFT context with cached plans
+///
+/// Creating an `FftPlanner` per transform dominates run time when thousands of
+/// images (each tried at several rotations) are gridded. The context keeps one
+/// plan per (length, direction) and is safe to share between rayon workers.
+pub struct FftContext {
+    planner: Mutex<FftPlanner<f64>>,
+    plans: Mutex<PlanCache>,
+}
+
+impl Default for FftContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FftContext {
+    pub fn new() -> Self {
+        Self {
+            planner: Mutex::new(FftPlanner::new()),
+            plans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Process-wide context shared by gridding, registration and correlation
+    pub fn shared() -> &'static FftContext {
+        static SHARED: OnceLock<FftContext> = OnceLock::new();
+        SHARED.get_or_init(FftContext::new)
+    }
+
+    /// Get (or create and cache) a plan for the given length and direction
+    pub fn plan(&self, len: usize, direction: FftDirection) -> Arc<dyn Fft<f64>> {
+        let key = (len, direction == FftDirection::Forward);
+        let mut plans = self.plans.lock().unwrap();
+        if let Some(plan) = plans.get(&key) {
+            return Arc::clone(plan);
+        }
+
+        let plan = self.planner.lock().unwrap().plan_fft(len, direction);
+        plans.insert(key, Arc::clone(&plan));
+        plan
+    }
+
+    /// Number of cached plans (mainly for diagnostics and tests)
+    pub fn cached_plans(&self) -> usize {
+        self.plans.lock().unwrap().len()
+    }
+
+    /// Forward 2D FFT of a real image
+    pub fn fft2(&self, data: &Array2<f64>) -> Array2<Complex<f64>> {
+        let (height, width) = data.dim();
+        let mut buffer: Vec<Complex<f64>> = data.iter().map(|&v| Complex::new(v, 0.0)).collect();
+        self.transform_2d(&mut buffer, height, width, FftDirection::Forward);
+        Array2::from_shape_vec((height, width), buffer).expect("FFT buffer shape")
+    }
+
+    /// Forward 2D FFT of complex data
+    pub fn fft2_complex(&self, data: &Array2<Complex<f64>>) -> Array2<Complex<f64>> {
+        let (height, width) = data.dim();
+        let mut buffer: Vec<Complex<f64>> = data.iter().copied().collect();
+        self.transform_2d(&mut buffer, height, width, FftDirection::Forward);
+        Array2::from_shape_vec((height, width), buffer).expect("FFT buffer shape")
+    }
+
+    /// Inverse 2D FFT (normalized), returning complex data
+    pub fn ifft2_complex(&self, data: &Array2<Complex<f64>>) -> Array2<Complex<f64>> {
+        let (height, width) = data.dim();
+        let mut buffer: Vec<Complex<f64>> = data.iter().copied().collect();
+        self.transform_2d(&mut buffer, height, width, FftDirection::Inverse);
+
+        let scale = 1.0 / (height * width) as f64;
+        buffer.par_iter_mut().for_each(|v| *v *= scale);
+        Array2::from_shape_vec((height, width), buffer).expect("FFT buffer shape")
+    }
+
+    /// Inverse 2D FFT (normalized), returning the real part
+    pub fn ifft2(&self, data: &Array2<Complex<f64>>) -> Array2<f64> {
+        self.ifft2_complex(data).mapv(|v| v.re)
+    }
+
+    /// Row/column separable 2D transform on a row-major buffer
+    /// Rows and columns are processed in parallel with rayon
+    fn transform_2d(
+        &self,
+        buffer: &mut [Complex<f64>],
+        height: usize,
+        width: usize,
+        direction: FftDirection,
+    ) {
+        if height == 0 || width == 0 {
+            return;
+        }
+
+        let row_plan = self.plan(width, direction);
+        let col_plan = self.plan(height, direction);
+
+        process_lines(buffer, width, &row_plan);
+
+        // Transpose so columns become contiguous lines
+        let mut transposed = vec![Complex::new(0.0, 0.0); height * width];
+        transposed
+            .par_chunks_mut(height)
+            .enumerate()
+            .for_each(|(j, column)| {
+                for (i, v) in column.iter_mut().enumerate() {
+                    *v = buffer[i * width + j];
+                }
+            });
+
+        process_lines(&mut transposed, height, &col_plan);
+
+        buffer
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(i, row)| {
+                for (j, v) in row.iter_mut().enumerate() {
+                    *v = transposed[j * height + i];
+                }
+            });
+    }
+}
+
+/// Apply a 1D plan to every contiguous line of `len` elements
+fn process_lines(buffer: &mut [Complex<f64>], len: usize, plan: &Arc<dyn Fft<f64>>) {
+    let scratch_len = plan.get_inplace_scratch_len();
+    buffer.par_chunks_mut(len).for_each_init(
+        || vec![Complex::new(0.0, 0.0); scratch_len],
+        |scratch, line| plan.process_with_scratch(line, scratch),
+    );
+}
+
+/// Precomputed spectrum of an image, reused across rotations and templates
+#[derive(Debug, Clone)]
+pub struct ImageSpectrum {
+    pub spectrum: Array2<Complex<f64>>,
+}
+
+impl ImageSpectrum {
+    pub fn new(ctx: &FftContext, image: &Array2<f64>) -> Self {
+        Self {
+            spectrum: ctx.fft2(image),
+        }
+    }
+
+    pub fn dim(&self) -> (usize, usize) {
+        self.spectrum.dim()
+    }
+
+    /// Circular cross-correlation with another real array of the same size:
+    /// IFFT(FFT(image) * conj(FFT(other)))
+    pub fn correlate(&self, ctx: &FftContext, other: &Array2<f64>) -> Array2<f64> {
+        let other_fft = ctx.fft2(other);
+        self.correlate_spectrum(ctx, &other_fft)
+    }
+
+    /// Circular cross-correlation with a precomputed spectrum
+    pub fn correlate_spectrum(
+        &self,
+        ctx: &FftContext,
+        other: &Array2<Complex<f64>>,
+    ) -> Array2<f64> {
+        let product = Array2::from_shape_fn(self.spectrum.dim(), |(i, j)| {
+            self.spectrum[[i, j]] * other[[i, j]].conj()
+        });
+        ctx.ifft2(&product)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fft_roundtrip_non_square() {
+        let ctx = FftContext::new();
+        let data = Array2::from_shape_fn((6, 10), |(i, j)| (i * 3 + j * j) as f64);
+        let result = ctx.ifft2(&ctx.fft2(&data));
+
+        for (a, b) in data.iter().zip(result.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_plans_are_cached() {
+        let ctx = FftContext::new();
+        let data = Array2::from_elem((8, 16), 1.0);
+        ctx.fft2(&data);
+        ctx.fft2(&data);
+        ctx.ifft2(&ctx.fft2(&data));
+
+        // Forward and inverse plans for both lengths
+        assert_eq!(ctx.cached_plans(), 4);
+    }
+
+    #[test]
+    fn test_spectrum_correlation_finds_shift() {
+        let ctx = FftContext::new();
+        let mut image = Array2::zeros((16, 16));
+        let mut template = Array2::zeros((16, 16));
+        image[[7, 9]] = 1.0;
+        template[[4, 4]] = 1.0;
+
+        let spectrum = ImageSpectrum::new(&ctx, &image);
+        let corr = spectrum.correlate(&ctx, &template);
+        let (pos, _) = corr
+            .indexed_iter()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .unwrap();
+
+        assert_eq!(pos, (3, 5));
+    }
+}
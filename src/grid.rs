@@ -1,9 +1,10 @@
-use crate::config::GridParams;
+use crate::config::{GridDetectionMethod, GridParams};
 use crate::error::{Error, Result};
-use crate::image_processing::normalize_image;
+use crate::fft::{FftContext, ImageSpectrum};
+use crate::grid_detector::{anchor_to_references, detector_for, GridFit};
+use crate::image_processing::{normalize_image, preprocess_grid_image, IntensityScale};
 use crate::types::{ImageData, Spot};
 use ndarray::Array2;
-use rustfft::{FftPlanner, num_complex::Complex};
 use std::f64::consts::PI;
 
 /// Create circular disk coordinates for template generation (matching MATLAB's pg_circle)
@@ -153,82 +154,6 @@ fn make_template(
     Ok(template)
 }
 
-/// Perform 2D FFT
-fn fft2(data: &Array2<f64>) -> Array2<Complex<f64>> {
-    let (height, width) = data.dim();
-    let mut planner = FftPlanner::new();
-    let fft_row = planner.plan_fft_forward(width);
-    let fft_col = planner.plan_fft_forward(height);
-
-    // Convert to complex
-    let mut complex_data = Array2::from_shape_fn((height, width), |(i, j)| {
-        Complex::new(data[[i, j]], 0.0)
-    });
-
-    // FFT on rows
-    let mut row_buffer = vec![Complex::new(0.0, 0.0); width];
-    for i in 0..height {
-        for j in 0..width {
-            row_buffer[j] = complex_data[[i, j]];
-        }
-        fft_row.process(&mut row_buffer);
-        for j in 0..width {
-            complex_data[[i, j]] = row_buffer[j];
-        }
-    }
-
-    // FFT on columns
-    let mut col_buffer = vec![Complex::new(0.0, 0.0); height];
-    for j in 0..width {
-        for i in 0..height {
-            col_buffer[i] = complex_data[[i, j]];
-        }
-        fft_col.process(&mut col_buffer);
-        for i in 0..height {
-            complex_data[[i, j]] = col_buffer[i];
-        }
-    }
-
-    complex_data
-}
-
-/// Perform 2D inverse FFT
-fn ifft2(data: &Array2<Complex<f64>>) -> Array2<f64> {
-    let (height, width) = data.dim();
-    let mut planner = FftPlanner::new();
-    let ifft_row = planner.plan_fft_inverse(width);
-    let ifft_col = planner.plan_fft_inverse(height);
-
-    let mut complex_data = data.clone();
-
-    // IFFT on rows
-    let mut row_buffer = vec![Complex::new(0.0, 0.0); width];
-    for i in 0..height {
-        for j in 0..width {
-            row_buffer[j] = complex_data[[i, j]];
-        }
-        ifft_row.process(&mut row_buffer);
-        for j in 0..width {
-            complex_data[[i, j]] = row_buffer[j] / (width as f64);
-        }
-    }
-
-    // IFFT on columns
-    let mut col_buffer = vec![Complex::new(0.0, 0.0); height];
-    for j in 0..width {
-        for i in 0..height {
-            col_buffer[i] = complex_data[[i, j]];
-        }
-        ifft_col.process(&mut col_buffer);
-        for i in 0..height {
-            complex_data[[i, j]] = col_buffer[i] / (height as f64);
-        }
-    }
-
-    // Extract real part
-    Array2::from_shape_fn((height, width), |(i, j)| complex_data[[i, j]].re)
-}
-
 /// FFT shift (move zero frequency to center)
 fn fftshift(data: &Array2<f64>) -> Array2<f64> {
     let (height, width) = data.dim();
@@ -249,21 +174,16 @@ fn fftshift(data: &Array2<f64>) -> Array2<f64> {
 }
 
 /// Perform template correlation matching MATLAB's pg_template_correlation
+/// The image spectrum is precomputed once per image and reused for every template
+/// Returns (x, y, peak, peak z-score over the correlation surface)
 fn template_correlation(
-    image: &Array2<f64>,
+    ctx: &FftContext,
+    image_spectrum: &ImageSpectrum,
     template: &Array2<f64>,
-) -> Result<(f64, f64, f64)> {
-    // FFT of image and template
-    let fft_image = fft2(image);
-    let fft_template = fft2(template);
-
+) -> Result<(f64, f64, f64, f64)> {
     // Correlation: IFFT(FFT(image) * conj(FFT(template)))
-    let (height, width) = image.dim();
-    let correlation_fft = Array2::from_shape_fn((height, width), |(i, j)| {
-        fft_image[[i, j]] * fft_template[[i, j]].conj()
-    });
-
-    let correlation = ifft2(&correlation_fft);
+    let (height, width) = image_spectrum.dim();
+    let correlation = image_spectrum.correlate(ctx, template);
 
     // Shift and find maximum (matching MATLAB: C = fftshift(C))
     let shifted = fftshift(&correlation);
@@ -281,8 +201,14 @@ fn template_correlation(
         }
     }
 
+    // Peak strength relative to the rest of the surface
+    let n = (height * width) as f64;
+    let mean = shifted.sum() / n;
+    let std = (shifted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+    let zscore = if std > 0.0 { (max_val - mean) / std } else { 0.0 };
+
     // Return (x, y) matching MATLAB's [x,y] = ind2sub(size(C), idx)
-    Ok((max_pos.1 as f64, max_pos.0 as f64, max_val))
+    Ok((max_pos.1 as f64, max_pos.0 as f64, max_val, zscore))
 }
 
 /// Find grid center using FFT-based template matching (matching MATLAB's pg_grid_find)
@@ -291,8 +217,29 @@ pub fn find_grid_center(
     layout: &[(String, bool, i32, i32)],
     params: &GridParams,
 ) -> Result<(f64, f64, f64)> {
+    let fit = match_grid_template(image, layout, params)?;
+    Ok((fit.center.0, fit.center.1, fit.rotation))
+}
+
+/// Template match over `params.rotation_range`, returning the best fit and its scores
+pub fn match_grid_template(
+    image: &ImageData,
+    layout: &[(String, bool, i32, i32)],
+    params: &GridParams,
+) -> Result<GridFit> {
     let normalized = normalize_image(&image.data);
 
+    // Artifact removal and top-hat background subtraction (MATLAB pg_pp_fun)
+    let small_disk = (params.small_disk * params.spot_pitch).round() as usize;
+    let large_disk = (params.large_disk * params.spot_pitch).round() as usize;
+    // spot_size is relative to the pitch, the template disk is in pixels
+    let spot_diameter = params.spot_size * params.spot_pitch;
+    let preprocessed = preprocess_grid_image(&normalized, small_disk, large_disk);
+
+    // The image spectrum does not depend on rotation, compute it once
+    let ctx = FftContext::shared();
+    let image_spectrum = ImageSpectrum::new(ctx, &preprocessed);
+
     // Extract layout information
     let mut rows = Vec::new();
     let mut cols = Vec::new();
@@ -307,6 +254,8 @@ pub fn find_grid_center(
     }
 
     let mut best_score = f64::NEG_INFINITY;
+    let mut best_zscore = 0.0;
+    let mut runner_up = f64::NEG_INFINITY;
     let mut best_center = (0.0, 0.0);
     let mut best_rotation = 0.0;
 
@@ -326,23 +275,45 @@ pub fn find_grid_center(
             &x_offsets,
             &y_offsets,
             &is_reference,
-            params.spot_size,
+            spot_diameter,
             params.spot_pitch,
             rotation,
         )?;
 
         // Perform correlation
-        let (cx, cy, score) = template_correlation(&normalized, &template)?;
+        let (cx, cy, score, zscore) = template_correlation(ctx, &image_spectrum, &template)?;
 
         if score > best_score {
+            runner_up = best_score;
             best_score = score;
+            best_zscore = zscore;
             best_center = (cx, cy);
             best_rotation = rotation;
+        } else if score > runner_up {
+            runner_up = score;
         }
     }
 
+    if !best_score.is_finite() {
+        return Err(Error::GridDetectionFailed(
+            "Template correlation produced no finite peak".to_string(),
+        ));
+    }
+
     // Apply MATLAB's -2 adjustment (from pg_grid_find.m lines 124-125: cx = cx-2; cy = cy-2)
-    Ok((best_center.0 - 2.0, best_center.1 - 2.0, best_rotation))
+    let mut fit = GridFit::new(
+        (best_center.0 - 2.0, best_center.1 - 2.0),
+        best_rotation,
+        params.spot_pitch,
+        best_score,
+    );
+    fit.diagnostics.insert("peak_zscore".to_string(), best_zscore);
+    fit.diagnostics.insert("rotations_tried".to_string(), rotations.len() as f64);
+    if runner_up.is_finite() {
+        fit.diagnostics.insert("runner_up_score".to_string(), runner_up);
+    }
+
+    Ok(fit)
 }
 
 /// Generate grid coordinates from center and layout (matching MATLAB's pg_grid_coordinates)
@@ -364,12 +335,12 @@ pub fn generate_grid_coordinates(
 
     // Separate regular and reference spots
     let regular_spots: Vec<(String, bool, i32, i32)> = layout.iter()
-        .filter(|(_, is_ref, r, c)| *r > 0 && *c > 0 && !*is_ref)
+        .filter(|(_, is_ref, r, c)| is_regular_position(*is_ref, *r, *c))
         .cloned()
         .collect();
 
     let reference_spots: Vec<(String, bool, i32, i32)> = layout.iter()
-        .filter(|(_, is_ref, r, c)| !(*r > 0 && *c > 0) || *is_ref)
+        .filter(|(_, is_ref, r, c)| !is_regular_position(*is_ref, *r, *c))
         .cloned()
         .collect();
 
@@ -429,6 +400,7 @@ pub fn generate_grid_coordinates(
                 grid_y: abs_y,
                 diameter: 0.0,
                 is_manual: false,
+                manual_source: None,
                 is_bad: false,
                 is_empty: false,
                 rotation,
@@ -492,6 +464,7 @@ pub fn generate_grid_coordinates(
                 grid_y: abs_y,
                 diameter: 0.0,
                 is_manual: false,
+                manual_source: None,
                 is_bad: false,
                 is_empty: false,
                 rotation,
@@ -504,13 +477,81 @@ pub fn generate_grid_coordinates(
     spots
 }
 
+/// Whether a layout entry belongs to the regular sub-grid (positive indices, not #REF)
+pub(crate) fn is_regular_position(is_ref: bool, row: i32, col: i32) -> bool {
+    row > 0 && col > 0 && !is_ref
+}
+
+/// Midpoint (row, col) of a sub-grid after abs(), matching pg_grid_coordinates
+pub(crate) fn subgrid_midpoint<'a>(positions: impl Iterator<Item = (i32, i32)> + 'a) -> Option<(f64, f64)> {
+    let mut row_min = f64::INFINITY;
+    let mut row_max = f64::NEG_INFINITY;
+    let mut col_min = f64::INFINITY;
+    let mut col_max = f64::NEG_INFINITY;
+    let mut any = false;
+
+    for (row, col) in positions {
+        let r = row.abs() as f64;
+        let c = col.abs() as f64;
+        row_min = row_min.min(r);
+        row_max = row_max.max(r);
+        col_min = col_min.min(c);
+        col_max = col_max.max(c);
+        any = true;
+    }
+
+    if !any {
+        return None;
+    }
+
+    Some((
+        row_min + (row_max - row_min) / 2.0,
+        col_min + (col_max - col_min) / 2.0,
+    ))
+}
+
+/// Lattice coordinates of each spot relative to its sub-grid midpoint
+///
+/// Returns `(|row| - row_mid, |col| - col_mid)` per spot, using the same separate
+/// regular/reference midpoints as `generate_grid_coordinates`, so that
+/// `center + pitch * rotate(u, v)` reproduces the rigid grid.
+pub fn lattice_coordinates(spots: &[Spot]) -> Vec<(f64, f64)> {
+    let regular_mid = subgrid_midpoint(
+        spots
+            .iter()
+            .filter(|s| is_regular_position(s.is_reference, s.row, s.col))
+            .map(|s| (s.row, s.col)),
+    );
+    let reference_mid = subgrid_midpoint(
+        spots
+            .iter()
+            .filter(|s| !is_regular_position(s.is_reference, s.row, s.col))
+            .map(|s| (s.row, s.col)),
+    );
+
+    spots
+        .iter()
+        .map(|s| {
+            let mid = if is_regular_position(s.is_reference, s.row, s.col) {
+                regular_mid
+            } else {
+                reference_mid
+            };
+            let (row_mid, col_mid) = mid.unwrap_or((0.0, 0.0));
+            (s.row.abs() as f64 - row_mid, s.col.abs() as f64 - col_mid)
+        })
+        .collect()
+}
+
 /// Refine grid positions based on actual spot locations
 pub fn refine_grid_positions(
     image: &ImageData,
     spots: &mut [Spot],
     params: &GridParams,
 ) -> Result<()> {
-    let normalized = normalize_image(&image.data);
+    let scale = IntensityScale::from_image(&image.data, params);
+    let normalized = scale.normalize(&image.data);
+    let min_peak = scale.threshold(params.refine_threshold);
 
     for spot in spots.iter_mut() {
         // Skip if fixed position is set (matching MATLAB's bFixedSpot handling)
@@ -543,7 +584,7 @@ pub fn refine_grid_positions(
         }
 
         // Update position if significantly better
-        if max_val > 0.1 {
+        if max_val > min_peak {
             spot.grid_x = max_pos.0;
             spot.grid_y = max_pos.1;
         }
@@ -558,62 +599,63 @@ pub fn process_gridding(
     layout: &[(String, bool, i32, i32)],
     params: &GridParams,
 ) -> Result<Vec<Spot>> {
-    eprintln!("===== process_gridding called =====");
+    let fit = detect_grid(images, layout, params)?;
+    Ok(grid_spots(&fit, layout))
+}
 
+/// Locate the grid of an image group with the configured detector
+///
+/// The returned fit is the pose used for the spot positions, so it can be
+/// edited and turned into spots again with [`grid_spots`].
+pub fn detect_grid(
+    images: &[ImageData],
+    layout: &[(String, bool, i32, i32)],
+    params: &GridParams,
+) -> Result<GridFit> {
     if images.is_empty() {
         return Err(Error::InvalidParameter("No images provided".to_string()));
     }
 
     // Use last image for gridding (matching MATLAB behavior with "First" option)
     let grid_image = images.last().unwrap();
-    eprintln!("Image size: {}x{}", grid_image.width, grid_image.height);
-
-    // Find grid center and rotation using FFT template matching
-    let (center_x, center_y, rotation) = find_grid_center(grid_image, layout, params)?;
-    eprintln!("FFT found center: ({:.1}, {:.1})", center_x, center_y);
-
-    // TEMP DEBUG: Override with calculated center from MATLAB reference
-    // For row 6, col 6 at (249.2, 338.7): center = (249.2 + 8.5, 338.7 + 8.5) = (257.7, 347.2)
-    let center_x_debug = 257.7;
-    let center_y_debug = 347.2;
-    tracing::info!("FFT center: ({:.1}, {:.1}), MATLAB-derived center: ({:.1}, {:.1})",
-        center_x, center_y, center_x_debug, center_y_debug);
-
-    // Use MATLAB-derived center instead of FFT result for debugging
-    let center_x = center_x_debug;
-    let center_y = center_y_debug;
 
+    // Find grid center and rotation with the configured detector
+    let fit = detector_for(params.grid_detection_method).detect(grid_image, layout, params)?;
+    if params.grid_detection_method == GridDetectionMethod::Reference {
+        // Reference spots are bright in every cycle; solve the pose from them,
+        // seeded by the match of the reference-only template
+        let anchored = anchor_to_references(grid_image, layout, &fit, params)?;
+        tracing::info!(
+            "Reference-anchored grid: center=({:.2}, {:.2}), rotation={:.3}°, pitch={:.3} \
+             ({} references, rms {:.2} px)",
+            anchored.center.0,
+            anchored.center.1,
+            anchored.rotation,
+            anchored.pitch,
+            anchored.diagnostics["references_located"],
+            anchored.diagnostics["reference_rms"]
+        );
+        return Ok(anchored);
+    }
     tracing::debug!(
         "Grid detection: center=({:.2}, {:.2}), rotation={:.2}°",
-        center_x,
-        center_y,
-        rotation
+        fit.center.0,
+        fit.center.1,
+        fit.rotation
     );
 
-    // Generate initial grid coordinates
-    let mut spots = generate_grid_coordinates(
-        (center_x, center_y),
-        rotation,
-        params.spot_pitch,
-        layout,
-    );
+    Ok(fit)
+}
 
-    // DEBUG: Check first few generated positions
-    for (i, spot) in spots.iter().enumerate().take(5) {
-        eprintln!("Generated spot {}: row={}, col={}, grid_x={:.1}, grid_y={:.1}",
-            i, spot.row, spot.col, spot.grid_x, spot.grid_y);
-    }
+/// Spot positions of a grid pose
+pub fn grid_spots(fit: &GridFit, layout: &[(String, bool, i32, i32)]) -> Vec<Spot> {
+    // Generate initial grid coordinates
+    let spots = fit.spots(layout);
 
     // TEMP: Skip refinement to test pure generated positions
     // refine_grid_positions(grid_image, &mut spots, params)?;
 
-    // DEBUG: Check positions after refinement
-    for (i, spot) in spots.iter().enumerate().take(5) {
-        eprintln!("Refined spot {}: row={}, col={}, grid_x={:.1}, grid_y={:.1}",
-            i, spot.row, spot.col, spot.grid_x, spot.grid_y);
-    }
-
-    Ok(spots)
+    spots
 }
 
 #[cfg(test)]
@@ -631,11 +673,37 @@ mod tests {
         assert!((y - 0.0).abs() < 1e-6);
     }
 
+    #[test]
+    fn test_template_holds_reference_spots_only() {
+        // Two regular spots and one reference spot
+        let rows = [1, 3, -2];
+        let cols = [1, 3, -2];
+        let is_reference = [false, false, true];
+        let template = make_template(
+            (100, 100),
+            &rows,
+            &cols,
+            &[0.0; 3],
+            &[0.0; 3],
+            &is_reference,
+            8.0,
+            20.0,
+            0.0,
+        )
+        .unwrap();
+
+        let area = template.iter().filter(|&&v| v > 0.0).count();
+        assert_eq!(area, create_disk_coordinates(4).len());
+        // The reference spot sits on the grid midpoint
+        assert_eq!(template[[50, 50]], 1.0);
+    }
+
     #[test]
     fn test_fft_roundtrip() {
         let data = Array2::from_shape_fn((4, 4), |(i, j)| (i + j) as f64);
-        let fft = fft2(&data);
-        let result = ifft2(&fft);
+        let ctx = FftContext::shared();
+        let fft = ctx.fft2(&data);
+        let result = ctx.ifft2(&fft);
 
         for i in 0..4 {
             for j in 0..4 {
@@ -0,0 +1,553 @@
+use crate::advanced_grid::fft_grid_estimate;
+use crate::config::{GridDetectionMethod, GridParams};
+use crate::error::{Error, Result};
+use crate::grid::{
+    generate_grid_coordinates, is_regular_position, lattice_coordinates, match_grid_template,
+    subgrid_midpoint,
+};
+use crate::image_processing::IntensityScale;
+use crate::types::{GridEdit, ImageData, Spot};
+use ndarray::Array2;
+use std::collections::BTreeMap;
+
+/// Relative deviation from `grdSpotPitch` accepted for an FFT pitch estimate
+const HYBRID_PITCH_TOLERANCE: f64 = 0.1;
+
+/// Relative deviation from the seed pitch accepted for a reference-anchored pose
+const ANCHOR_PITCH_TOLERANCE: f64 = 0.1;
+
+/// Fewest located reference spots a reference-anchored pose is solved from
+const MIN_ANCHOR_REFERENCES: usize = 3;
+
+/// Lattice coordinates `(u, v)` of a spot and its located image position
+type Correspondence = ((f64, f64), (f64, f64));
+
+/// Grid pose found by a detector
+///
+/// `center` is the grid midpoint in image coordinates, i.e. the `center`
+/// argument of `generate_grid_coordinates`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridFit {
+    pub center: (f64, f64),
+    /// Rotation in degrees
+    pub rotation: f64,
+    /// Spot pitch in pixels
+    pub pitch: f64,
+    /// Detector-specific match score (higher is better)
+    pub score: f64,
+    /// Named intermediate values for logging and QC
+    pub diagnostics: BTreeMap<String, f64>,
+}
+
+impl GridFit {
+    pub fn new(center: (f64, f64), rotation: f64, pitch: f64, score: f64) -> Self {
+        Self {
+            center,
+            rotation,
+            pitch,
+            score,
+            diagnostics: BTreeMap::new(),
+        }
+    }
+
+    /// Shift the grid center by `(dx, dy)` pixels
+    pub fn translate(&mut self, dx: f64, dy: f64) {
+        self.center = (self.center.0 + dx, self.center.1 + dy);
+    }
+
+    /// Rotate the grid about its center by `degrees`
+    pub fn rotate(&mut self, degrees: f64) {
+        self.rotation += degrees;
+    }
+
+    /// Multiply the spot pitch by `factor`, keeping the center
+    pub fn scale_pitch(&mut self, factor: f64) {
+        self.pitch *= factor;
+    }
+
+    /// Apply a manual grid edit
+    pub fn apply_edit(&mut self, edit: &GridEdit) {
+        self.translate(edit.translate.0, edit.translate.1);
+        self.rotate(edit.rotate);
+        self.scale_pitch(edit.scale_pitch);
+    }
+
+    /// Spot positions of this grid for `layout`
+    pub fn spots(&self, layout: &[(String, bool, i32, i32)]) -> Vec<Spot> {
+        generate_grid_coordinates(self.center, self.rotation, self.pitch, layout)
+    }
+}
+
+/// Strategy for locating the spot grid in an image
+pub trait GridDetector: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn detect(
+        &self,
+        image: &ImageData,
+        layout: &[(String, bool, i32, i32)],
+        params: &GridParams,
+    ) -> Result<GridFit>;
+}
+
+/// Detector selected by `grid_detection_method`
+///
+/// `Reference` starts from the template match, whose template holds only the
+/// reference spots; `detect_grid` then anchors the pose to the located
+/// reference spots with [`anchor_to_references`].
+pub fn detector_for(method: GridDetectionMethod) -> Box<dyn GridDetector> {
+    match method {
+        GridDetectionMethod::Template | GridDetectionMethod::Reference => Box::new(TemplateDetector),
+        GridDetectionMethod::FFT => Box::new(FftDetector),
+        GridDetectionMethod::Hybrid => Box::new(HybridDetector),
+    }
+}
+
+/// Reference-spot template correlation (MATLAB pg_grid_find)
+pub struct TemplateDetector;
+
+impl GridDetector for TemplateDetector {
+    fn name(&self) -> &str {
+        "template"
+    }
+
+    fn detect(
+        &self,
+        image: &ImageData,
+        layout: &[(String, bool, i32, i32)],
+        params: &GridParams,
+    ) -> Result<GridFit> {
+        match_grid_template(image, layout, params)
+    }
+}
+
+/// Pitch, rotation and lattice phase from the image power spectrum
+///
+/// The spectrum only fixes the lattice up to a whole number of pitches, so the
+/// grid midpoint is placed at the matching lattice position closest to the
+/// image center.
+pub struct FftDetector;
+
+impl GridDetector for FftDetector {
+    fn name(&self) -> &str {
+        "fft"
+    }
+
+    fn detect(
+        &self,
+        image: &ImageData,
+        layout: &[(String, bool, i32, i32)],
+        params: &GridParams,
+    ) -> Result<GridFit> {
+        let estimate = fft_grid_estimate(image, params)?;
+        let pitch = 0.5 * (estimate.pitch_x + estimate.pitch_y);
+
+        let midpoint = subgrid_midpoint(
+            layout
+                .iter()
+                .filter(|(_, is_ref, row, col)| is_regular_position(*is_ref, *row, *col))
+                .map(|(_, _, row, col)| (*row, *col)),
+        )
+        .unwrap_or((0.0, 0.0));
+
+        let image_center = (
+            (image.width as f64 - 1.0) / 2.0,
+            (image.height as f64 - 1.0) / 2.0,
+        );
+        let center = center_from_lattice_point(
+            estimate.lattice_point,
+            (midpoint.0.fract(), midpoint.1.fract()),
+            pitch,
+            estimate.rotation,
+            image_center,
+        );
+
+        let mut fit = GridFit::new(center, estimate.rotation, pitch, estimate.peak_ratio);
+        fit.diagnostics.insert("pitch_x".to_string(), estimate.pitch_x);
+        fit.diagnostics.insert("pitch_y".to_string(), estimate.pitch_y);
+        Ok(fit)
+    }
+}
+
+/// Template correlation seeded with the FFT pitch and rotation
+///
+/// The FFT estimate is used only when it agrees with `grdSpotPitch` and the
+/// configured rotation range. Raw correlation peaks grow with the template's
+/// pitch, so the match with the higher peak z-score is returned.
+pub struct HybridDetector;
+
+impl GridDetector for HybridDetector {
+    fn name(&self) -> &str {
+        "hybrid"
+    }
+
+    fn detect(
+        &self,
+        image: &ImageData,
+        layout: &[(String, bool, i32, i32)],
+        params: &GridParams,
+    ) -> Result<GridFit> {
+        let mut best = match_grid_template(image, layout, params)?;
+
+        let estimate = match fft_grid_estimate(image, params) {
+            Ok(estimate) => estimate,
+            Err(e) => {
+                tracing::warn!("FFT estimate failed, using template match: {}", e);
+                return Ok(best);
+            }
+        };
+
+        let fft_pitch = 0.5 * (estimate.pitch_x + estimate.pitch_y);
+        let mut refined = params.clone();
+        let mut changed = false;
+
+        if (fft_pitch - params.spot_pitch).abs() <= HYBRID_PITCH_TOLERANCE * params.spot_pitch {
+            refined.spot_pitch = fft_pitch;
+            changed = true;
+        }
+
+        let (min_rot, max_rot) = params
+            .rotation_range
+            .iter()
+            .fold((0.0_f64, 0.0_f64), |(lo, hi), &r| (lo.min(r), hi.max(r)));
+        if estimate.rotation >= min_rot
+            && estimate.rotation <= max_rot
+            && !params.rotation_range.iter().any(|&r| (r - estimate.rotation).abs() < 1e-9)
+        {
+            refined.rotation_range.push(estimate.rotation);
+            changed = true;
+        }
+
+        if changed {
+            let candidate = match_grid_template(image, layout, &refined)?;
+            let zscore = |fit: &GridFit| {
+                fit.diagnostics.get("peak_zscore").copied().unwrap_or(f64::NEG_INFINITY)
+            };
+            if zscore(&candidate) > zscore(&best) {
+                best = candidate;
+            }
+        }
+
+        best.diagnostics.insert("fft_pitch".to_string(), fft_pitch);
+        best.diagnostics.insert("fft_rotation".to_string(), estimate.rotation);
+        best.diagnostics.insert("fft_peak_ratio".to_string(), estimate.peak_ratio);
+        Ok(best)
+    }
+}
+
+/// Re-solve a grid pose from its reference spots
+///
+/// Each reference spot of `seed` is searched within `max_position_offset_refs`
+/// pitches of its predicted position. The spot-sized disk with the highest mean
+/// intensity is taken if it is brighter than the dimmest disk of the search
+/// area by `refine_threshold`, and its intensity-weighted centroid is the
+/// located center. Center, rotation and
+/// pitch are the least-squares similarity transform from the reference lattice
+/// to the located centers; references more than `max_position_offset` pitches
+/// off the first solution are dropped and the pose solved again.
+pub fn anchor_to_references(
+    image: &ImageData,
+    layout: &[(String, bool, i32, i32)],
+    seed: &GridFit,
+    params: &GridParams,
+) -> Result<GridFit> {
+    let scale = IntensityScale::from_image(&image.data, params);
+    let normalized = scale.normalize(&image.data);
+    let min_contrast = scale.threshold(params.refine_threshold);
+
+    let spots = seed.spots(layout);
+    let search = params.max_position_offset_refs * seed.pitch;
+    let radius = 0.5 * params.spot_size * seed.pitch;
+
+    let references = spots.iter().filter(|s| s.is_reference).count();
+    let mut located: Vec<Correspondence> = spots
+        .iter()
+        .zip(lattice_coordinates(&spots))
+        .filter(|(spot, _)| spot.is_reference)
+        .filter_map(|(spot, lattice)| {
+            locate_spot(&normalized, (spot.grid_x, spot.grid_y), search, radius, min_contrast)
+                .map(|center| (lattice, center))
+        })
+        .collect();
+
+    let solve = |located: &[Correspondence]| {
+        if located.len() < MIN_ANCHOR_REFERENCES {
+            return Err(Error::GridDetectionFailed(format!(
+                "{} of {} reference spots located, at least {} are required",
+                located.len(),
+                references,
+                MIN_ANCHOR_REFERENCES
+            )));
+        }
+        similarity_pose(located).ok_or_else(|| {
+            Error::GridDetectionFailed("Reference spots do not determine a grid pose".to_string())
+        })
+    };
+
+    let mut pose = solve(&located)?;
+    let max_offset = params.max_position_offset * pose.pitch;
+    located.retain(|&(lattice, center)| pose_residual(&pose, lattice, center) <= max_offset);
+    pose = solve(&located)?;
+
+    if (pose.pitch / seed.pitch - 1.0).abs() > ANCHOR_PITCH_TOLERANCE {
+        return Err(Error::GridDetectionFailed(format!(
+            "Reference spots give pitch {:.2}, expected about {:.2}",
+            pose.pitch, seed.pitch
+        )));
+    }
+
+    let rms = (located
+        .iter()
+        .map(|&(lattice, center)| pose_residual(&pose, lattice, center).powi(2))
+        .sum::<f64>()
+        / located.len() as f64)
+        .sqrt();
+
+    let mut fit = GridFit {
+        score: seed.score,
+        diagnostics: seed.diagnostics.clone(),
+        ..pose
+    };
+    fit.diagnostics.insert("references_located".to_string(), located.len() as f64);
+    fit.diagnostics.insert("reference_rms".to_string(), rms);
+    Ok(fit)
+}
+
+/// Center of the brightest spot-sized disk within `search` pixels of `predicted`
+///
+/// `None` unless its mean exceeds that of the dimmest disk by `min_contrast`.
+fn locate_spot(
+    normalized: &Array2<f64>,
+    predicted: (f64, f64),
+    search: f64,
+    radius: f64,
+    min_contrast: f64,
+) -> Option<(f64, f64)> {
+    let (height, width) = normalized.dim();
+    let r = radius.ceil() as i64;
+    let disk: Vec<(i64, i64)> = (-r..=r)
+        .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+        .filter(|&(dx, dy)| ((dx * dx + dy * dy) as f64) <= radius * radius)
+        .collect();
+    let pixels = |x: i64, y: i64| {
+        disk.iter()
+            .map(move |&(dx, dy)| (x + dx, y + dy))
+            .filter(|&(px, py)| px >= 0 && py >= 0 && (px as usize) < width && (py as usize) < height)
+            .map(|(px, py)| (px, py, normalized[[py as usize, px as usize]]))
+    };
+
+    let (x0, y0) = (predicted.0.round() as i64, predicted.1.round() as i64);
+    let s = search.round() as i64;
+    let mut best: Option<(f64, i64, i64)> = None;
+    let mut dimmest = f64::INFINITY;
+    for oy in -s..=s {
+        for ox in -s..=s {
+            if ((ox * ox + oy * oy) as f64) > search * search {
+                continue;
+            }
+            let (x, y) = (x0 + ox, y0 + oy);
+            // Disks mostly outside the image are not spots
+            let values: Vec<f64> = pixels(x, y).map(|(_, _, v)| v).collect();
+            if values.len() * 2 < disk.len() {
+                continue;
+            }
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            dimmest = dimmest.min(mean);
+            if best.is_none_or(|(m, _, _)| mean > m) {
+                best = Some((mean, x, y));
+            }
+        }
+    }
+
+    let (mean, x, y) = best?;
+    if mean - dimmest < min_contrast {
+        return None;
+    }
+
+    // Centroid above the darkest disk pixel, so the background does not pull it to the disk center
+    let floor = pixels(x, y).map(|(_, _, v)| v).fold(f64::INFINITY, f64::min);
+    let (mut sum, mut sum_x, mut sum_y) = (0.0, 0.0, 0.0);
+    for (px, py, v) in pixels(x, y) {
+        let w = v - floor;
+        sum += w;
+        sum_x += w * px as f64;
+        sum_y += w * py as f64;
+    }
+    if sum > 0.0 {
+        Some((sum_x / sum, sum_y / sum))
+    } else {
+        Some((x as f64, y as f64))
+    }
+}
+
+/// Least-squares similarity transform from lattice coordinates to image positions
+///
+/// Solves `x = cx + a*u - b*v`, `y = cy + b*u + a*v`, i.e. the pose of
+/// `generate_grid_coordinates` with pitch `hypot(a, b)` and rotation `atan2(b, a)`.
+fn similarity_pose(points: &[Correspondence]) -> Option<GridFit> {
+    let n = points.len() as f64;
+    let (mut mu, mut mv, mut mx, mut my) = (0.0, 0.0, 0.0, 0.0);
+    for &((u, v), (x, y)) in points {
+        mu += u / n;
+        mv += v / n;
+        mx += x / n;
+        my += y / n;
+    }
+
+    let (mut spread, mut sa, mut sb) = (0.0, 0.0, 0.0);
+    for &((u, v), (x, y)) in points {
+        let (du, dv, dx, dy) = (u - mu, v - mv, x - mx, y - my);
+        spread += du * du + dv * dv;
+        sa += du * dx + dv * dy;
+        sb += du * dy - dv * dx;
+    }
+    if spread <= 0.0 {
+        return None;
+    }
+
+    let (a, b) = (sa / spread, sb / spread);
+    let center = (mx - a * mu + b * mv, my - b * mu - a * mv);
+    Some(GridFit::new(center, b.atan2(a).to_degrees(), a.hypot(b), 0.0))
+}
+
+/// Distance of `center` from the position `pose` gives lattice point `(u, v)`
+fn pose_residual(pose: &GridFit, (u, v): (f64, f64), center: (f64, f64)) -> f64 {
+    let (sin_a, cos_a) = pose.rotation.to_radians().sin_cos();
+    let x = pose.center.0 + pose.pitch * (u * cos_a - v * sin_a);
+    let y = pose.center.1 + pose.pitch * (u * sin_a + v * cos_a);
+    (x - center.0).hypot(y - center.1)
+}
+
+/// Grid midpoint nearest `target` that is consistent with a known lattice point
+///
+/// `fraction` is the fractional (row, col) lattice offset of the midpoint, 0.5
+/// for sub-grids with an even number of rows or columns. Rows map to x and
+/// columns to y as in `generate_grid_coordinates`.
+fn center_from_lattice_point(
+    lattice_point: (f64, f64),
+    fraction: (f64, f64),
+    pitch: f64,
+    rotation: f64,
+    target: (f64, f64),
+) -> (f64, f64) {
+    if pitch <= 0.0 {
+        return target;
+    }
+
+    let (sin_a, cos_a) = rotation.to_radians().sin_cos();
+    let dx = target.0 - lattice_point.0;
+    let dy = target.1 - lattice_point.1;
+
+    // Lattice units of the offset (inverse rotation)
+    let u = (dx * cos_a + dy * sin_a) / pitch;
+    let v = (-dx * sin_a + dy * cos_a) / pitch;
+
+    let u = (u - fraction.0).round() + fraction.0;
+    let v = (v - fraction.1).round() + fraction.1;
+
+    (
+        lattice_point.0 + pitch * (u * cos_a - v * sin_a),
+        lattice_point.1 + pitch * (u * sin_a + v * cos_a),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detector_for_config() {
+        assert_eq!(detector_for(GridDetectionMethod::Template).name(), "template");
+        assert_eq!(detector_for(GridDetectionMethod::FFT).name(), "fft");
+        assert_eq!(detector_for(GridDetectionMethod::Hybrid).name(), "hybrid");
+        assert_eq!(detector_for(GridDetectionMethod::Reference).name(), "template");
+    }
+
+    #[test]
+    fn test_anchor_to_references() {
+        // 3x3 regular spots and five references around them
+        let mut layout: Vec<(String, bool, i32, i32)> = (1..=3)
+            .flat_map(|row| (1..=3).map(move |col| (format!("S{}{}", row, col), false, row, col)))
+            .collect();
+        for (row, col) in [(-1, -1), (-1, -5), (-5, -1), (-5, -5), (-3, -3)] {
+            layout.push(("#REF".to_string(), true, row, col));
+        }
+
+        // Bright references at the true pose, nothing else
+        let truth = GridFit::new((100.3, 98.6), 1.5, 20.4, 0.0);
+        let references: Vec<(f64, f64)> = truth
+            .spots(&layout)
+            .iter()
+            .zip(lattice_coordinates(&truth.spots(&layout)))
+            .filter(|(spot, _)| spot.is_reference)
+            .map(|(_, lattice)| {
+                let (sin_a, cos_a) = truth.rotation.to_radians().sin_cos();
+                let (u, v) = (truth.pitch * lattice.0, truth.pitch * lattice.1);
+                (truth.center.0 + u * cos_a - v * sin_a, truth.center.1 + u * sin_a + v * cos_a)
+            })
+            .collect();
+        let data = Array2::from_shape_fn((200, 200), |(y, x)| {
+            let peak = references
+                .iter()
+                .map(|&(rx, ry)| (-((x as f64 - rx).powi(2) + (y as f64 - ry).powi(2)) / 18.0).exp())
+                .fold(0.0, f64::max);
+            (50.0 + 950.0 * peak) as u16
+        });
+        let image = ImageData::new(data, "refs".to_string());
+
+        let params = GridParams {
+            spot_pitch: 20.0,
+            ..GridParams::default()
+        };
+        let seed = GridFit::new((104.0, 96.0), 0.0, 20.0, 7.0);
+        let fit = anchor_to_references(&image, &layout, &seed, &params).unwrap();
+        assert!((fit.center.0 - 100.3).abs() < 0.2 && (fit.center.1 - 98.6).abs() < 0.2, "{:?}", fit);
+        assert!((fit.rotation - 1.5).abs() < 0.1);
+        assert!((fit.pitch - 20.4).abs() < 0.05);
+        assert_eq!(fit.diagnostics["references_located"], 5.0);
+        assert_eq!(fit.score, 7.0);
+
+        // A blank image has no references to anchor to
+        let blank = ImageData::new(Array2::from_elem((200, 200), 50u16), "blank".to_string());
+        assert!(anchor_to_references(&blank, &layout, &seed, &params).is_err());
+    }
+
+    #[test]
+    fn test_grid_edit_regenerates_positions() {
+        let layout: Vec<(String, bool, i32, i32)> = (1..=3)
+            .flat_map(|row| (1..=3).map(move |col| (format!("S{}{}", row, col), false, row, col)))
+            .collect();
+        let mut fit = GridFit::new((100.0, 100.0), 0.0, 20.0, 1.0);
+        let edit: GridEdit =
+            serde_json::from_str(r#"{"translate": [4, -2], "rotate": 90, "scalePitch": 1.5}"#).unwrap();
+        fit.apply_edit(&edit);
+        assert_eq!((fit.center, fit.rotation, fit.pitch), ((104.0, 98.0), 90.0, 30.0));
+
+        let spots = fit.spots(&layout);
+        // The middle spot stays on the center, the others turn about it at the new pitch
+        assert_eq!((spots[4].grid_x, spots[4].grid_y), (104.0, 98.0));
+        assert_eq!((spots[7].row, spots[7].col), (3, 2));
+        assert_eq!((spots[7].grid_x, spots[7].grid_y), (104.0, 128.0));
+
+        // Omitted entries leave the grid unchanged
+        let unchanged = fit.clone();
+        fit.apply_edit(&GridEdit::default());
+        assert_eq!(fit, unchanged);
+    }
+
+    #[test]
+    fn test_center_from_lattice_point() {
+        // Even-sized grid: midpoint lies half a pitch between lattice points
+        let center = center_from_lattice_point((13.0, 7.0), (0.5, 0.5), 20.0, 0.0, (100.0, 100.0));
+        assert!((center.0 - 103.0).abs() < 1e-9);
+        assert!((center.1 - 97.0).abs() < 1e-9);
+
+        // Rotated odd grid: result is an integer lattice step from the point
+        let center = center_from_lattice_point((50.0, 50.0), (0.0, 0.0), 10.0, 30.0, (72.0, 61.0));
+        let (sin_a, cos_a) = 30.0_f64.to_radians().sin_cos();
+        let du = ((center.0 - 50.0) * cos_a + (center.1 - 50.0) * sin_a) / 10.0;
+        let dv = (-(center.0 - 50.0) * sin_a + (center.1 - 50.0) * cos_a) / 10.0;
+        assert!((du - du.round()).abs() < 1e-9);
+        assert!((dv - dv.round()).abs() < 1e-9);
+    }
+}
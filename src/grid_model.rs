@@ -0,0 +1,407 @@
+use crate::config::GridModelType;
+use crate::error::{Error, Result};
+use crate::grid::lattice_coordinates;
+use crate::segmentation::calculate_tukey_weights;
+use crate::types::Spot;
+use nalgebra::{DMatrix, DVector};
+
+/// Grid geometry mapping lattice coordinates (u, v) to image positions
+///
+/// `u`/`v` are the row/column offsets from the sub-grid midpoint (see
+/// `grid::lattice_coordinates`). Coefficients apply to the basis
+/// `[1, u, v]` (rigid/affine) or `[1, u, v, u², uv, v²]` (polynomial).
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridModel {
+    pub model_type: GridModelType,
+    pub coef_x: Vec<f64>,
+    pub coef_y: Vec<f64>,
+}
+
+impl GridModel {
+    /// Rigid model equivalent to `generate_grid_coordinates(center, rotation, pitch, ..)`
+    pub fn rigid(center: (f64, f64), rotation: f64, spot_pitch: f64) -> Self {
+        let angle = rotation.to_radians();
+        let a = spot_pitch * angle.cos();
+        let b = spot_pitch * angle.sin();
+        Self {
+            model_type: GridModelType::Rigid,
+            coef_x: vec![center.0, a, -b],
+            coef_y: vec![center.1, b, a],
+        }
+    }
+
+    /// Image position of lattice coordinate (u, v)
+    pub fn predict(&self, u: f64, v: f64) -> (f64, f64) {
+        let basis = basis(self.model_type, u, v);
+        let x = basis.iter().zip(&self.coef_x).map(|(b, c)| b * c).sum();
+        let y = basis.iter().zip(&self.coef_y).map(|(b, c)| b * c).sum();
+        (x, y)
+    }
+
+    /// Grid midpoint in image coordinates
+    pub fn center(&self) -> (f64, f64) {
+        (self.coef_x[0], self.coef_y[0])
+    }
+
+    /// Pitch along the row direction at the midpoint (pixels)
+    pub fn pitch_x(&self) -> f64 {
+        self.coef_x[1].hypot(self.coef_y[1])
+    }
+
+    /// Pitch along the column direction at the midpoint (pixels)
+    pub fn pitch_y(&self) -> f64 {
+        self.coef_x[2].hypot(self.coef_y[2])
+    }
+
+    /// Rotation of the row axis in degrees (same convention as grdRotation)
+    pub fn rotation(&self) -> f64 {
+        self.coef_y[1].atan2(self.coef_x[1]).to_degrees()
+    }
+
+    /// Deviation from orthogonality between row and column axes in degrees
+    pub fn shear(&self) -> f64 {
+        let (ux, uy) = (self.coef_x[1], self.coef_y[1]);
+        let (vx, vy) = (self.coef_x[2], self.coef_y[2]);
+        let angle = (ux * vy - uy * vx).atan2(ux * vx + uy * vy).to_degrees();
+        90.0 - angle
+    }
+}
+
+/// Summary of fit residuals (Euclidean distance in pixels)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResidualStats {
+    pub count: usize,
+    pub rms: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub max: f64,
+}
+
+impl ResidualStats {
+    pub fn from_residuals(residuals: &[f64]) -> Self {
+        if residuals.is_empty() {
+            return Self::default();
+        }
+
+        let n = residuals.len() as f64;
+        let mut sorted = residuals.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let median = if sorted.len().is_multiple_of(2) {
+            (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.0
+        } else {
+            sorted[sorted.len() / 2]
+        };
+
+        Self {
+            count: residuals.len(),
+            rms: (residuals.iter().map(|r| r * r).sum::<f64>() / n).sqrt(),
+            mean: residuals.iter().sum::<f64>() / n,
+            median,
+            max: *sorted.last().unwrap(),
+        }
+    }
+}
+
+/// Result of fitting a grid model to segmented spot centers
+#[derive(Debug, Clone)]
+pub struct GridModelFit {
+    pub model: GridModel,
+    /// Residual statistics over all spots used in the fit
+    pub residuals: ResidualStats,
+    /// Residual per input spot (None if the spot was not used)
+    pub spot_residuals: Vec<Option<f64>>,
+    /// Number of spots with non-zero robust weight
+    pub inliers: usize,
+}
+
+/// Spot position was fixed by the user (grdXFixedPosition/grdYFixedPosition)
+fn has_fixed_position(spot: &Spot) -> bool {
+    spot.x_fixed != 0.0 && spot.y_fixed != 0.0
+}
+
+/// Basis functions for a model type
+fn basis(model_type: GridModelType, u: f64, v: f64) -> Vec<f64> {
+    match model_type {
+        GridModelType::Rigid | GridModelType::Affine => vec![1.0, u, v],
+        GridModelType::Polynomial => vec![1.0, u, v, u * u, u * v, v * v],
+    }
+}
+
+/// Number of free parameters of a model type
+fn parameter_count(model_type: GridModelType) -> usize {
+    match model_type {
+        GridModelType::Rigid => 4, // cx, cy, p*cos, p*sin
+        GridModelType::Affine => 6,
+        GridModelType::Polynomial => 12,
+    }
+}
+
+/// Design matrix rows (x-equation, y-equation) for one point
+fn design_rows(model_type: GridModelType, u: f64, v: f64) -> (Vec<f64>, Vec<f64>) {
+    match model_type {
+        GridModelType::Rigid => (vec![1.0, 0.0, u, -v], vec![0.0, 1.0, v, u]),
+        _ => {
+            let b = basis(model_type, u, v);
+            let k = b.len();
+            let mut row_x = vec![0.0; 2 * k];
+            let mut row_y = vec![0.0; 2 * k];
+            row_x[..k].copy_from_slice(&b);
+            row_y[k..].copy_from_slice(&b);
+            (row_x, row_y)
+        }
+    }
+}
+
+/// Convert a solved parameter vector into a model
+fn model_from_parameters(model_type: GridModelType, p: &DVector<f64>) -> GridModel {
+    match model_type {
+        GridModelType::Rigid => GridModel {
+            model_type,
+            coef_x: vec![p[0], p[2], -p[3]],
+            coef_y: vec![p[1], p[3], p[2]],
+        },
+        _ => {
+            let k = p.len() / 2;
+            GridModel {
+                model_type,
+                coef_x: p.iter().take(k).copied().collect(),
+                coef_y: p.iter().skip(k).copied().collect(),
+            }
+        }
+    }
+}
+
+/// Weighted linear least squares for the given lattice/position pairs
+fn solve_weighted(
+    model_type: GridModelType,
+    lattice: &[(f64, f64)],
+    positions: &[(f64, f64)],
+    weights: &[f64],
+) -> Option<GridModel> {
+    let n = lattice.len();
+    let k = parameter_count(model_type);
+    let mut a = DMatrix::<f64>::zeros(2 * n, k);
+    let mut b = DVector::<f64>::zeros(2 * n);
+
+    for (i, (&(u, v), &(x, y))) in lattice.iter().zip(positions).enumerate() {
+        let w = weights[i].sqrt();
+        let (row_x, row_y) = design_rows(model_type, u, v);
+        for j in 0..k {
+            a[(2 * i, j)] = w * row_x[j];
+            a[(2 * i + 1, j)] = w * row_y[j];
+        }
+        b[2 * i] = w * x;
+        b[2 * i + 1] = w * y;
+    }
+
+    let solution = a.svd(true, true).solve(&b, 1e-10).ok()?;
+    if solution.iter().any(|v| !v.is_finite()) {
+        return None;
+    }
+
+    Some(model_from_parameters(model_type, &solution))
+}
+
+/// Fit a grid model to segmented spot centers using robust least squares
+///
+/// Only spots that segmented correctly (not bad, not empty) and are not fixed
+/// are used. Outliers are down-weighted with Tukey bisquare weights
+/// (pg_seg_calc_tukey_weights) in an iteratively reweighted loop.
+pub fn fit_grid_model(spots: &[Spot], model_type: GridModelType) -> Result<GridModelFit> {
+    let lattice_all = lattice_coordinates(spots);
+
+    let used: Vec<usize> = spots
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| !(s.is_bad || s.is_empty || has_fixed_position(s)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let min_spots = parameter_count(model_type).div_ceil(2) + 2;
+    if used.len() < min_spots {
+        return Err(Error::GridDetectionFailed(format!(
+            "Too few spots to fit {:?} grid model ({}/{})",
+            model_type,
+            used.len(),
+            min_spots
+        )));
+    }
+
+    let lattice: Vec<(f64, f64)> = used.iter().map(|&i| lattice_all[i]).collect();
+    let positions: Vec<(f64, f64)> = used
+        .iter()
+        .map(|&i| (spots[i].grid_x, spots[i].grid_y))
+        .collect();
+
+    let residuals_for = |model: &GridModel| -> Vec<f64> {
+        lattice
+            .iter()
+            .zip(&positions)
+            .map(|(&(u, v), &(x, y))| {
+                let (px, py) = model.predict(u, v);
+                (px - x).hypot(py - y)
+            })
+            .collect()
+    };
+
+    let max_iter = 10;
+    let eps = 1e-3;
+
+    let mut weights = vec![1.0; used.len()];
+    let mut model = solve_weighted(model_type, &lattice, &positions, &weights)
+        .ok_or_else(|| Error::GridDetectionFailed("Singular grid model fit".to_string()))?;
+
+    for _ in 0..max_iter {
+        let residuals = residuals_for(&model);
+        if residuals.iter().all(|&r| r < 1e-9) {
+            break; // Exact fit, reweighting would be degenerate
+        }
+        weights = calculate_tukey_weights(&residuals);
+        if weights.iter().filter(|&&w| w > 0.0).count() < min_spots {
+            break;
+        }
+
+        let Some(next) = solve_weighted(model_type, &lattice, &positions, &weights) else {
+            break;
+        };
+
+        let change = next
+            .coef_x
+            .iter()
+            .chain(&next.coef_y)
+            .zip(model.coef_x.iter().chain(&model.coef_y))
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+
+        model = next;
+        if change < eps {
+            break;
+        }
+    }
+
+    let residuals = residuals_for(&model);
+    let mut spot_residuals = vec![None; spots.len()];
+    for (&i, &r) in used.iter().zip(&residuals) {
+        spot_residuals[i] = Some(r);
+    }
+
+    Ok(GridModelFit {
+        model,
+        residuals: ResidualStats::from_residuals(&residuals),
+        spot_residuals,
+        inliers: weights.iter().filter(|&&w| w > 0.0).count(),
+    })
+}
+
+/// Regenerate spot positions from a grid model
+/// Spots with a fixed position keep it (matching MATLAB's bFixedSpot handling),
+/// manually corrected spots keep their corrected position
+pub fn apply_grid_model(model: &GridModel, spots: &mut [Spot]) {
+    let lattice = lattice_coordinates(spots);
+    let rotation = model.rotation();
+
+    for (spot, (u, v)) in spots.iter_mut().zip(lattice) {
+        if spot.is_manual {
+            continue;
+        }
+        if has_fixed_position(spot) {
+            spot.grid_x = spot.x_fixed;
+            spot.grid_y = spot.y_fixed;
+            continue;
+        }
+
+        let (x, y) = model.predict(u, v);
+        spot.grid_x = x;
+        spot.grid_y = y;
+        spot.rotation = rotation;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lattice_spots(model: &GridModel) -> Vec<Spot> {
+        let mut spots = Vec::new();
+        for row in 1..=8 {
+            for col in 1..=8 {
+                let (x, y) = model.predict(row as f64 - 4.5, col as f64 - 4.5);
+                spots.push(Spot {
+                    id: format!("R{}C{}", row, col),
+                    row,
+                    col,
+                    is_reference: false,
+                    x_offset: 0.0,
+                    y_offset: 0.0,
+                    x_fixed: 0.0,
+                    y_fixed: 0.0,
+                    grid_x: x,
+                    grid_y: y,
+                    diameter: 14.0,
+                    is_manual: false,
+                    manual_source: None,
+                    is_bad: false,
+                    is_empty: false,
+                    rotation: 0.0,
+                });
+            }
+        }
+        spots
+    }
+
+    #[test]
+    fn test_rigid_model_properties() {
+        let model = GridModel::rigid((250.0, 350.0), 1.5, 21.5);
+        assert!((model.pitch_x() - 21.5).abs() < 1e-9);
+        assert!((model.pitch_y() - 21.5).abs() < 1e-9);
+        assert!((model.rotation() - 1.5).abs() < 1e-9);
+        assert!(model.shear().abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_affine_fit_recovers_pitch_and_shear() {
+        let truth = GridModel {
+            model_type: GridModelType::Affine,
+            coef_x: vec![250.0, 21.7, -0.4],
+            coef_y: vec![340.0, 0.3, 21.2],
+        };
+        let mut spots = lattice_spots(&truth);
+
+        // One grossly mis-segmented spot must not pull the fit
+        spots[10].grid_x += 9.0;
+
+        let fit = fit_grid_model(&spots, GridModelType::Affine).unwrap();
+        for (a, b) in fit.model.coef_x.iter().zip(&truth.coef_x) {
+            assert!((a - b).abs() < 1e-3, "coef_x {} vs {}", a, b);
+        }
+        for (a, b) in fit.model.coef_y.iter().zip(&truth.coef_y) {
+            assert!((a - b).abs() < 1e-3, "coef_y {} vs {}", a, b);
+        }
+        assert!(fit.residuals.median < 1e-3);
+        assert!(fit.residuals.max > 8.0);
+        assert!(fit.inliers < spots.len());
+    }
+
+    #[test]
+    fn test_polynomial_fit_and_apply() {
+        let truth = GridModel {
+            model_type: GridModelType::Polynomial,
+            coef_x: vec![250.0, 21.5, 0.0, 0.05, 0.0, 0.02],
+            coef_y: vec![340.0, 0.0, 21.5, 0.0, 0.04, 0.0],
+        };
+        let spots = lattice_spots(&truth);
+        let fit = fit_grid_model(&spots, GridModelType::Polynomial).unwrap();
+        assert!(fit.residuals.rms < 1e-6);
+
+        let mut moved = spots.clone();
+        for s in moved.iter_mut() {
+            s.grid_x = 0.0;
+            s.grid_y = 0.0;
+        }
+        apply_grid_model(&fit.model, &mut moved);
+        for (a, b) in moved.iter().zip(&spots) {
+            assert!((a.grid_x - b.grid_x).abs() < 1e-6);
+            assert!((a.grid_y - b.grid_y).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::config::GridParams;
+use crate::grid_detector::GridFit;
+use crate::types::Spot;
+
+/// Correlation peak z-score at which the peak component of the score is 0.5
+const PEAK_ZSCORE_HALF: f64 = 5.0;
+
+/// How well a detected grid matches the segmented spots
+///
+/// Blank or misloaded images still produce a best correlation; they are
+/// recognised by a weak peak, reference spots that are not found where the
+/// grid puts them, and segmented centers far from the grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridQuality {
+    /// Correlation peak z-score, if the detector reports one
+    pub peak_zscore: Option<f64>,
+    /// Fraction of reference spots segmented within `max_position_offset_refs`;
+    /// NaN without reference spots
+    pub reference_fraction: f64,
+    /// RMS distance of good segmented centers from the grid, in pitches;
+    /// NaN without good spots
+    pub residual_rms: f64,
+    /// Mean of the available components, each mapped to [0, 1]; higher is better
+    pub score: f64,
+}
+
+impl GridQuality {
+    /// Whether the score is below `params.min_grid_quality`
+    pub fn is_below(&self, params: &GridParams) -> bool {
+        self.score < params.min_grid_quality
+    }
+}
+
+/// Assess a grid from its detector fit and the spots segmented on it
+///
+/// `grid_positions` are the spot positions before segmentation, in spot
+/// order. Manually corrected spots are not counted.
+pub fn assess_grid(
+    fit: &GridFit,
+    spots: &[Spot],
+    grid_positions: &[(f64, f64)],
+    params: &GridParams,
+) -> GridQuality {
+    let peak_zscore = fit.diagnostics.get("peak_zscore").copied();
+
+    let mut references = 0usize;
+    let mut references_found = 0usize;
+    let mut squared_offsets = Vec::new();
+    for (spot, &(gx, gy)) in spots.iter().zip(grid_positions) {
+        if spot.is_manual {
+            continue;
+        }
+        let offset = ((spot.grid_x - gx).powi(2) + (spot.grid_y - gy).powi(2)).sqrt()
+            / params.spot_pitch;
+        let good = !spot.is_bad && !spot.is_empty;
+        if spot.is_reference {
+            references += 1;
+            references_found += (good && offset <= params.max_position_offset_refs) as usize;
+        }
+        if good {
+            squared_offsets.push(offset * offset);
+        }
+    }
+
+    let reference_fraction = if references == 0 {
+        f64::NAN
+    } else {
+        references_found as f64 / references as f64
+    };
+    let residual_rms = if squared_offsets.is_empty() {
+        f64::NAN
+    } else {
+        (squared_offsets.iter().sum::<f64>() / squared_offsets.len() as f64).sqrt()
+    };
+
+    let mut components = Vec::new();
+    if let Some(z) = peak_zscore {
+        components.push(z.max(0.0) / (z.max(0.0) + PEAK_ZSCORE_HALF));
+    }
+    if !reference_fraction.is_nan() {
+        components.push(reference_fraction);
+    }
+    // No good spot at all is the worst case, not a missing component
+    components.push(if residual_rms.is_nan() {
+        0.0
+    } else {
+        (1.0 - residual_rms / params.max_position_offset).clamp(0.0, 1.0)
+    });
+    let score = components.iter().sum::<f64>() / components.len() as f64;
+
+    GridQuality {
+        peak_zscore,
+        reference_fraction,
+        residual_rms,
+        score,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spot(is_reference: bool, x: f64, is_bad: bool) -> Spot {
+        Spot {
+            id: if is_reference { "#REF" } else { "A" }.to_string(),
+            row: 1,
+            col: 1,
+            is_reference,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            x_fixed: 0.0,
+            y_fixed: 0.0,
+            grid_x: x,
+            grid_y: 0.0,
+            diameter: 12.0,
+            is_manual: false,
+            manual_source: None,
+            is_bad,
+            is_empty: false,
+            rotation: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_quality_components() {
+        let params = GridParams {
+            spot_pitch: 20.0,
+            ..GridParams::default()
+        };
+        let mut fit = GridFit::new((0.0, 0.0), 0.0, 20.0, 1.0);
+        fit.diagnostics.insert("peak_zscore".to_string(), 15.0);
+        let positions = [(0.0, 0.0); 4];
+
+        // Two of three references found; the regular spot is 0.1 pitch off
+        let spots = [
+            spot(true, 0.0, false),
+            spot(true, 0.0, true),
+            spot(true, 0.0, false),
+            spot(false, 2.0, false),
+        ];
+        let quality = assess_grid(&fit, &spots, &positions, &params);
+        assert!((quality.reference_fraction - 2.0 / 3.0).abs() < 1e-12);
+        assert!((quality.residual_rms - (0.01 / 3.0f64).sqrt()).abs() < 1e-12);
+        assert!(!quality.is_below(&params));
+
+        // Blank image: weak peak, nothing segmented
+        fit.diagnostics.insert("peak_zscore".to_string(), 1.0);
+        let blank = [spot(true, 0.0, true), spot(false, 0.0, true)];
+        let quality = assess_grid(&fit, &blank, &positions, &params);
+        assert_eq!(quality.reference_fraction, 0.0);
+        assert!(quality.residual_rms.is_nan());
+        assert!(quality.is_below(&params));
+    }
+}
@@ -1,4 +1,6 @@
+use crate::config::{GridParams, IntensityNormalization, IntensityThreshold};
 use crate::error::{Error, Result};
+use crate::fft::{FftContext, ImageSpectrum};
 use crate::types::ImageData;
 use ndarray::{Array2, s};
 
@@ -35,37 +37,13 @@ pub fn rescale_image(image: &ImageData, scale_factor: f64) -> Result<ImageData>
     Ok(ImageData::new(scaled, image.name.clone()))
 }
 
-/// Preprocess images: rescale and combine exposures
+/// Preprocess images: select the `grdUseImage` image and rescale it
 pub fn preprocess_images(images: &[ImageData], rescale_factor: Option<f64>, use_image: &str) -> Result<ImageData> {
-    if images.is_empty() {
-        return Err(Error::InvalidParameter("No images provided".to_string()));
-    }
-
-    eprintln!("DEBUG: preprocess_images called with {} images, use_image={}", images.len(), use_image);
     tracing::info!("preprocess_images: {} images, use_image={}", images.len(), use_image);
 
-    // Select image based on configuration
-    let base_image = if images.len() == 1 {
-        tracing::info!("Only 1 image, using: {}", images[0].name);
-        images[0].clone()
-    } else {
-        // Select image according to use_image parameter
-        let selected = match use_image.to_lowercase().as_str() {
-            "first" => {
-                tracing::info!("Selecting FIRST image for grid detection: {}", images[0].name);
-                images[0].clone()
-            },
-            "last" => {
-                tracing::info!("Selecting LAST image for grid detection: {}", images.last().unwrap().name);
-                images.last().unwrap().clone()
-            },
-            "average" | "median" | _ => {
-                tracing::info!("Selecting FIRST image (default) for grid detection: {}", images[0].name);
-                images[0].clone()
-            }
-        };
-        selected
-    };
+    let base_image = select_grid_image(images, use_image)
+        .ok_or_else(|| Error::InvalidParameter("No images provided".to_string()))?
+        .clone();
 
     // Apply rescaling if specified
     if let Some(scale) = rescale_factor {
@@ -75,6 +53,27 @@ pub fn preprocess_images(images: &[ImageData], rescale_factor: Option<f64>, use_
     }
 }
 
+/// The image `preprocess_images` builds the grid image from
+///
+/// "Last" selects the last image; everything else, including the MATLAB
+/// options FirstLast, All and `<exposure>_<cycle>`, selects the first.
+///
+/// MATLAB (`pg_grd_preprocess_images`) instead takes every image of the
+/// first or last cycle and merges their exposures (`pg_combine_exposures`),
+/// using the ExposureTime and Cycle TIFF tags. Those tags are not read
+/// here, so a single image is used and `grdImageNameUsed` names only that
+/// image, where MATLAB lists all the combined ones.
+pub fn select_grid_image<'a>(images: &'a [ImageData], use_image: &str) -> Option<&'a ImageData> {
+    let selected = match use_image.to_lowercase().as_str() {
+        "last" => images.last(),
+        _ => images.first(),
+    };
+    if let Some(image) = selected {
+        tracing::info!("Selecting {} for grid detection (grdUseImage={})", image.name, use_image);
+    }
+    selected
+}
+
 /// Normalize image intensity to 0-1 range
 pub fn normalize_image(data: &Array2<u16>) -> Array2<f64> {
     let max_val = *data.iter().max().unwrap_or(&1) as f64;
@@ -85,41 +84,126 @@ pub fn normalize_image(data: &Array2<u16>) -> Array2<f64> {
     data.mapv(|x| x as f64 / max_val)
 }
 
-/// Apply Gaussian smoothing (simplified)
+/// Raw-count percentile (0-100) of a 16-bit image using a histogram
+pub fn intensity_percentile(data: &Array2<u16>, percentile: f64) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let mut histogram = vec![0usize; u16::MAX as usize + 1];
+    for &v in data.iter() {
+        histogram[v as usize] += 1;
+    }
+
+    let rank = ((percentile.clamp(0.0, 100.0) / 100.0) * (data.len() - 1) as f64).round() as usize;
+    let mut seen = 0;
+    for (value, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen > rank {
+            return value as f64;
+        }
+    }
+
+    u16::MAX as f64
+}
+
+/// Mapping between raw sensor counts and normalized intensities
+///
+/// `reference` is the raw count that maps to 1.0. Raw data is never modified, so
+/// thresholds can be given either relative to the reference or in counts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntensityScale {
+    pub reference: f64,
+}
+
+impl IntensityScale {
+    /// Choose the reference according to `params.intensity_normalization`
+    pub fn from_image(data: &Array2<u16>, params: &GridParams) -> Self {
+        let reference = match params.intensity_normalization {
+            IntensityNormalization::Max => data.iter().copied().max().unwrap_or(0) as f64,
+            IntensityNormalization::BitDepth => params.sensor_max(),
+            IntensityNormalization::Percentile => {
+                intensity_percentile(data, params.normalization_percentile)
+            }
+        };
+        Self { reference }
+    }
+
+    /// Normalized intensities; values above the reference are kept (> 1.0)
+    pub fn normalize(&self, data: &Array2<u16>) -> Array2<f64> {
+        if self.reference <= 0.0 {
+            return Array2::zeros(data.dim());
+        }
+        let scale = 1.0 / self.reference;
+        data.mapv(|x| x as f64 * scale)
+    }
+
+    /// Threshold in normalized units
+    pub fn threshold(&self, threshold: IntensityThreshold) -> f64 {
+        threshold.normalized(self.reference)
+    }
+}
+
+/// Normalize an image according to the intensity parameters
+pub fn normalize_intensity(data: &Array2<u16>, params: &GridParams) -> Array2<f64> {
+    IntensityScale::from_image(data, params).normalize(data)
+}
+
+/// Normalized 1D Gaussian kernel with radius ceil(3 * sigma)
+pub fn gaussian_kernel(sigma: f64) -> Vec<f64> {
+    if sigma <= 0.0 {
+        return vec![1.0];
+    }
+
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.into_iter().map(|k| k / sum).collect()
+}
+
+/// Apply separable Gaussian smoothing with the given sigma
+/// Borders are handled by replicating edge pixels (MATLAB imfilter 'replicate')
 pub fn gaussian_blur(data: &Array2<f64>, sigma: f64) -> Array2<f64> {
-    // Simplified box blur for now
-    let kernel_size = (sigma * 3.0).ceil() as usize;
-    let kernel_size = kernel_size.max(1);
+    let kernel = gaussian_kernel(sigma);
+    if kernel.len() == 1 {
+        return data.clone();
+    }
 
+    let radius = (kernel.len() / 2) as isize;
     let (height, width) = data.dim();
-    let mut result = data.clone();
+    if height == 0 || width == 0 {
+        return data.clone();
+    }
 
     // Horizontal pass
+    let mut horizontal = Array2::zeros((height, width));
     for y in 0..height {
         for x in 0..width {
-            let x_start = x.saturating_sub(kernel_size);
-            let x_end = (x + kernel_size + 1).min(width);
-
-            let sum: f64 = (x_start..x_end).map(|i| data[[y, i]]).sum();
-            let count = (x_end - x_start) as f64;
-            result[[y, x]] = sum / count;
+            let mut acc = 0.0;
+            for (k, w) in kernel.iter().enumerate() {
+                let xi = (x as isize + k as isize - radius).clamp(0, width as isize - 1) as usize;
+                acc += w * data[[y, xi]];
+            }
+            horizontal[[y, x]] = acc;
         }
     }
 
     // Vertical pass
-    let mut final_result = result.clone();
-    for x in 0..width {
-        for y in 0..height {
-            let y_start = y.saturating_sub(kernel_size);
-            let y_end = (y + kernel_size + 1).min(height);
-
-            let sum: f64 = (y_start..y_end).map(|i| result[[i, x]]).sum();
-            let count = (y_end - y_start) as f64;
-            final_result[[y, x]] = sum / count;
+    let mut result = Array2::zeros((height, width));
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = 0.0;
+            for (k, w) in kernel.iter().enumerate() {
+                let yi = (y as isize + k as isize - radius).clamp(0, height as isize - 1) as usize;
+                acc += w * horizontal[[yi, x]];
+            }
+            result[[y, x]] = acc;
         }
     }
 
-    final_result
+    result
 }
 
 /// Compute image gradient magnitude using Sobel operator
@@ -174,80 +258,140 @@ fn create_disk_structuring_element(radius: usize) -> Array2<bool> {
     disk
 }
 
-/// Morphological erosion operation
-/// Replaces each pixel with the minimum value in its neighborhood defined by structuring element
-pub fn erode(image: &Array2<f64>, structuring_element: &Array2<bool>) -> Array2<f64> {
-    let (img_h, img_w) = image.dim();
-    let (se_h, se_w) = structuring_element.dim();
-    let se_h_half = se_h / 2;
-    let se_w_half = se_w / 2;
-
-    let mut result = Array2::zeros((img_h, img_w));
-
-    for i in 0..img_h {
-        for j in 0..img_w {
-            let mut min_val = f64::MAX;
-
-            // Apply structuring element
-            for si in 0..se_h {
-                for sj in 0..se_w {
-                    if structuring_element[[si, sj]] {
-                        let ii = i as isize + si as isize - se_h_half as isize;
-                        let jj = j as isize + sj as isize - se_w_half as isize;
-
-                        if ii >= 0 && ii < img_h as isize && jj >= 0 && jj < img_w as isize {
-                            let val = image[[ii as usize, jj as usize]];
-                            if val < min_val {
-                                min_val = val;
-                            }
-                        }
-                    }
-                }
-            }
+/// Sliding-window extreme over `k` samples using the van Herk/Gil-Werman algorithm
+///
+/// Returns `out[i] = op(line[i], ..., line[i + k - 1])` for every `i`, treating
+/// samples past the end as `identity`. Runs in O(n) regardless of `k`.
+fn van_herk(line: &[f64], k: usize, identity: f64, op: fn(f64, f64) -> f64) -> Vec<f64> {
+    let n = line.len();
+    if k <= 1 {
+        return line.to_vec();
+    }
 
-            result[[i, j]] = if min_val == f64::MAX { image[[i, j]] } else { min_val };
+    // Pad to a whole number of blocks plus one window
+    let padded_len = (n + k).div_ceil(k) * k;
+    let mut padded = vec![identity; padded_len];
+    padded[..n].copy_from_slice(line);
+
+    // Forward (prefix) and backward (suffix) extremes within each block
+    let mut prefix = padded.clone();
+    let mut suffix = padded.clone();
+    for block in (0..padded_len).step_by(k) {
+        for i in block + 1..block + k {
+            prefix[i] = op(prefix[i - 1], padded[i]);
+        }
+        for i in (block..block + k - 1).rev() {
+            suffix[i] = op(suffix[i + 1], padded[i]);
         }
     }
-    result
+
+    (0..n).map(|i| op(suffix[i], prefix[i + k - 1])).collect()
 }
 
-/// Morphological dilation operation
-/// Replaces each pixel with the maximum value in its neighborhood defined by structuring element
-pub fn dilate(image: &Array2<f64>, structuring_element: &Array2<bool>) -> Array2<f64> {
-    let (img_h, img_w) = image.dim();
+/// Horizontal runs of a structuring element: (row offset, column offset, length)
+fn structuring_element_runs(structuring_element: &Array2<bool>) -> Vec<(isize, isize, usize)> {
     let (se_h, se_w) = structuring_element.dim();
-    let se_h_half = se_h / 2;
-    let se_w_half = se_w / 2;
-
-    let mut result = Array2::zeros((img_h, img_w));
-
-    for i in 0..img_h {
-        for j in 0..img_w {
-            let mut max_val = f64::MIN;
-
-            // Apply structuring element
-            for si in 0..se_h {
-                for sj in 0..se_w {
-                    if structuring_element[[si, sj]] {
-                        let ii = i as isize + si as isize - se_h_half as isize;
-                        let jj = j as isize + sj as isize - se_w_half as isize;
-
-                        if ii >= 0 && ii < img_h as isize && jj >= 0 && jj < img_w as isize {
-                            let val = image[[ii as usize, jj as usize]];
-                            if val > max_val {
-                                max_val = val;
-                            }
-                        }
-                    }
-                }
+    let se_h_half = (se_h / 2) as isize;
+    let se_w_half = (se_w / 2) as isize;
+    let mut runs = Vec::new();
+
+    for si in 0..se_h {
+        let mut sj = 0;
+        while sj < se_w {
+            if !structuring_element[[si, sj]] {
+                sj += 1;
+                continue;
+            }
+            let start = sj;
+            while sj < se_w && structuring_element[[si, sj]] {
+                sj += 1;
             }
+            runs.push((si as isize - se_h_half, start as isize - se_w_half, sj - start));
+        }
+    }
+
+    runs
+}
+
+/// Grey-scale morphology by run-length decomposition of the structuring element
+///
+/// Each row of the element is split into horizontal runs; the extreme over a
+/// run is a 1D sliding window (van Herk/Gil-Werman) and the result is the
+/// extreme over all runs. Pixels outside the image are ignored, so a pixel
+/// with no neighbours inside the image keeps its own value.
+fn morphology(
+    image: &Array2<f64>,
+    structuring_element: &Array2<bool>,
+    identity: f64,
+    op: fn(f64, f64) -> f64,
+) -> Array2<f64> {
+    let (img_h, img_w) = image.dim();
+    let runs = structuring_element_runs(structuring_element);
+    if runs.is_empty() || img_h == 0 || img_w == 0 {
+        return image.clone();
+    }
 
-            result[[i, j]] = if max_val == f64::MIN { image[[i, j]] } else { max_val };
+    let pad = structuring_element.ncols() as isize;
+
+    // Sliding extremes per distinct run length, on rows padded by `pad` at both ends
+    let mut lengths: Vec<usize> = runs.iter().map(|r| r.2).collect();
+    lengths.sort_unstable();
+    lengths.dedup();
+
+    let padded_w = img_w + 2 * pad as usize;
+    let mut windows = std::collections::HashMap::new();
+    for &len in &lengths {
+        let mut table = Array2::from_elem((img_h, padded_w), identity);
+        let mut line = vec![identity; padded_w];
+        for y in 0..img_h {
+            line[pad as usize..pad as usize + img_w]
+                .iter_mut()
+                .zip(image.row(y))
+                .for_each(|(l, &v)| *l = v);
+            let extremes = van_herk(&line, len, identity, op);
+            table.row_mut(y).iter_mut().zip(extremes).for_each(|(t, v)| *t = v);
         }
+        windows.insert(len, table);
     }
+
+    let mut result = Array2::from_elem((img_h, img_w), identity);
+    for &(dy, dx, len) in &runs {
+        let table = &windows[&len];
+        for y in 0..img_h {
+            let yy = y as isize + dy;
+            if yy < 0 || yy >= img_h as isize {
+                continue;
+            }
+            for x in 0..img_w {
+                let col = x as isize + dx + pad;
+                let v = table[[yy as usize, col as usize]];
+                result[[y, x]] = op(result[[y, x]], v);
+            }
+        }
+    }
+
+    // No in-bounds neighbour: keep the original value
+    result.zip_mut_with(image, |r, &orig| {
+        if *r == identity {
+            *r = orig;
+        }
+    });
+
     result
 }
 
+/// Morphological erosion operation
+/// Replaces each pixel with the minimum value in its neighborhood defined by structuring element
+pub fn erode(image: &Array2<f64>, structuring_element: &Array2<bool>) -> Array2<f64> {
+    morphology(image, structuring_element, f64::INFINITY, f64::min)
+}
+
+/// Morphological dilation operation
+/// Replaces each pixel with the maximum value in its neighborhood defined by structuring element
+pub fn dilate(image: &Array2<f64>, structuring_element: &Array2<bool>) -> Array2<f64> {
+    morphology(image, structuring_element, f64::NEG_INFINITY, f64::max)
+}
+
 /// Morphological opening operation (erosion followed by dilation)
 /// Removes small bright features while preserving the overall shape
 pub fn morphological_opening(image: &Array2<f64>, radius: usize) -> Array2<f64> {
@@ -256,40 +400,134 @@ pub fn morphological_opening(image: &Array2<f64>, radius: usize) -> Array2<f64>
     dilate(&eroded, &se)
 }
 
-/// Compute 2D cross-correlation using FFT (simplified version)
+/// Morphological top-hat (image minus its opening)
+/// Removes background and halo structures larger than the disk
+pub fn top_hat(image: &Array2<f64>, radius: usize) -> Array2<f64> {
+    let opened = morphological_opening(image, radius);
+    image - &opened
+}
+
+/// Grid image preprocessing matching MATLAB's pg_pp_fun
+///
+/// An opening with a small disk removes small artifacts, then a top-hat with a
+/// large disk subtracts background. Radii are in pixels
+/// (`round(prpSmallDisk * grdSpotPitch)`, `round(prpLargeDisk * grdSpotPitch)`);
+/// a radius of 0 skips the step.
+pub fn preprocess_grid_image(image: &Array2<f64>, small_disk: usize, large_disk: usize) -> Array2<f64> {
+    let mut result = if small_disk > 0 {
+        morphological_opening(image, small_disk)
+    } else {
+        image.clone()
+    };
+
+    if large_disk > 0 {
+        result = top_hat(&result, large_disk);
+    }
+
+    result
+}
+
+/// Normalization applied by `cross_correlate_with`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CorrelationNormalization {
+    /// Raw correlation sum
+    None,
+    /// Divide by the L2 norms of the image window and template (cosine similarity)
+    #[default]
+    Energy,
+    /// Zero-mean normalized cross-correlation in [-1, 1]
+    Zncc,
+}
+
+/// Summed-area table with a zero first row/column
+fn integral_image(data: &Array2<f64>) -> Array2<f64> {
+    let (height, width) = data.dim();
+    let mut table = Array2::zeros((height + 1, width + 1));
+    for y in 0..height {
+        let mut row_sum = 0.0;
+        for x in 0..width {
+            row_sum += data[[y, x]];
+            table[[y + 1, x + 1]] = table[[y, x + 1]] + row_sum;
+        }
+    }
+    table
+}
+
+/// Sum over the window [y, y + h) x [x, x + w) from a summed-area table
+fn window_sum(table: &Array2<f64>, y: usize, x: usize, h: usize, w: usize) -> f64 {
+    table[[y + h, x + w]] - table[[y, x + w]] - table[[y + h, x]] + table[[y, x]]
+}
+
+/// Compute normalized 2D cross-correlation ("valid" region)
+///
+/// Output has shape `(img_h - tmpl_h + 1, img_w - tmpl_w + 1)`; element `(y, x)`
+/// correlates the template with the image window whose top-left corner is `(y, x)`.
 pub fn cross_correlate(image: &Array2<f64>, template: &Array2<f64>) -> Array2<f64> {
+    cross_correlate_with(image, template, CorrelationNormalization::Energy)
+}
+
+/// FFT-based 2D cross-correlation with the requested normalization
+///
+/// The raw correlation is computed in the frequency domain and window
+/// statistics come from summed-area tables, so the cost is O(N log N)
+/// independent of the template size. Returns an empty array if the template
+/// is larger than the image.
+pub fn cross_correlate_with(
+    image: &Array2<f64>,
+    template: &Array2<f64>,
+    normalization: CorrelationNormalization,
+) -> Array2<f64> {
     let (img_h, img_w) = image.dim();
     let (tmpl_h, tmpl_w) = template.dim();
 
+    if tmpl_h == 0 || tmpl_w == 0 || tmpl_h > img_h || tmpl_w > img_w {
+        return Array2::zeros((0, 0));
+    }
+
     let result_h = img_h - tmpl_h + 1;
     let result_w = img_w - tmpl_w + 1;
+    let n = (tmpl_h * tmpl_w) as f64;
 
-    let mut result = Array2::zeros((result_h, result_w));
-
-    // Direct correlation (simplified - FFT version would be much faster)
-    for y in 0..result_h {
-        for x in 0..result_w {
-            let mut sum = 0.0;
-            let mut img_sum = 0.0;
-            let mut tmpl_sum = 0.0;
-
-            for ty in 0..tmpl_h {
-                for tx in 0..tmpl_w {
-                    let img_val = image[[y + ty, x + tx]];
-                    let tmpl_val = template[[ty, tx]];
-                    sum += img_val * tmpl_val;
-                    img_sum += img_val * img_val;
-                    tmpl_sum += tmpl_val * tmpl_val;
-                }
+    // Zero-mean template for ZNCC: sum(I * (T - mean_T)) = n * cov(I, T)
+    let tmpl_mean = template.mean().unwrap_or(0.0);
+    let template = match normalization {
+        CorrelationNormalization::Zncc => template.mapv(|v| v - tmpl_mean),
+        _ => template.clone(),
+    };
+
+    // Circular correlation with the zero-padded template; the valid region never wraps
+    let mut padded = Array2::zeros((img_h, img_w));
+    padded.slice_mut(s![..tmpl_h, ..tmpl_w]).assign(&template);
+
+    let ctx = FftContext::shared();
+    let raw = ImageSpectrum::new(ctx, image).correlate(ctx, &padded);
+
+    let tmpl_energy: f64 = template.iter().map(|v| v * v).sum();
+    let sum_sq = integral_image(&image.mapv(|v| v * v));
+    let sum = integral_image(image);
+
+    Array2::from_shape_fn((result_h, result_w), |(y, x)| {
+        let numerator = raw[[y, x]];
+        let denom = match normalization {
+            CorrelationNormalization::None => return numerator,
+            CorrelationNormalization::Energy => {
+                (window_sum(&sum_sq, y, x, tmpl_h, tmpl_w) * tmpl_energy).sqrt()
+            }
+            CorrelationNormalization::Zncc => {
+                let s1 = window_sum(&sum, y, x, tmpl_h, tmpl_w);
+                let s2 = window_sum(&sum_sq, y, x, tmpl_h, tmpl_w);
+                let image_var = (s2 - s1 * s1 / n).max(0.0);
+                (image_var * tmpl_energy).sqrt()
             }
+        };
 
-            // Normalized cross-correlation
-            let denom = (img_sum * tmpl_sum).sqrt();
-            result[[y, x]] = if denom > 0.0 { sum / denom } else { 0.0 };
+        // Guard against round-off in flat windows
+        if denom > 1e-12 * n.max(1.0) {
+            numerator / denom
+        } else {
+            0.0
         }
-    }
-
-    result
+    })
 }
 
 /// Find maximum value and its position in 2D array
@@ -311,6 +549,192 @@ pub fn find_max_2d(data: &Array2<f64>) -> Option<(f64, (usize, usize))> {
     }
 }
 
+/// Rigid offset of an image relative to a reference (grid) image
+///
+/// A point at `(x, y)` in the reference image (x = column, y = row) is found at
+/// `R(rotation) * ((x, y) - center) + center + (dx, dy)` in the moving image,
+/// where `center` is the image center.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Registration {
+    pub dx: f64,
+    pub dy: f64,
+    /// Rotation in degrees
+    pub rotation: f64,
+    /// Phase correlation peak height (1.0 = perfect match)
+    pub peak: f64,
+}
+
+impl Registration {
+    /// Map a reference image position into the moving image
+    pub fn apply(&self, x: f64, y: f64, center: (f64, f64)) -> (f64, f64) {
+        let angle = self.rotation.to_radians();
+        let (sin_a, cos_a) = angle.sin_cos();
+        let rx = x - center.0;
+        let ry = y - center.1;
+        (
+            center.0 + rx * cos_a - ry * sin_a + self.dx,
+            center.1 + rx * sin_a + ry * cos_a + self.dy,
+        )
+    }
+}
+
+/// Hann-windowed, zero-mean copy of an image to suppress FFT edge effects
+fn apodize(data: &Array2<f64>) -> Array2<f64> {
+    let (height, width) = data.dim();
+    let mean = data.mean().unwrap_or(0.0);
+    let hann = |i: usize, n: usize| {
+        if n <= 1 {
+            1.0
+        } else {
+            0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (n - 1) as f64).cos()
+        }
+    };
+
+    Array2::from_shape_fn((height, width), |(y, x)| {
+        (data[[y, x]] - mean) * hann(y, height) * hann(x, width)
+    })
+}
+
+/// Sub-pixel offset of a peak from a 3-point parabola fit
+fn parabolic_offset(left: f64, center: f64, right: f64) -> f64 {
+    let denom = left - 2.0 * center + right;
+    if denom.abs() < 1e-12 {
+        0.0
+    } else {
+        (0.5 * (left - right) / denom).clamp(-0.5, 0.5)
+    }
+}
+
+/// Estimate translation of `moving` relative to `reference` by phase correlation
+///
+/// Returns `(dx, dy, peak)` with sub-pixel precision; shifts wrap to
+/// `[-size/2, size/2)`.
+pub fn phase_correlation(reference: &Array2<f64>, moving: &Array2<f64>) -> Result<(f64, f64, f64)> {
+    if reference.dim() != moving.dim() {
+        return Err(Error::InvalidDimensions {
+            expected: format!("{:?}", reference.dim()),
+            actual: format!("{:?}", moving.dim()),
+        });
+    }
+
+    let (height, width) = reference.dim();
+    let ctx = FftContext::shared();
+    let f_ref = ctx.fft2(&apodize(reference));
+    let f_mov = ctx.fft2(&apodize(moving));
+
+    // Normalized cross-power spectrum
+    let cross = Array2::from_shape_fn((height, width), |(i, j)| {
+        let c = f_mov[[i, j]] * f_ref[[i, j]].conj();
+        let norm = c.norm();
+        if norm > 1e-12 { c / norm } else { c * 0.0 }
+    });
+    let surface = ctx.ifft2(&cross);
+
+    let (peak, (py, px)) = find_max_2d(&surface)
+        .ok_or_else(|| Error::ProcessingError("Empty correlation surface".to_string()))?;
+
+    let at = |y: isize, x: isize| {
+        surface[[y.rem_euclid(height as isize) as usize, x.rem_euclid(width as isize) as usize]]
+    };
+    let (py_i, px_i) = (py as isize, px as isize);
+    let sub_x = parabolic_offset(at(py_i, px_i - 1), peak, at(py_i, px_i + 1));
+    let sub_y = parabolic_offset(at(py_i - 1, px_i), peak, at(py_i + 1, px_i));
+
+    let wrap = |p: usize, n: usize| if p >= n.div_ceil(2) { p as f64 - n as f64 } else { p as f64 };
+
+    Ok((wrap(px, width) + sub_x, wrap(py, height) + sub_y, peak))
+}
+
+/// Rotate an image about its center (bilinear interpolation, zero fill)
+pub fn rotate_image(data: &Array2<f64>, angle_deg: f64) -> Array2<f64> {
+    let (height, width) = data.dim();
+    let (sin_a, cos_a) = angle_deg.to_radians().sin_cos();
+    let cx = (width as f64 - 1.0) / 2.0;
+    let cy = (height as f64 - 1.0) / 2.0;
+
+    Array2::from_shape_fn((height, width), |(y, x)| {
+        // Inverse mapping: sample the source at R(-angle) * (p - c) + c
+        let rx = x as f64 - cx;
+        let ry = y as f64 - cy;
+        let sx = cx + rx * cos_a + ry * sin_a;
+        let sy = cy - rx * sin_a + ry * cos_a;
+        bilinear_sample(data, sx, sy)
+    })
+}
+
+/// Bilinear sample at a fractional position, 0 outside the image
+fn bilinear_sample(data: &Array2<f64>, x: f64, y: f64) -> f64 {
+    let (height, width) = data.dim();
+    if x < 0.0 || y < 0.0 || x > (width - 1) as f64 || y > (height - 1) as f64 {
+        return 0.0;
+    }
+
+    let x0 = x.floor() as usize;
+    let y0 = y.floor() as usize;
+    let x1 = (x0 + 1).min(width - 1);
+    let y1 = (y0 + 1).min(height - 1);
+    let fx = x - x0 as f64;
+    let fy = y - y0 as f64;
+
+    let top = data[[y0, x0]] * (1.0 - fx) + data[[y0, x1]] * fx;
+    let bottom = data[[y1, x0]] * (1.0 - fx) + data[[y1, x1]] * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Register `moving` against `reference` (translation and small rotation)
+///
+/// Rotation is searched over `-max_rotation..=max_rotation` in steps of
+/// `rotation_step` degrees; with `max_rotation == 0` only translation is estimated.
+pub fn register_image(
+    reference: &ImageData,
+    moving: &ImageData,
+    max_rotation: f64,
+    rotation_step: f64,
+) -> Result<Registration> {
+    let reference_data = normalize_image(&reference.data);
+    let moving_data = normalize_image(&moving.data);
+
+    let mut angles = vec![0.0];
+    if max_rotation > 0.0 && rotation_step > 0.0 {
+        let steps = (max_rotation / rotation_step).floor() as i32;
+        angles = (-steps..=steps).map(|k| k as f64 * rotation_step).collect();
+    }
+
+    let mut best: Option<Registration> = None;
+    for &angle in &angles {
+        let derotated = if angle == 0.0 {
+            moving_data.clone()
+        } else {
+            rotate_image(&moving_data, -angle)
+        };
+        let (sx, sy, peak) = phase_correlation(&reference_data, &derotated)?;
+
+        if best.is_none_or(|b| peak > b.peak) {
+            // The shift was measured in the de-rotated frame: d = R(angle) * d'
+            let (sin_a, cos_a) = angle.to_radians().sin_cos();
+            best = Some(Registration {
+                dx: sx * cos_a - sy * sin_a,
+                dy: sx * sin_a + sy * cos_a,
+                rotation: angle,
+                peak,
+            });
+        }
+    }
+
+    let registration = best.unwrap_or_default();
+    tracing::debug!(
+        "Registered {} to {}: dx={:.2}, dy={:.2}, rotation={:.2}°, peak={:.3}",
+        moving.name,
+        reference.name,
+        registration.dx,
+        registration.dy,
+        registration.rotation,
+        registration.peak
+    );
+
+    Ok(registration)
+}
+
 /// Canny edge detection with hysteresis thresholding
 /// Implements the complete Canny algorithm matching MATLAB's edge() function
 ///
@@ -535,6 +959,41 @@ mod tests {
         assert!((normalized[[1, 1]] - 1.0).abs() < 1e-6);
     }
 
+    #[test]
+    fn test_grid_image_selection() {
+        let images: Vec<ImageData> = ["a", "b", "c"]
+            .iter()
+            .map(|name| ImageData::new(Array2::zeros((2, 2)), name.to_string()))
+            .collect();
+        let name = |use_image: &str| select_grid_image(&images, use_image).map(|i| i.name.as_str());
+        assert_eq!(name("Last"), Some("c"));
+        assert_eq!(name("First"), Some("a"));
+        assert_eq!(name("FirstLast"), Some("a"));
+        assert_eq!(select_grid_image(&[], "Last").map(|i| i.name.as_str()), None);
+        assert_eq!(preprocess_images(&images, None, "last").unwrap().name, "c");
+    }
+
+    #[test]
+    fn test_intensity_scale_modes() {
+        // One saturated hot pixel among dim background
+        let mut data = Array2::from_elem((10, 10), 400u16);
+        data[[0, 0]] = 4095;
+
+        let mut params = GridParams::default();
+        assert_eq!(IntensityScale::from_image(&data, &params).reference, 4095.0);
+
+        params.intensity_normalization = IntensityNormalization::Percentile;
+        params.normalization_percentile = 95.0;
+        let scale = IntensityScale::from_image(&data, &params);
+        assert_eq!(scale.reference, 400.0);
+        assert!((scale.normalize(&data)[[5, 5]] - 1.0).abs() < 1e-12);
+        assert!((scale.threshold(IntensityThreshold::Absolute(40.0)) - 0.1).abs() < 1e-12);
+
+        params.intensity_normalization = IntensityNormalization::BitDepth;
+        params.bit_depth = 16;
+        assert_eq!(IntensityScale::from_image(&data, &params).reference, 65535.0);
+    }
+
     #[test]
     fn test_threshold() {
         let data = arr2(&[[0.1, 0.5], [0.7, 0.9]]);
@@ -546,6 +1005,133 @@ mod tests {
         assert!(thresholded[[1, 1]]);
     }
 
+    fn blob_image(height: usize, width: usize, centers: &[(f64, f64)]) -> Array2<f64> {
+        Array2::from_shape_fn((height, width), |(y, x)| {
+            centers
+                .iter()
+                .map(|&(cx, cy)| {
+                    let d2 = (x as f64 - cx).powi(2) + (y as f64 - cy).powi(2);
+                    (-d2 / 8.0).exp()
+                })
+                .sum()
+        })
+    }
+
+    #[test]
+    fn test_phase_correlation_translation() {
+        let centers = [(20.0, 18.0), (40.0, 22.0), (30.0, 45.0), (12.0, 50.0)];
+        let shifted: Vec<(f64, f64)> = centers.iter().map(|&(x, y)| (x + 3.0, y - 2.0)).collect();
+        let reference = blob_image(64, 64, &centers);
+        let moving = blob_image(64, 64, &shifted);
+
+        let (dx, dy, peak) = phase_correlation(&reference, &moving).unwrap();
+        assert!((dx - 3.0).abs() < 0.2, "dx = {}", dx);
+        assert!((dy + 2.0).abs() < 0.2, "dy = {}", dy);
+        assert!(peak > 0.1);
+    }
+
+    #[test]
+    fn test_registration_apply_roundtrip() {
+        let reg = Registration { dx: 1.5, dy: -0.5, rotation: 90.0, peak: 1.0 };
+        let (x, y) = reg.apply(11.0, 10.0, (10.0, 10.0));
+        assert!((x - 11.5).abs() < 1e-9);
+        assert!((y - 10.5).abs() < 1e-9);
+    }
+
+    /// Reference O(N*K) erosion used to validate the fast implementation
+    fn naive_erode(image: &Array2<f64>, se: &Array2<bool>) -> Array2<f64> {
+        let (h, w) = image.dim();
+        let (sh, sw) = se.dim();
+        Array2::from_shape_fn((h, w), |(i, j)| {
+            let mut m = f64::INFINITY;
+            for si in 0..sh {
+                for sj in 0..sw {
+                    let ii = i as isize + si as isize - (sh / 2) as isize;
+                    let jj = j as isize + sj as isize - (sw / 2) as isize;
+                    if se[[si, sj]] && ii >= 0 && jj >= 0 && ii < h as isize && jj < w as isize {
+                        m = m.min(image[[ii as usize, jj as usize]]);
+                    }
+                }
+            }
+            if m.is_finite() { m } else { image[[i, j]] }
+        })
+    }
+
+    #[test]
+    fn test_fast_erode_matches_naive() {
+        let image = Array2::from_shape_fn((23, 31), |(y, x)| ((x * 7 + y * 13) % 17) as f64);
+        for radius in [1, 3, 5] {
+            let se = create_disk_structuring_element(radius);
+            assert_eq!(erode(&image, &se), naive_erode(&image, &se), "radius {}", radius);
+        }
+    }
+
+    #[test]
+    fn test_gaussian_blur_preserves_mass_and_smooths() {
+        let mut image = Array2::zeros((21, 21));
+        image[[10, 10]] = 1.0;
+        let blurred = gaussian_blur(&image, 1.5);
+
+        assert!((blurred.sum() - 1.0).abs() < 1e-9);
+        // True Gaussian: value at 1 pixel is exp(-1/(2σ²)) of the peak along an axis
+        let ratio = blurred[[10, 11]] / blurred[[10, 10]];
+        assert!((ratio - (-1.0_f64 / (2.0 * 1.5 * 1.5)).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_top_hat_removes_background() {
+        let mut image = Array2::from_elem((40, 40), 0.5);
+        for y in 18..22 {
+            for x in 18..22 {
+                image[[y, x]] = 1.0;
+            }
+        }
+        let result = preprocess_grid_image(&image, 0, 6);
+
+        assert!(result[[2, 2]].abs() < 1e-9);
+        assert!((result[[20, 20]] - 0.5).abs() < 1e-9);
+    }
+
+    /// Direct correlation used to validate the FFT implementation
+    fn direct_correlate(image: &Array2<f64>, template: &Array2<f64>, zero_mean: bool) -> Array2<f64> {
+        let (ih, iw) = image.dim();
+        let (th, tw) = template.dim();
+        let t_mean = template.mean().unwrap();
+        Array2::from_shape_fn((ih - th + 1, iw - tw + 1), |(y, x)| {
+            let window = image.slice(s![y..y + th, x..x + tw]);
+            let w_mean = if zero_mean { window.mean().unwrap() } else { 0.0 };
+            let t_off = if zero_mean { t_mean } else { 0.0 };
+            let mut num = 0.0;
+            let mut wi = 0.0;
+            let mut wt = 0.0;
+            for ((a, b), _) in window.iter().zip(template.iter()).zip(0..) {
+                num += (a - w_mean) * (b - t_off);
+                wi += (a - w_mean) * (a - w_mean);
+                wt += (b - t_off) * (b - t_off);
+            }
+            if wi * wt > 0.0 { num / (wi * wt).sqrt() } else { 0.0 }
+        })
+    }
+
+    #[test]
+    fn test_fft_cross_correlate_matches_direct() {
+        let image = Array2::from_shape_fn((19, 23), |(y, x)| ((x * 5 + y * 3) % 11) as f64 + 0.5);
+        let template = Array2::from_shape_fn((5, 7), |(y, x)| ((x + 2 * y) % 4) as f64);
+
+        let energy = cross_correlate(&image, &template);
+        let zncc = cross_correlate_with(&image, &template, CorrelationNormalization::Zncc);
+        let expected_energy = direct_correlate(&image, &template, false);
+        let expected_zncc = direct_correlate(&image, &template, true);
+
+        assert_eq!(energy.dim(), (15, 17));
+        for (a, b) in energy.iter().zip(expected_energy.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+        for (a, b) in zncc.iter().zip(expected_zncc.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
     #[test]
     fn test_find_max_2d() {
         let data = arr2(&[[1.0, 2.0], [3.0, 4.0]]);
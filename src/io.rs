@@ -1,11 +1,18 @@
 use crate::error::{Error, Result};
-use crate::types::{BatchConfig, ImageData, ImageType};
-use image::{DynamicImage, ImageBuffer, Luma};
+use crate::quantification::QuantRecord;
+use crate::types::{
+    BatchConfig, GridEdit, ImageData, ImageType, SingleRunConfig, Spot, SpotResult,
+};
+use image::{DynamicImage, ImageBuffer, Luma, Rgb, RgbImage};
+use imageproc::drawing::draw_hollow_circle_mut;
 use ndarray::Array2;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
 
+pub mod table;
+pub mod tercen;
+
 /// Load a TIFF image from file
 pub fn load_tiff_image<P: AsRef<Path>>(path: P) -> Result<ImageData> {
     let path = path.as_ref();
@@ -107,19 +114,297 @@ pub fn load_batch_config<P: AsRef<Path>>(path: P) -> Result<BatchConfig> {
     Ok(config)
 }
 
-/// Write progress to file
+/// Load a grid edit from JSON file, rejecting a non-positive `scalePitch`
+pub fn load_grid_edit<P: AsRef<Path>>(path: P) -> Result<GridEdit> {
+    let path = path.as_ref();
+    let edit: GridEdit = serde_json::from_reader(File::open(path)?)?;
+    if !(edit.scale_pitch.is_finite() && edit.scale_pitch > 0.0) {
+        return Err(Error::InvalidConfiguration(format!(
+            "Grid edit {}: scalePitch must be a positive number, got {}",
+            path.display(),
+            edit.scale_pitch
+        )));
+    }
+    Ok(edit)
+}
+
+/// Load single-run (MATLAB `pamsoft_grid`) parameters from JSON file
+///
+/// Accepts the parameter object itself or, as written by some MATLAB scripts,
+/// a one-element array containing it.
+pub fn load_single_run_config<P: AsRef<Path>>(path: P) -> Result<SingleRunConfig> {
+    let file = File::open(path)?;
+    let value = match serde_json::from_reader(file)? {
+        serde_json::Value::Array(mut items) if items.len() == 1 => items.remove(0),
+        value => value,
+    };
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Column header of the MATLAB gridding output (output_grid.txt)
+pub const GRID_OUTPUT_HEADER: &str = "qntSpotID,grdIsReference,grdRow,grdCol,grdXOffset,grdYOffset,\
+grdXFixedPosition,grdYFixedPosition,gridX,gridY,grdRotation,grdImageNameUsed";
+
+/// Column header of the MATLAB quantification output (output_quant.txt)
+pub const QUANT_OUTPUT_HEADER: &str = "Row,Column,Mean_SigmBg,Median_SigmBg,Rse_MedianSigmBg,\
+Mean_Signal,Median_Signal,Std_Signal,Sum_Signal,Rse_Signal,Mean_Background,Median_Background,\
+Std_Background,Sum_Background,Rse_Background,Signal_Saturation,Fraction_Ignored,Diameter,\
+X_Position,Y_Position,Position_Offset,Empty_Spot,Bad_Spot,Replaced_Spot,ImageName";
+
+fn flag(value: bool) -> u8 {
+    value as u8
+}
+
+/// Format a number like C `printf("%.<precision>g")`, as MATLAB writes numbers
+///
+/// Trailing zeros are dropped, exponents have at least two digits
+/// (`1.5e-05`), and non-finite values are written as MATLAB does
+/// (`NaN`, `Inf`, `-Inf`).
+pub fn format_g(value: f64, precision: usize) -> String {
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    let precision = precision.max(1);
+
+    // The exponent after rounding to `precision` significant digits decides the style
+    let scientific = format!("{:.*e}", precision - 1, value);
+    let (mantissa, exponent) = scientific.split_once('e').expect("exponent format");
+    let exponent: i32 = exponent.parse().expect("exponent");
+
+    if exponent < -4 || exponent >= precision as i32 {
+        format!(
+            "{}e{}{:02}",
+            trim_fraction(mantissa),
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        )
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        trim_fraction(&format!("{:.*}", decimals, value)).to_string()
+    }
+}
+
+/// Drop trailing zeros (and a trailing point) of a decimal fraction
+fn trim_fraction(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+/// Double precision column (`%.15g`)
+fn double(value: f64) -> String {
+    format_g(value, 15)
+}
+
+/// Single precision column (`%.7g` of the value rounded to single)
+fn single(value: f64) -> String {
+    format_g(value as f32 as f64, 7)
+}
+
+/// Write grid positions in the MATLAB gridding output format
+///
+/// Formatted byte for byte like output_grid.txt: `%.15g` numbers, 0/1 flags
+/// and the images the grid was found on (`image_names`) comma-joined and
+/// quoted in every row. Only the formatting is compatible; positions found
+/// by this implementation are not expected to equal MATLAB's to the last digit.
+pub fn write_grid_output<P: AsRef<Path>>(path: P, spots: &[Spot], image_names: &[String]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let names = image_names.join(",");
+
+    writeln!(writer, "{}", GRID_OUTPUT_HEADER)?;
+    for spot in spots {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{},\"{}\"",
+            spot.id,
+            flag(spot.is_reference),
+            spot.row,
+            spot.col,
+            double(spot.x_offset),
+            double(spot.y_offset),
+            double(spot.x_fixed),
+            double(spot.y_fixed),
+            double(spot.grid_x),
+            double(spot.grid_y),
+            double(spot.rotation),
+            names
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read grid positions from a MATLAB gridding output file
+///
+/// Spot diameters are not part of the file and are left at 0.
+pub fn read_grid_output<P: AsRef<Path>>(path: P) -> Result<Vec<Spot>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let columns = [
+        "qntSpotID",
+        "grdIsReference",
+        "grdRow",
+        "grdCol",
+        "grdXOffset",
+        "grdYOffset",
+        "grdXFixedPosition",
+        "grdYFixedPosition",
+        "gridX",
+        "gridY",
+        "grdRotation",
+    ]
+    .iter()
+    .map(|&name| {
+        headers.iter().position(|h| h.trim() == name).ok_or_else(|| {
+            Error::InvalidConfiguration(format!("Grid file has no {} column", name))
+        })
+    })
+    .collect::<Result<Vec<usize>>>()?;
+
+    let mut spots = Vec::new();
+    for (line, record) in reader.records().enumerate() {
+        let record = record?;
+        let field = |i: usize| record.get(columns[i]).unwrap_or("").trim();
+        let number = |i: usize| -> Result<f64> {
+            field(i).parse::<f64>().map_err(|_| {
+                Error::InvalidConfiguration(format!(
+                    "Grid file row {}: invalid number {:?}",
+                    line + 2,
+                    field(i)
+                ))
+            })
+        };
+
+        spots.push(Spot {
+            id: field(0).to_string(),
+            is_reference: number(1)? != 0.0,
+            row: number(2)? as i32,
+            col: number(3)? as i32,
+            x_offset: number(4)?,
+            y_offset: number(5)?,
+            x_fixed: number(6)?,
+            y_fixed: number(7)?,
+            grid_x: number(8)?,
+            grid_y: number(9)?,
+            diameter: 0.0,
+            is_manual: false,
+            manual_source: None,
+            is_bad: false,
+            is_empty: false,
+            rotation: number(10)?,
+        });
+    }
+
+    Ok(spots)
+}
+
+/// Write quantification records in the MATLAB quantification output format
+///
+/// Formatted byte for byte like output_quant.txt: MATLAB stores the standard
+/// deviations and relative standard errors in single precision (`%.7g`), all
+/// other numbers in double precision (`%.15g`); ImageName is quoted. As for
+/// [`write_grid_output`], only the formatting is compatible, not the values.
+pub fn write_quant_output<P: AsRef<Path>>(path: P, records: &[QuantRecord]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writeln!(writer, "{}", QUANT_OUTPUT_HEADER)?;
+    for r in records {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\"{}\"",
+            r.row,
+            r.col,
+            double(r.mean_sigm_bg),
+            double(r.median_sigm_bg),
+            single(r.rse_median_sigm_bg),
+            double(r.mean_signal),
+            double(r.median_signal),
+            single(r.std_signal),
+            double(r.sum_signal),
+            single(r.rse_signal),
+            double(r.mean_background),
+            double(r.median_background),
+            single(r.std_background),
+            double(r.sum_background),
+            single(r.rse_background),
+            double(r.signal_saturation),
+            double(r.fraction_ignored),
+            double(r.diameter),
+            double(r.x_position),
+            double(r.y_position),
+            double(r.position_offset),
+            flag(r.is_empty),
+            flag(r.is_bad),
+            flag(r.is_replaced),
+            r.image_name
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read quantification records from a MATLAB quantification output file
+///
+/// The file has no spot IDs, they are left empty.
+pub fn read_quant_output<P: AsRef<Path>>(path: P) -> Result<Vec<QuantRecord>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let records = reader.deserialize().collect::<std::result::Result<_, _>>()?;
+    Ok(records)
+}
+
+/// Write progress to file (nothing is written for an empty path)
 pub fn write_progress<P: AsRef<Path>>(
     path: P,
     current: usize,
     total: usize,
     message: &str,
 ) -> Result<()> {
-    use std::io::Write;
+    if path.as_ref().as_os_str().is_empty() {
+        return Ok(());
+    }
     let mut file = File::create(path)?;
     writeln!(file, "{}/{}: {}", current, total, message)?;
     Ok(())
 }
 
+/// 8-bit RGB rendering of an image with the spot circles drawn on top
+///
+/// Intensities are scaled to the image maximum. Good spots are green, bad
+/// spots red, empty spots yellow and reference spots blue.
+pub fn render_overlay(image: &ImageData, spots: &[SpotResult]) -> RgbImage {
+    let max = image.data.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let mut canvas = RgbImage::from_fn(image.width as u32, image.height as u32, |x, y| {
+        let value = (image.data[[y as usize, x as usize]] as f64 / max * 255.0).round() as u8;
+        Rgb([value, value, value])
+    });
+
+    for spot in spots {
+        let color = if spot.is_reference {
+            [64, 128, 255]
+        } else if spot.is_bad {
+            [255, 0, 0]
+        } else if spot.is_empty {
+            [255, 255, 0]
+        } else {
+            [0, 255, 0]
+        };
+        let radius = (spot.diameter / 2.0).round().max(1.0) as i32;
+        draw_hollow_circle_mut(
+            &mut canvas,
+            (spot.grid_x.round() as i32, spot.grid_y.round() as i32),
+            radius,
+            Rgb(color),
+        );
+    }
+
+    canvas
+}
+
 /// Convert ImageData to image crate format for saving
 pub fn to_image_buffer(data: &ImageData) -> ImageBuffer<Luma<u16>, Vec<u16>> {
     let mut img = ImageBuffer::new(data.width as u32, data.height as u32);
@@ -148,4 +433,79 @@ mod tests {
         assert_eq!(ImageType::Evolve2.default_spot_pitch(), Some(21.5));
         assert_eq!(ImageType::Unknown.default_spot_pitch(), None);
     }
+
+    #[test]
+    fn test_grid_edit_rejects_invalid_pitch_scale() {
+        let path = std::env::temp_dir().join("pamsoft_grid_edit.json");
+        std::fs::write(&path, r#"{"translate": [2, -1.5], "scalePitch": 1.01}"#).unwrap();
+        let edit = load_grid_edit(&path).unwrap();
+        assert_eq!((edit.translate, edit.rotate, edit.scale_pitch), ((2.0, -1.5), 0.0, 1.01));
+
+        std::fs::write(&path, r#"{"scalePitch": 0}"#).unwrap();
+        let err = load_grid_edit(&path).unwrap_err();
+        assert!(matches!(err, Error::InvalidConfiguration(_)));
+        assert!(err.to_string().contains(&path.display().to_string()));
+    }
+
+    #[test]
+    fn test_read_matlab_grid_output() {
+        let path = std::env::temp_dir().join("pamsoft_read_grid_output.txt");
+        std::fs::write(
+            &path,
+            format!(
+                "{}\n#REF,1,-1,-1,0,0,0,0,240.081810387499,165.410318090533,0.5,\"a,b\"\n\
+                 AMPE_5_17,0,1,3,0,0,0,0,174.744415558998,293.650955217479,0.5,\"a,b\"\n",
+                GRID_OUTPUT_HEADER
+            ),
+        )
+        .unwrap();
+
+        let spots = read_grid_output(&path).unwrap();
+        assert_eq!(spots.len(), 2);
+        assert!(spots[0].is_reference);
+        assert_eq!((spots[0].row, spots[0].col), (-1, -1));
+        assert_eq!(spots[1].id, "AMPE_5_17");
+        assert_eq!(spots[1].grid_x, 174.744415558998);
+        assert_eq!(spots[1].rotation, 0.5);
+
+        // Writing and reading back keeps the positions
+        write_grid_output(&path, &spots, &["a".to_string(), "b".to_string()]).unwrap();
+        let again = read_grid_output(&path).unwrap();
+        assert_eq!(again[0].grid_y, spots[0].grid_y);
+        assert_eq!(again[1].id, spots[1].id);
+    }
+
+    #[test]
+    fn test_format_g_matches_printf() {
+        assert_eq!(format_g(240.0818103874987, 15), "240.081810387499");
+        assert_eq!(format_g(0.5, 15), "0.5");
+        assert_eq!(format_g(1438.0, 15), "1438");
+        assert_eq!(format_g(-0.0, 15), "-0");
+        assert_eq!(format_g(0.000015, 15), "1.5e-05");
+        assert_eq!(format_g(1.0e15, 15), "1e+15");
+        assert_eq!(format_g(999999.96, 7), "1000000");
+        assert_eq!(format_g(792.79669, 7), "792.7967");
+        assert_eq!(format_g(f64::NAN, 15), "NaN");
+        assert_eq!(format_g(f64::NEG_INFINITY, 15), "-Inf");
+    }
+
+    #[test]
+    fn test_matlab_outputs_are_byte_compatible() {
+        // Covers the formatting layer only: MATLAB's values are read back and
+        // rewritten. The images behind these files are not in the repository,
+        // so pipeline output cannot be compared with them.
+        let reference = std::fs::read_to_string("output_grid.txt").unwrap();
+        let spots = read_grid_output("output_grid.txt").unwrap();
+        let first_row = reference.lines().nth(1).unwrap();
+        let names: Vec<String> = first_row.split('"').nth(1).unwrap().split(',').map(String::from).collect();
+        let path = std::env::temp_dir().join("pamsoft_output_grid.txt");
+        write_grid_output(&path, &spots, &names).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), reference);
+
+        let reference = std::fs::read_to_string("output_quant.txt").unwrap();
+        let records = read_quant_output("output_quant.txt").unwrap();
+        let path = std::env::temp_dir().join("pamsoft_output_quant.txt");
+        write_quant_output(&path, &records).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), reference);
+    }
 }
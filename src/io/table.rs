@@ -0,0 +1,476 @@
+use super::tercen::TercenWriter;
+use crate::config::OutputFormat;
+use crate::error::{Error, Result};
+use crate::quantification::QuantRecord;
+use crate::types::SpotResult;
+use arrow_array::builder::{BooleanBuilder, Float64Builder, Int32Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Type of a table column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Utf8,
+    Int32,
+    Float64,
+    Boolean,
+}
+
+/// One value of a table row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cell<'a> {
+    Utf8(&'a str),
+    Int32(i32),
+    Float64(f64),
+    Boolean(bool),
+}
+
+/// A row type that can be written as a table
+///
+/// Delimited formats use the serde representation, columnar formats the typed
+/// cells; both use the same column names and order.
+pub trait TableRecord: Serialize {
+    fn columns() -> Vec<(&'static str, ColumnType)>;
+
+    /// Values in `columns()` order
+    fn cells(&self) -> Vec<Cell<'_>>;
+}
+
+impl TableRecord for SpotResult {
+    fn columns() -> Vec<(&'static str, ColumnType)> {
+        use ColumnType::*;
+        vec![
+            ("groupId", Utf8),
+            ("qntSpotID", Utf8),
+            ("grdIsReference", Boolean),
+            ("grdRow", Float64),
+            ("grdCol", Float64),
+            ("grdXFixedPosition", Float64),
+            ("grdYFixedPosition", Float64),
+            ("gridX", Float64),
+            ("gridY", Float64),
+            ("diameter", Float64),
+            ("isManual", Boolean),
+            ("segIsBad", Boolean),
+            ("segIsEmpty", Boolean),
+            ("grdRotation", Float64),
+            ("grdImageNameUsed", Utf8),
+            ("manualSource", Utf8),
+            ("grdQuality", Float64),
+            ("grdNeedsReview", Boolean),
+            ("qntDriftX", Float64),
+            ("qntDriftY", Float64),
+            ("qntDriftRotation", Float64),
+            ("qntDriftPeak", Float64),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell<'_>> {
+        vec![
+            Cell::Utf8(&self.group_id),
+            Cell::Utf8(&self.spot_id),
+            Cell::Boolean(self.is_reference),
+            Cell::Float64(self.row),
+            Cell::Float64(self.col),
+            Cell::Float64(self.x_fixed),
+            Cell::Float64(self.y_fixed),
+            Cell::Float64(self.grid_x),
+            Cell::Float64(self.grid_y),
+            Cell::Float64(self.diameter),
+            Cell::Boolean(self.is_manual),
+            Cell::Boolean(self.is_bad),
+            Cell::Boolean(self.is_empty),
+            Cell::Float64(self.rotation),
+            Cell::Utf8(&self.image_name),
+            Cell::Utf8(&self.manual_source),
+            Cell::Float64(self.grid_quality),
+            Cell::Boolean(self.needs_review),
+            Cell::Float64(self.drift_x),
+            Cell::Float64(self.drift_y),
+            Cell::Float64(self.drift_rotation),
+            Cell::Float64(self.drift_peak),
+        ]
+    }
+}
+
+impl TableRecord for QuantRecord {
+    fn columns() -> Vec<(&'static str, ColumnType)> {
+        use ColumnType::*;
+        vec![
+            ("qntSpotID", Utf8),
+            ("Row", Int32),
+            ("Column", Int32),
+            ("Mean_SigmBg", Float64),
+            ("Median_SigmBg", Float64),
+            ("Rse_MedianSigmBg", Float64),
+            ("Mean_Signal", Float64),
+            ("Median_Signal", Float64),
+            ("Std_Signal", Float64),
+            ("Sum_Signal", Float64),
+            ("Rse_Signal", Float64),
+            ("Mean_Background", Float64),
+            ("Median_Background", Float64),
+            ("Std_Background", Float64),
+            ("Sum_Background", Float64),
+            ("Rse_Background", Float64),
+            ("Signal_Saturation", Float64),
+            ("Fraction_Ignored", Float64),
+            ("Diameter", Float64),
+            ("X_Position", Float64),
+            ("Y_Position", Float64),
+            ("Position_Offset", Float64),
+            ("Empty_Spot", Boolean),
+            ("Bad_Spot", Boolean),
+            ("Replaced_Spot", Boolean),
+            ("ImageName", Utf8),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell<'_>> {
+        vec![
+            Cell::Utf8(&self.spot_id),
+            Cell::Int32(self.row),
+            Cell::Int32(self.col),
+            Cell::Float64(self.mean_sigm_bg),
+            Cell::Float64(self.median_sigm_bg),
+            Cell::Float64(self.rse_median_sigm_bg),
+            Cell::Float64(self.mean_signal),
+            Cell::Float64(self.median_signal),
+            Cell::Float64(self.std_signal),
+            Cell::Float64(self.sum_signal),
+            Cell::Float64(self.rse_signal),
+            Cell::Float64(self.mean_background),
+            Cell::Float64(self.median_background),
+            Cell::Float64(self.std_background),
+            Cell::Float64(self.sum_background),
+            Cell::Float64(self.rse_background),
+            Cell::Float64(self.signal_saturation),
+            Cell::Float64(self.fraction_ignored),
+            Cell::Float64(self.diameter),
+            Cell::Float64(self.x_position),
+            Cell::Float64(self.y_position),
+            Cell::Float64(self.position_offset),
+            Cell::Boolean(self.is_empty),
+            Cell::Boolean(self.is_bad),
+            Cell::Boolean(self.is_replaced),
+            Cell::Utf8(&self.image_name),
+        ]
+    }
+}
+
+/// Incremental writer of one result table
+///
+/// Every `write` is flushed to the file (a Parquet row group or an Arrow
+/// record batch for columnar formats). Columnar files are only readable once
+/// `finish` wrote their footer; Tercen tables are assembled by `finish` from
+/// per-column files.
+pub trait TableWriter<R: TableRecord> {
+    fn write(&mut self, rows: &[R]) -> Result<()>;
+
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Create a table writer for `format`, truncating any existing file
+pub fn create_table_writer<R: TableRecord + 'static, P: AsRef<Path>>(
+    path: P,
+    format: OutputFormat,
+) -> Result<Box<dyn TableWriter<R>>> {
+    Ok(match format {
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            Box::new(DelimitedWriter::create(path, delimiter(format))?)
+        }
+        OutputFormat::Parquet => {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            let writer = ArrowWriter::try_new(File::create(path)?, schema::<R>(), Some(properties))?;
+            Box::new(ParquetWriter(writer))
+        }
+        OutputFormat::ArrowIpc => {
+            let schema = schema::<R>();
+            Box::new(IpcWriter(FileWriter::try_new(File::create(path)?, &schema)?))
+        }
+        OutputFormat::Tercen => Box::new(TercenWriter::create::<R, _>(path)?),
+    })
+}
+
+/// Write a whole table in one go
+pub fn write_table<R: TableRecord + 'static, P: AsRef<Path>>(
+    rows: &[R],
+    path: P,
+    format: OutputFormat,
+) -> Result<()> {
+    let mut writer = create_table_writer(path, format)?;
+    writer.write(rows)?;
+    writer.finish()
+}
+
+/// Append to a CSV or TSV table, keeping only its first `len` bytes
+///
+/// Anything after `len` (e.g. a torn last row) is cut off. The header is
+/// written only when nothing of the file is kept.
+pub fn append_table_writer<R: TableRecord + 'static, P: AsRef<Path>>(
+    path: P,
+    format: OutputFormat,
+    len: u64,
+) -> Result<Box<dyn TableWriter<R>>> {
+    if !format.is_delimited() {
+        return Err(Error::InvalidConfiguration(format!(
+            "{:?} output cannot be appended to, use CSV or TSV",
+            format
+        )));
+    }
+    Ok(Box::new(DelimitedWriter::append(path, delimiter(format), len)?))
+}
+
+/// Reader for a delimited table written by [`create_table_writer`]
+pub fn delimited_reader<P: AsRef<Path>>(path: P, format: OutputFormat) -> Result<csv::Reader<File>> {
+    Ok(csv::ReaderBuilder::new().delimiter(delimiter(format)).from_path(path)?)
+}
+
+fn delimiter(format: OutputFormat) -> u8 {
+    if format == OutputFormat::Tsv {
+        b'\t'
+    } else {
+        b','
+    }
+}
+
+struct DelimitedWriter(csv::Writer<File>);
+
+impl DelimitedWriter {
+    fn create<P: AsRef<Path>>(path: P, delimiter: u8) -> Result<Self> {
+        Ok(Self(csv::WriterBuilder::new().delimiter(delimiter).from_path(path)?))
+    }
+
+    fn append<P: AsRef<Path>>(path: P, delimiter: u8, len: u64) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.set_len(len)?;
+        Ok(Self(
+            csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .has_headers(len == 0)
+                .from_writer(file),
+        ))
+    }
+}
+
+impl<R: TableRecord> TableWriter<R> for DelimitedWriter {
+    fn write(&mut self, rows: &[R]) -> Result<()> {
+        for row in rows {
+            self.0.serialize(row)?;
+        }
+        self.0.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+struct ParquetWriter(ArrowWriter<File>);
+
+impl<R: TableRecord> TableWriter<R> for ParquetWriter {
+    fn write(&mut self, rows: &[R]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        self.0.write(&record_batch(rows)?)?;
+        self.0.flush()?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.0.close()?;
+        Ok(())
+    }
+}
+
+struct IpcWriter(FileWriter<File>);
+
+impl<R: TableRecord> TableWriter<R> for IpcWriter {
+    fn write(&mut self, rows: &[R]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        self.0.write(&record_batch(rows)?)?;
+        self.0.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.0.finish()?;
+        Ok(())
+    }
+}
+
+/// Arrow schema of a record type; no column is nullable
+pub fn schema<R: TableRecord>() -> SchemaRef {
+    let fields: Vec<Field> = R::columns()
+        .into_iter()
+        .map(|(name, kind)| {
+            let data_type = match kind {
+                ColumnType::Utf8 => DataType::Utf8,
+                ColumnType::Int32 => DataType::Int32,
+                ColumnType::Float64 => DataType::Float64,
+                ColumnType::Boolean => DataType::Boolean,
+            };
+            Field::new(name, data_type, false)
+        })
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+/// Builder of one column
+enum ColumnBuilder {
+    Utf8(StringBuilder),
+    Int32(Int32Builder),
+    Float64(Float64Builder),
+    Boolean(BooleanBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(kind: ColumnType, capacity: usize) -> Self {
+        match kind {
+            ColumnType::Utf8 => ColumnBuilder::Utf8(StringBuilder::with_capacity(capacity, capacity * 16)),
+            ColumnType::Int32 => ColumnBuilder::Int32(Int32Builder::with_capacity(capacity)),
+            ColumnType::Float64 => ColumnBuilder::Float64(Float64Builder::with_capacity(capacity)),
+            ColumnType::Boolean => ColumnBuilder::Boolean(BooleanBuilder::with_capacity(capacity)),
+        }
+    }
+
+    fn append(&mut self, cell: Cell) {
+        match (self, cell) {
+            (ColumnBuilder::Utf8(b), Cell::Utf8(v)) => b.append_value(v),
+            (ColumnBuilder::Int32(b), Cell::Int32(v)) => b.append_value(v),
+            (ColumnBuilder::Float64(b), Cell::Float64(v)) => b.append_value(v),
+            (ColumnBuilder::Boolean(b), Cell::Boolean(v)) => b.append_value(v),
+            (_, cell) => panic!("cell {:?} does not match its column type", cell),
+        }
+    }
+
+    fn finish(self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Utf8(mut b) => Arc::new(b.finish()),
+            ColumnBuilder::Int32(mut b) => Arc::new(b.finish()),
+            ColumnBuilder::Float64(mut b) => Arc::new(b.finish()),
+            ColumnBuilder::Boolean(mut b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// Rows as one Arrow record batch
+pub fn record_batch<R: TableRecord>(rows: &[R]) -> Result<RecordBatch> {
+    let mut builders: Vec<ColumnBuilder> = R::columns()
+        .into_iter()
+        .map(|(_, kind)| ColumnBuilder::new(kind, rows.len()))
+        .collect();
+    for row in rows {
+        for (builder, cell) in builders.iter_mut().zip(row.cells()) {
+            builder.append(cell);
+        }
+    }
+    let columns = builders.into_iter().map(ColumnBuilder::finish).collect();
+    Ok(RecordBatch::try_new(schema::<R>(), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Array, BooleanArray, Float64Array, Int32Array, StringArray};
+    use arrow_ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn result(spot_id: &str, is_bad: bool) -> SpotResult {
+        SpotResult {
+            is_bad,
+            ..SpotResult::example("g", spot_id)
+        }
+    }
+
+    #[test]
+    fn test_serde_header_matches_columns() {
+        let path = std::env::temp_dir().join("pamsoft_table_header.tsv");
+        write_table(&[result("a", true)], &path, OutputFormat::Tsv).unwrap();
+
+        let mut reader = delimited_reader(&path, OutputFormat::Tsv).unwrap();
+        let header: Vec<String> = reader.headers().unwrap().iter().map(String::from).collect();
+        let columns: Vec<&str> = SpotResult::columns().iter().map(|(name, _)| *name).collect();
+        assert_eq!(header, columns);
+
+        // Delimited flags stay 0/1 and read back as booleans
+        let row: SpotResult = reader.deserialize().next().unwrap().unwrap();
+        assert!(row.is_bad);
+        assert!(std::fs::read_to_string(&path).unwrap().contains("\t1\t0\t0.0\timg"));
+    }
+
+    #[test]
+    fn test_parquet_has_typed_columns() {
+        let path = std::env::temp_dir().join("pamsoft_table_typed.parquet");
+        let mut writer = create_table_writer(&path, OutputFormat::Parquet).unwrap();
+        writer.write(&[result("a", true)]).unwrap();
+        writer.write(&[result("b", false)]).unwrap();
+        writer.finish().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+
+        let batch = &batches[0];
+        let is_bad = batch.column_by_name("segIsBad").unwrap();
+        assert_eq!(is_bad.data_type(), &DataType::Boolean);
+        assert!(is_bad.as_any().downcast_ref::<BooleanArray>().unwrap().value(0));
+        let grid_x = batch.column_by_name("gridX").unwrap();
+        assert_eq!(grid_x.as_any().downcast_ref::<Float64Array>().unwrap().value(0), 10.5);
+    }
+
+    #[test]
+    fn test_arrow_ipc_round_trip() {
+        let path = std::env::temp_dir().join("pamsoft_table_round_trip.arrow");
+        let mut writer = create_table_writer(&path, OutputFormat::ArrowIpc).unwrap();
+        writer.write(&[result("a", true)]).unwrap();
+        writer.write(&[result("b", false)]).unwrap();
+        writer.finish().unwrap();
+
+        let reader = FileReader::try_new(File::open(&path).unwrap(), None).unwrap();
+        assert_eq!(reader.schema(), schema::<SpotResult>());
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(batches.len(), 2);
+
+        let expected = [result("a", true), result("b", false)];
+        for (batch, row) in batches.iter().zip(&expected) {
+            assert_eq!(batch.num_rows(), 1);
+            for (index, cell) in row.cells().into_iter().enumerate() {
+                let column = batch.column(index);
+                match cell {
+                    Cell::Utf8(text) => {
+                        assert_eq!(column.as_any().downcast_ref::<StringArray>().unwrap().value(0), text)
+                    }
+                    Cell::Int32(value) => {
+                        assert_eq!(column.as_any().downcast_ref::<Int32Array>().unwrap().value(0), value)
+                    }
+                    Cell::Float64(value) => {
+                        let read = column.as_any().downcast_ref::<Float64Array>().unwrap().value(0);
+                        assert!(read == value || (read.is_nan() && value.is_nan()));
+                    }
+                    Cell::Boolean(value) => {
+                        assert_eq!(column.as_any().downcast_ref::<BooleanArray>().unwrap().value(0), value)
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use super::table::{Cell, ColumnType, TableRecord, TableWriter};
+use crate::error::{Error, Result};
+use crate::types::{BatchConfig, GroupConfig, TercenConfig};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A table in Tercen's JSON representation
+///
+/// `{"kind": "Table", "nRows": 2, "properties": {...}, "columns": [...]}`;
+/// unknown keys (column metadata, ids) are ignored when reading.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TercenTable {
+    #[serde(default = "table_kind")]
+    pub kind: String,
+    #[serde(rename = "nRows")]
+    pub n_rows: usize,
+    #[serde(default)]
+    pub properties: TableProperties,
+    pub columns: Vec<TercenColumn>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableProperties {
+    #[serde(default = "properties_kind")]
+    pub kind: String,
+    #[serde(default)]
+    pub name: String,
+}
+
+impl Default for TableProperties {
+    fn default() -> Self {
+        Self {
+            kind: properties_kind(),
+            name: String::new(),
+        }
+    }
+}
+
+/// One column: `{"kind": "Column", "name": "x", "type": "double", "nRows": 2, "values": [...]}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TercenColumn {
+    #[serde(default = "column_kind")]
+    pub kind: String,
+    pub name: String,
+    /// "string", "double", "int32", ...
+    #[serde(rename = "type")]
+    pub column_type: String,
+    #[serde(rename = "nRows")]
+    pub n_rows: usize,
+    pub values: Vec<Value>,
+}
+
+fn table_kind() -> String {
+    "Table".to_string()
+}
+
+fn properties_kind() -> String {
+    "TableProperties".to_string()
+}
+
+fn column_kind() -> String {
+    "Column".to_string()
+}
+
+impl TercenTable {
+    /// Column by name
+    pub fn column(&self, name: &str) -> Result<&TercenColumn> {
+        self.columns
+            .iter()
+            .find(|column| column.name == name)
+            .ok_or_else(|| Error::InvalidConfiguration(format!("Tercen table has no column {:?}", name)))
+    }
+
+    /// Table of records; flags become int32 0/1 columns as in the MATLAB outputs
+    pub fn from_records<R: TableRecord>(name: &str, rows: &[R]) -> Self {
+        let mut columns: Vec<TercenColumn> = R::columns()
+            .into_iter()
+            .map(|(name, kind)| TercenColumn {
+                kind: column_kind(),
+                name: name.to_string(),
+                column_type: tercen_type(kind).to_string(),
+                n_rows: rows.len(),
+                values: Vec::with_capacity(rows.len()),
+            })
+            .collect();
+        for row in rows {
+            for (column, cell) in columns.iter_mut().zip(row.cells()) {
+                column.values.push(cell_value(cell));
+            }
+        }
+
+        Self {
+            kind: table_kind(),
+            n_rows: rows.len(),
+            properties: TableProperties {
+                name: name.to_string(),
+                ..TableProperties::default()
+            },
+            columns,
+        }
+    }
+}
+
+impl TercenColumn {
+    /// Values as text; numbers are formatted (group ids are often numeric)
+    pub fn strings(&self) -> Result<Vec<String>> {
+        self.values
+            .iter()
+            .map(|value| match value {
+                Value::String(text) => Ok(text.clone()),
+                Value::Number(number) => Ok(number.to_string()),
+                other => Err(Error::InvalidConfiguration(format!(
+                    "Tercen column {:?}: expected text, got {}",
+                    self.name, other
+                ))),
+            })
+            .collect()
+    }
+
+    /// Values as numbers; `null` (a missing or non-finite value) is NaN
+    pub fn numbers(&self) -> Result<Vec<f64>> {
+        self.values
+            .iter()
+            .map(|value| match value {
+                Value::Number(number) => Ok(number.as_f64().unwrap_or(f64::NAN)),
+                Value::Null => Ok(f64::NAN),
+                other => Err(Error::InvalidConfiguration(format!(
+                    "Tercen column {:?}: expected a number, got {}",
+                    self.name, other
+                ))),
+            })
+            .collect()
+    }
+}
+
+fn tercen_type(kind: ColumnType) -> &'static str {
+    match kind {
+        ColumnType::Utf8 => "string",
+        ColumnType::Int32 | ColumnType::Boolean => "int32",
+        ColumnType::Float64 => "double",
+    }
+}
+
+fn cell_value(cell: Cell) -> Value {
+    match cell {
+        Cell::Utf8(text) => Value::from(text),
+        Cell::Int32(value) => Value::from(value),
+        // Non-finite values have no JSON representation and become null
+        Cell::Float64(value) => Value::from(value),
+        Cell::Boolean(value) => Value::from(value as i32),
+    }
+}
+
+/// Read a table in Tercen JSON representation
+pub fn read_tercen_table<P: AsRef<Path>>(path: P) -> Result<TercenTable> {
+    let table: TercenTable = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    if let Some(column) = table.columns.iter().find(|c| c.values.len() != table.n_rows) {
+        return Err(Error::InvalidConfiguration(format!(
+            "Tercen column {:?} has {} values, table has {} rows",
+            column.name,
+            column.values.len(),
+            table.n_rows
+        )));
+    }
+    Ok(table)
+}
+
+/// Write a table in Tercen JSON representation
+pub fn write_tercen_table<P: AsRef<Path>>(path: P, table: &TercenTable) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut writer, table)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Table writer spooling every column to a file next to the output
+///
+/// Tercen tables are stored column by column, so rows cannot be appended to
+/// the output itself; `finish` assembles the table from the column files,
+/// which are removed when the writer is dropped. Memory use does not grow with
+/// the number of rows.
+pub(crate) struct TercenWriter {
+    file: File,
+    name: String,
+    columns: Vec<(&'static str, ColumnType)>,
+    spools: Vec<(PathBuf, BufWriter<File>)>,
+    n_rows: usize,
+}
+
+impl TercenWriter {
+    /// Create the file now, so an unwritable path fails before processing
+    pub fn create<R: TableRecord, P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("results");
+        let file = File::create(path)?;
+        let columns = R::columns();
+
+        let mut writer = Self {
+            file,
+            name: name.to_string(),
+            columns,
+            spools: Vec::new(),
+            n_rows: 0,
+        };
+        for index in 0..writer.columns.len() {
+            let spool = PathBuf::from(format!("{}.column{}", path.display(), index));
+            let file = File::create(&spool)?;
+            writer.spools.push((spool, BufWriter::new(file)));
+        }
+        Ok(writer)
+    }
+}
+
+impl<R: TableRecord> TableWriter<R> for TercenWriter {
+    fn write(&mut self, rows: &[R]) -> Result<()> {
+        for row in rows {
+            for ((_, spool), cell) in self.spools.iter_mut().zip(row.cells()) {
+                if self.n_rows > 0 {
+                    spool.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut *spool, &cell_value(cell))?;
+            }
+            self.n_rows += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let mut writer = BufWriter::new(&self.file);
+        let properties = TableProperties {
+            name: self.name.clone(),
+            ..TableProperties::default()
+        };
+
+        // Same layout as serializing a `TercenTable`
+        write!(writer, r#"{{"kind":"{}","nRows":{},"properties":"#, table_kind(), self.n_rows)?;
+        serde_json::to_writer(&mut writer, &properties)?;
+        writer.write_all(br#","columns":["#)?;
+        for (index, ((name, kind), (spool, values))) in
+            self.columns.iter().zip(&mut self.spools).enumerate()
+        {
+            values.flush()?;
+            if index > 0 {
+                writer.write_all(b",")?;
+            }
+            write!(
+                writer,
+                r#"{{"kind":"{}","name":{},"type":"{}","nRows":{},"values":["#,
+                column_kind(),
+                serde_json::to_string(name)?,
+                tercen_type(*kind),
+                self.n_rows
+            )?;
+            std::io::copy(&mut File::open(spool)?, &mut writer)?;
+            writer.write_all(b"]}")?;
+        }
+        writer.write_all(b"]}")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for TercenWriter {
+    fn drop(&mut self) {
+        for (spool, _) in self.spools.drain(..) {
+            let _ = std::fs::remove_file(spool);
+        }
+    }
+}
+
+/// Image groups described by a Tercen input table, one row per image
+///
+/// Groups keep the order of their first row and images their row order. Every
+/// group takes `config.parameters`; all rows of a group must name the same
+/// layout file.
+pub fn tercen_image_groups(config: &TercenConfig) -> Result<Vec<GroupConfig>> {
+    let table = read_tercen_table(&config.input_table)?;
+    let group_ids = table.column(&config.group_column)?.strings()?;
+    let images = table.column(&config.image_column)?.strings()?;
+    let layouts = match table.column(&config.layout_column) {
+        Ok(column) => Some(column.strings()?),
+        Err(_) if !config.parameters.array_layout_file.is_empty() => None,
+        Err(e) => return Err(e),
+    };
+
+    let mut groups: Vec<GroupConfig> = Vec::new();
+    for (row, (group_id, image)) in group_ids.into_iter().zip(images).enumerate() {
+        let image = match config.document_dir {
+            Some(ref dir) => Path::new(dir).join(&image).to_string_lossy().into_owned(),
+            None => image,
+        };
+        let layout = match layouts {
+            Some(ref layouts) => layouts[row].clone(),
+            None => config.parameters.array_layout_file.clone(),
+        };
+
+        match groups.iter_mut().find(|g| g.group_id == group_id) {
+            Some(group) if group.array_layout_file != layout => {
+                return Err(Error::InvalidConfiguration(format!(
+                    "Tercen table row {}: group {} uses layouts {:?} and {:?}",
+                    row + 1,
+                    group_id,
+                    group.array_layout_file,
+                    layout
+                )));
+            }
+            Some(group) => group.images_list.push(image),
+            None => groups.push(GroupConfig {
+                group_id,
+                images_list: vec![image],
+                array_layout_file: layout,
+                ..config.parameters.clone()
+            }),
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Move the groups of the batch's Tercen input table into `imageGroups`
+///
+/// The `tercen` section is removed, so the effective configuration lists
+/// every group explicitly.
+pub fn expand_tercen_input(config: &mut BatchConfig) -> Result<()> {
+    if let Some(tercen) = config.tercen.take() {
+        let groups = tercen_image_groups(&tercen)?;
+        tracing::info!("Read {} image group(s) from {}", groups.len(), tercen.input_table);
+        config.image_groups.extend(groups);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OutputFormat;
+    use crate::io::table::create_table_writer;
+    use crate::types::SpotResult;
+
+    #[test]
+    fn test_image_groups_from_fixture() {
+        let config = TercenConfig {
+            input_table: "test/tercen/input_table.json".to_string(),
+            document_dir: Some("images".to_string()),
+            parameters: GroupConfig {
+                spot_pitch: 21.5,
+                ..GroupConfig::default()
+            },
+            ..TercenConfig::default()
+        };
+
+        let groups = tercen_image_groups(&config).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].group_id, "1");
+        assert_eq!(groups[0].images_list, ["images/W1_P1.tif", "images/W1_P2.tif"]);
+        assert_eq!(groups[0].array_layout_file, "layout_86312.txt");
+        assert_eq!(groups[1].group_id, "2");
+        assert_eq!(groups[1].images_list.len(), 1);
+        assert_eq!(groups[1].spot_pitch, 21.5);
+    }
+
+    #[test]
+    fn test_results_match_fixture() {
+        let row = |spot_id: &str, is_bad: bool| SpotResult {
+            is_reference: spot_id == "#REF",
+            grid_x: 240.5,
+            grid_y: f64::NAN,
+            is_bad,
+            rotation: 0.5,
+            image_name: "W1_P2".to_string(),
+            ..SpotResult::example("1", spot_id)
+        };
+        let table = TercenTable::from_records("spots", &[row("#REF", false), row("ABL1", true)]);
+
+        let path = std::env::temp_dir().join("pamsoft_tercen_results.json");
+        write_tercen_table(&path, &table).unwrap();
+        let written = read_tercen_table(&path).unwrap();
+        let expected = read_tercen_table("test/tercen/spot_results.json").unwrap();
+        assert_eq!(written, expected);
+        assert_eq!(written.column("segIsBad").unwrap().numbers().unwrap(), [0.0, 1.0]);
+        assert!(written.column("gridY").unwrap().numbers().unwrap()[0].is_nan());
+
+        // Streamed in two writes, the table is the same (named after the file)
+        let dir = std::env::temp_dir().join("pamsoft_tercen_streamed");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("spots.json");
+        let mut writer = create_table_writer::<SpotResult, _>(&path, OutputFormat::Tercen).unwrap();
+        writer.write(&[row("#REF", false)]).unwrap();
+        writer.write(&[row("ABL1", true)]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), serde_json::to_string(&table).unwrap());
+        assert!(!Path::new(&format!("{}.column0", path.display())).exists());
+    }
+}
@@ -3,20 +3,35 @@
 //! Image analysis library for processing PamGene array images.
 //! Provides grid detection, spot segmentation, and quantification.
 
+pub mod checkpoint;
 pub mod config;
 pub mod error;
+pub mod fft;
 pub mod grid;
+pub mod grid_detector;
+pub mod grid_model;
+pub mod grid_quality;
 pub mod advanced_grid;
 pub mod image_processing;
 pub mod io;
+pub mod manual;
+pub mod progress;
 pub mod quantification;
+pub mod schema;
 pub mod segmentation;
+pub mod server;
+pub mod single_run;
+pub mod sweep;
 pub mod advanced_segmentation;
 pub mod types;
 pub mod batch;
 
-pub use config::{GridParams, SegmentationMethod};
+pub use config::{
+    GridDetectionMethod, GridModelType, GridParams, GridQualityAction, IntensityNormalization,
+    IntensityThreshold, OutputFormat, RunMode, SegmentationMethod,
+};
 pub use error::{Error, Result};
+pub use manual::{apply_spot_overrides, read_spot_overrides, SpotOverride};
 pub use types::{ImageData, Spot, SpotResult, BatchConfig};
 
 /// Library version
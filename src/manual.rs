@@ -0,0 +1,203 @@
+use crate::config::OutputFormat;
+use crate::error::{Error, Result};
+use crate::io::table::delimited_reader;
+use crate::types::Spot;
+use serde::Deserialize;
+use std::path::Path;
+
+/// A manual correction of one spot
+///
+/// Unset fields keep the segmented value. The spot is marked `is_manual` and
+/// is not re-segmented afterwards.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpotOverride {
+    pub spot_id: String,
+    /// Grid row and column, required when the spot id occurs more than once (e.g. `#REF`)
+    pub row: Option<i32>,
+    pub col: Option<i32>,
+    /// Corrected center in pixels
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    /// Corrected diameter in pixels
+    pub diameter: Option<f64>,
+    pub is_bad: Option<bool>,
+    pub is_empty: Option<bool>,
+    /// Where the override came from, written to the `manualSource` output column
+    pub source: String,
+}
+
+/// One row of an override file, in the column names of the spot output
+#[derive(Deserialize)]
+struct OverrideRow {
+    #[serde(rename = "qntSpotID")]
+    spot_id: String,
+    #[serde(rename = "grdRow", default)]
+    row: Option<f64>,
+    #[serde(rename = "grdCol", default)]
+    col: Option<f64>,
+    #[serde(rename = "gridX", default)]
+    x: Option<f64>,
+    #[serde(rename = "gridY", default)]
+    y: Option<f64>,
+    #[serde(default)]
+    diameter: Option<f64>,
+    #[serde(rename = "segIsBad", default)]
+    is_bad: Option<f64>,
+    #[serde(rename = "segIsEmpty", default)]
+    is_empty: Option<f64>,
+}
+
+/// Read manual overrides from a CSV or TSV file
+///
+/// Columns are named as in the spot output (`qntSpotID`, `grdRow`, `grdCol`,
+/// `gridX`, `gridY`, `diameter`, `segIsBad`, `segIsEmpty`); only `qntSpotID`
+/// is required, empty cells keep the segmented value and other columns are
+/// ignored, so rows of a previous spot output can be edited and fed back.
+/// The source of each override is `<path>:<line>`.
+pub fn read_spot_overrides<P: AsRef<Path>>(path: P) -> Result<Vec<SpotOverride>> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy();
+    let format = OutputFormat::from_path(&name)
+        .filter(|format| format.is_delimited())
+        .ok_or_else(|| {
+            Error::InvalidConfiguration(format!(
+                "Manual override file {} must be a .csv or .tsv file",
+                name
+            ))
+        })?;
+
+    let mut reader = delimited_reader(path, format)?;
+    let headers = reader.headers()?.clone();
+    let mut overrides = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+        let row: OverrideRow = record.deserialize(Some(&headers))?;
+        overrides.push(SpotOverride {
+            spot_id: row.spot_id,
+            row: row.row.map(|r| r as i32),
+            col: row.col.map(|c| c as i32),
+            x: row.x,
+            y: row.y,
+            diameter: row.diameter,
+            is_bad: row.is_bad.map(|v| v != 0.0),
+            is_empty: row.is_empty.map(|v| v != 0.0),
+            source: format!("{}:{}", name, line),
+        });
+    }
+    Ok(overrides)
+}
+
+/// Apply manual overrides to segmented spots
+///
+/// A corrected center or diameter clears `is_bad` unless `segIsBad` is given.
+/// Fails if an override matches no spot, or several spots without naming the
+/// row and column.
+pub fn apply_spot_overrides(spots: &mut [Spot], overrides: &[SpotOverride]) -> Result<()> {
+    for spot_override in overrides {
+        let matches: Vec<usize> = spots
+            .iter()
+            .enumerate()
+            .filter(|(_, spot)| {
+                spot.id == spot_override.spot_id
+                    && spot_override.row.is_none_or(|row| row == spot.row)
+                    && spot_override.col.is_none_or(|col| col == spot.col)
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        let index = match matches[..] {
+            [index] => index,
+            [] => {
+                return Err(Error::InvalidConfiguration(format!(
+                    "{}: no spot {:?} in the layout",
+                    spot_override.source, spot_override.spot_id
+                )))
+            }
+            _ => {
+                return Err(Error::InvalidConfiguration(format!(
+                    "{}: spot {:?} occurs {} times, grdRow and grdCol are required",
+                    spot_override.source,
+                    spot_override.spot_id,
+                    matches.len()
+                )))
+            }
+        };
+
+        let spot = &mut spots[index];
+        let corrected = spot_override.x.is_some()
+            || spot_override.y.is_some()
+            || spot_override.diameter.is_some();
+        spot.grid_x = spot_override.x.unwrap_or(spot.grid_x);
+        spot.grid_y = spot_override.y.unwrap_or(spot.grid_y);
+        spot.diameter = spot_override.diameter.unwrap_or(spot.diameter);
+        if corrected {
+            spot.is_bad = false;
+        }
+        spot.is_bad = spot_override.is_bad.unwrap_or(spot.is_bad);
+        spot.is_empty = spot_override.is_empty.unwrap_or(spot.is_empty);
+        spot.is_manual = true;
+        spot.manual_source = Some(spot_override.source.clone());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spot(id: &str, row: i32, col: i32) -> Spot {
+        Spot {
+            id: id.to_string(),
+            row,
+            col,
+            is_reference: id == "#REF",
+            x_offset: 0.0,
+            y_offset: 0.0,
+            x_fixed: 0.0,
+            y_fixed: 0.0,
+            grid_x: 100.0,
+            grid_y: 100.0,
+            diameter: 0.0,
+            is_manual: false,
+            manual_source: None,
+            is_bad: true,
+            is_empty: true,
+            rotation: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_overrides_from_file() {
+        let path = std::env::temp_dir().join("pamsoft_manual_overrides.csv");
+        std::fs::write(
+            &path,
+            "qntSpotID,grdRow,grdCol,gridX,gridY,diameter,segIsBad,segIsEmpty\n\
+             ABL1,,,120.5,98,14,,0\n\
+             #REF,1,2,,,,1,\n",
+        )
+        .unwrap();
+        let overrides = read_spot_overrides(&path).unwrap();
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(overrides[1].source, format!("{}:3", path.display()));
+
+        let mut spots = vec![spot("#REF", 1, 1), spot("#REF", 1, 2), spot("ABL1", 2, 1)];
+        apply_spot_overrides(&mut spots, &overrides).unwrap();
+
+        // Corrected position: no longer bad, explicitly not empty
+        assert_eq!((spots[2].grid_x, spots[2].grid_y, spots[2].diameter), (120.5, 98.0, 14.0));
+        assert!(spots[2].is_manual && !spots[2].is_bad && !spots[2].is_empty);
+        assert_eq!(spots[2].manual_source, Some(format!("{}:2", path.display())));
+        // Flags only: the position is kept
+        assert!(spots[1].is_manual && spots[1].is_bad);
+        assert_eq!(spots[1].grid_x, 100.0);
+        assert!(!spots[0].is_manual);
+
+        // #REF occurs twice, so the row and column are required
+        let ambiguous = SpotOverride {
+            spot_id: "#REF".to_string(),
+            ..SpotOverride::default()
+        };
+        assert!(apply_spot_overrides(&mut spots, &[ambiguous]).is_err());
+    }
+}
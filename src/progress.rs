@@ -0,0 +1,165 @@
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Structured progress event of a batch run
+///
+/// Serialized as one JSON object per line, tagged by `event`
+/// (e.g. `{"event":"group_finished","groupId":"3",...}`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum ProgressEvent {
+    BatchStarted {
+        total_groups: usize,
+        workers: usize,
+    },
+    /// A worker picked up the group
+    GroupStarted { group_id: String, index: usize },
+    /// A processing stage of a group finished
+    StageFinished {
+        group_id: String,
+        stage: String,
+        elapsed_ms: f64,
+    },
+    GroupFinished {
+        group_id: String,
+        index: usize,
+        /// Distinct spots quantified
+        spots: usize,
+        /// Result rows (spots times images in series mode)
+        rows: usize,
+        elapsed_ms: f64,
+        completed: usize,
+        total: usize,
+        eta_seconds: Option<f64>,
+    },
+    GroupFailed {
+        group_id: String,
+        index: usize,
+        stage: String,
+        error: String,
+        message: String,
+        completed: usize,
+        total: usize,
+        eta_seconds: Option<f64>,
+    },
+    BatchFinished {
+        succeeded: usize,
+        failed: usize,
+        elapsed_ms: f64,
+    },
+}
+
+/// Receiver of progress events
+///
+/// Events are delivered from worker threads (`GroupStarted`, `StageFinished`)
+/// as well as the thread running the batch, so observers must be thread safe.
+pub trait ProgressObserver: Send + Sync {
+    fn on_event(&self, event: &ProgressEvent);
+}
+
+impl<F> ProgressObserver for F
+where
+    F: Fn(&ProgressEvent) + Send + Sync,
+{
+    fn on_event(&self, event: &ProgressEvent) {
+        self(event)
+    }
+}
+
+/// Observer that ignores all events
+pub struct NoProgress;
+
+impl ProgressObserver for NoProgress {
+    fn on_event(&self, _event: &ProgressEvent) {}
+}
+
+/// Writes events as JSON lines, flushed after every event
+pub struct JsonLinesProgress {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLinesProgress {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Write events to a file (`-` for stdout)
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if path == Path::new("-") {
+            return Ok(Self::new(std::io::stdout()));
+        }
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl ProgressObserver for JsonLinesProgress {
+    fn on_event(&self, event: &ProgressEvent) {
+        let mut writer = self.writer.lock().unwrap();
+        let written = serde_json::to_writer(&mut *writer, event)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writer.write_all(b"\n"))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            tracing::warn!("Failed to write progress event: {}", e);
+        }
+    }
+}
+
+/// Remaining time estimate from the mean wall time per completed group
+///
+/// Wall time already reflects the number of workers. `None` before the first
+/// group completes and once all groups are done.
+pub fn estimate_remaining(started: Instant, completed: usize, total: usize) -> Option<f64> {
+    if completed == 0 || completed >= total {
+        return None;
+    }
+    let per_group = started.elapsed().as_secs_f64() / completed as f64;
+    Some(per_group * (total - completed) as f64)
+}
+
+/// Duration in milliseconds, as reported in events
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json_format() {
+        let event = ProgressEvent::GroupFinished {
+            group_id: "7".to_string(),
+            index: 2,
+            spots: 100,
+            rows: 300,
+            elapsed_ms: 12.5,
+            completed: 3,
+            total: 10,
+            eta_seconds: None,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], "group_finished");
+        assert_eq!(json["groupId"], "7");
+        assert_eq!(json["etaSeconds"], serde_json::Value::Null);
+
+        let parsed: ProgressEvent = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, event);
+    }
+
+    #[test]
+    fn test_estimate_remaining_bounds() {
+        let started = Instant::now();
+        assert_eq!(estimate_remaining(started, 0, 4), None);
+        assert_eq!(estimate_remaining(started, 4, 4), None);
+        assert!(estimate_remaining(started, 1, 4).unwrap() >= 0.0);
+    }
+}
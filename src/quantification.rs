@@ -1,30 +1,113 @@
 use crate::config::GridParams;
 use crate::error::Result;
-use crate::image_processing::normalize_image;
-use crate::types::{ImageData, Spot, SpotResult};
+use crate::image_processing::{normalize_intensity, register_image, Registration};
+use crate::types::{deserialize_flag, serialize_flag, ImageData, Spot, SpotResult};
+use serde::{Deserialize, Serialize};
+use ndarray::{s, Array2};
+use rayon::prelude::*;
+
+/// Rotation search step (degrees) for drift registration, matching the grdRotation spacing
+const DRIFT_ROTATION_STEP: f64 = 0.25;
 
 /// Quantify spots in image
+/// Spots are processed in parallel; results keep the order of `spots`
 pub fn quantify_spots(
     image: &ImageData,
     spots: &[Spot],
     group_id: &str,
     _params: &GridParams,
 ) -> Result<Vec<SpotResult>> {
-    let normalized = normalize_image(&image.data);
-    let mut results = Vec::new();
+    Ok(spots
+        .par_iter()
+        .map(|spot| quantify_single_spot(image, spot, group_id))
+        .collect())
+}
+
+/// Estimated drift of one series image relative to the grid image
+#[derive(Debug, Clone)]
+pub struct ImageShift {
+    pub image_name: String,
+    pub registration: Registration,
+}
+
+/// Quantify every image of a series using the spots found on `grid_image`
+///
+/// With `drift_correction` enabled each image is first registered to the grid
+/// image and spot coordinates are moved by the estimated shift and rotation.
+/// Returns the results for all images (in input order) and the per-image shifts.
+pub fn quantify_series(
+    images: &[ImageData],
+    grid_image: &ImageData,
+    spots: &[Spot],
+    group_id: &str,
+    params: &GridParams,
+) -> Result<(Vec<SpotResult>, Vec<ImageShift>)> {
+    let center = (
+        (grid_image.width as f64 - 1.0) / 2.0,
+        (grid_image.height as f64 - 1.0) / 2.0,
+    );
+
+    // Images are independent; collect keeps input order
+    let per_image: Vec<(Vec<SpotResult>, ImageShift)> = images
+        .par_iter()
+        .map(|image| quantify_series_image(image, grid_image, spots, center, group_id, params))
+        .collect::<Result<_>>()?;
+
+    let mut results = Vec::with_capacity(images.len() * spots.len());
+    let mut shifts = Vec::with_capacity(images.len());
+    for (image_results, shift) in per_image {
+        results.extend(image_results);
+        shifts.push(shift);
+    }
+
+    Ok((results, shifts))
+}
+
+/// Register (optionally) and quantify one image of a series
+fn quantify_series_image(
+    image: &ImageData,
+    grid_image: &ImageData,
+    spots: &[Spot],
+    center: (f64, f64),
+    group_id: &str,
+    params: &GridParams,
+) -> Result<(Vec<SpotResult>, ImageShift)> {
+    let registration = if params.drift_correction && image.name != grid_image.name {
+        register_image(grid_image, image, params.drift_max_rotation, DRIFT_ROTATION_STEP)?
+    } else {
+        Registration::default()
+    };
+
+    let moved: Vec<Spot> = spots
+        .iter()
+        .map(|spot| {
+            let mut spot = spot.clone();
+            let (x, y) = registration.apply(spot.grid_x, spot.grid_y, center);
+            spot.grid_x = x;
+            spot.grid_y = y;
+            spot.rotation += registration.rotation;
+            spot
+        })
+        .collect();
 
-    for spot in spots {
-        let result = quantify_single_spot(image, &normalized, spot, group_id);
-        results.push(result);
+    let mut results = quantify_spots(image, &moved, group_id, params)?;
+    for result in &mut results {
+        result.drift_x = registration.dx;
+        result.drift_y = registration.dy;
+        result.drift_rotation = registration.rotation;
+        result.drift_peak = registration.peak;
     }
+    let shift = ImageShift {
+        image_name: image.name.clone(),
+        registration,
+    };
 
-    Ok(results)
+    Ok((results, shift))
 }
 
 /// Quantify a single spot
 fn quantify_single_spot(
     image: &ImageData,
-    normalized: &ndarray::Array2<f64>,
     spot: &Spot,
     group_id: &str,
 ) -> SpotResult {
@@ -39,47 +122,75 @@ fn quantify_single_spot(
         grid_x: spot.grid_x,
         grid_y: spot.grid_y,
         diameter: spot.diameter,
-        is_manual: if spot.is_manual { 1 } else { 0 },
-        is_bad: if spot.is_bad { 1 } else { 0 },
-        is_empty: if spot.is_empty { 1 } else { 0 },
+        is_manual: spot.is_manual,
+        is_bad: spot.is_bad,
+        is_empty: spot.is_empty,
         rotation: spot.rotation,
         image_name: image.name.clone(),
+        manual_source: spot.manual_source.clone().unwrap_or_default(),
+        grid_quality: f64::NAN,
+        needs_review: false,
+        drift_x: 0.0,
+        drift_y: 0.0,
+        drift_rotation: 0.0,
+        drift_peak: 0.0,
     }
 }
 
-/// Compute spot intensity statistics
+/// Compute spot intensity statistics, normalized as in [`compute_spots_statistics`]
 pub fn compute_spot_statistics(
     image: &ImageData,
     spot: &Spot,
+    params: &GridParams,
 ) -> Option<SpotStatistics> {
-    if spot.is_bad {
-        return None;
-    }
+    compute_spots_statistics(image, std::slice::from_ref(spot), params)
+        .pop()
+        .flatten()
+}
+
+/// Statistics for all spots, normalizing the image once
+/// Spots are processed in parallel; results keep the order of `spots`
+pub fn compute_spots_statistics(
+    image: &ImageData,
+    spots: &[Spot],
+    params: &GridParams,
+) -> Vec<Option<SpotStatistics>> {
+    let normalized = normalize_intensity(&image.data, params);
 
-    let normalized = normalize_image(&image.data);
+    spots
+        .par_iter()
+        .map(|spot| {
+            if spot.is_bad {
+                None
+            } else {
+                spot_statistics(&normalized, spot)
+            }
+        })
+        .collect()
+}
 
+/// Statistics of the pixels inside the spot circle, read from a spot-local view
+fn spot_statistics(normalized: &Array2<f64>, spot: &Spot) -> Option<SpotStatistics> {
+    let (height, width) = normalized.dim();
     let radius = spot.diameter / 2.0;
     let x = spot.grid_x;
     let y = spot.grid_y;
 
-    let mut intensities = Vec::new();
-
-    let x_start = ((x - radius).max(0.0) as usize).min(image.width);
-    let x_end = ((x + radius).ceil() as usize).min(image.width);
-    let y_start = ((y - radius).max(0.0) as usize).min(image.height);
-    let y_end = ((y + radius).ceil() as usize).min(image.height);
+    let x_start = ((x - radius).max(0.0) as usize).min(width);
+    let x_end = ((x + radius).ceil() as usize).min(width);
+    let y_start = ((y - radius).max(0.0) as usize).min(height);
+    let y_end = ((y + radius).ceil() as usize).min(height);
 
-    for yi in y_start..y_end {
-        for xi in x_start..x_end {
-            let dx = xi as f64 - x;
-            let dy = yi as f64 - y;
-            let dist = (dx * dx + dy * dy).sqrt();
-
-            if dist <= radius {
-                intensities.push(normalized[[yi, xi]]);
-            }
-        }
-    }
+    let window = normalized.slice(s![y_start..y_end.max(y_start), x_start..x_end.max(x_start)]);
+    let intensities: Vec<f64> = window
+        .indexed_iter()
+        .filter(|&((wy, wx), _)| {
+            let dx = (x_start + wx) as f64 - x;
+            let dy = (y_start + wy) as f64 - y;
+            (dx * dx + dy * dy).sqrt() <= radius
+        })
+        .map(|(_, &v)| v)
+        .collect();
 
     if intensities.is_empty() {
         return None;
@@ -101,7 +212,7 @@ pub fn compute_spot_statistics(
 
     let mut sorted = intensities.clone();
     sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    let median = if sorted.len() % 2 == 0 {
+    let median = if sorted.len().is_multiple_of(2) {
         (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.0
     } else {
         sorted[sorted.len() / 2]
@@ -130,9 +241,234 @@ pub struct SpotStatistics {
     pub pixel_count: usize,
 }
 
+/// One row of the MATLAB quantification table (output_quant.txt)
+///
+/// Intensities are raw sensor counts. Signal pixels lie inside the spot circle,
+/// background pixels in the annulus from `(1 + bg_offset) * radius` out to the
+/// neighbouring spots (`spot_pitch - radius`). Serialized under the MATLAB
+/// column names, preceded by the spot ID.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantRecord {
+    #[serde(rename = "qntSpotID", default)]
+    pub spot_id: String,
+    #[serde(rename = "Row")]
+    pub row: i32,
+    #[serde(rename = "Column")]
+    pub col: i32,
+    #[serde(rename = "Mean_SigmBg")]
+    pub mean_sigm_bg: f64,
+    #[serde(rename = "Median_SigmBg")]
+    pub median_sigm_bg: f64,
+    #[serde(rename = "Rse_MedianSigmBg")]
+    pub rse_median_sigm_bg: f64,
+    #[serde(rename = "Mean_Signal")]
+    pub mean_signal: f64,
+    #[serde(rename = "Median_Signal")]
+    pub median_signal: f64,
+    #[serde(rename = "Std_Signal")]
+    pub std_signal: f64,
+    #[serde(rename = "Sum_Signal")]
+    pub sum_signal: f64,
+    #[serde(rename = "Rse_Signal")]
+    pub rse_signal: f64,
+    #[serde(rename = "Mean_Background")]
+    pub mean_background: f64,
+    #[serde(rename = "Median_Background")]
+    pub median_background: f64,
+    #[serde(rename = "Std_Background")]
+    pub std_background: f64,
+    #[serde(rename = "Sum_Background")]
+    pub sum_background: f64,
+    #[serde(rename = "Rse_Background")]
+    pub rse_background: f64,
+    /// Fraction of signal pixels at or above `saturation_limit`
+    #[serde(rename = "Signal_Saturation")]
+    pub signal_saturation: f64,
+    /// Fraction of spot pixels excluded as outliers (none are excluded yet)
+    #[serde(rename = "Fraction_Ignored")]
+    pub fraction_ignored: f64,
+    #[serde(rename = "Diameter")]
+    pub diameter: f64,
+    #[serde(rename = "X_Position")]
+    pub x_position: f64,
+    #[serde(rename = "Y_Position")]
+    pub y_position: f64,
+    /// Distance between spot and grid position, relative to the pitch
+    #[serde(rename = "Position_Offset")]
+    pub position_offset: f64,
+    #[serde(
+        rename = "Empty_Spot",
+        serialize_with = "serialize_flag",
+        deserialize_with = "deserialize_flag"
+    )]
+    pub is_empty: bool,
+    #[serde(
+        rename = "Bad_Spot",
+        serialize_with = "serialize_flag",
+        deserialize_with = "deserialize_flag"
+    )]
+    pub is_bad: bool,
+    /// Bad spot quantified with the default circle at its grid position
+    #[serde(
+        rename = "Replaced_Spot",
+        serialize_with = "serialize_flag",
+        deserialize_with = "deserialize_flag"
+    )]
+    pub is_replaced: bool,
+    #[serde(rename = "ImageName")]
+    pub image_name: String,
+}
+
+/// Segmented spot to quantify, with the grid position it was found from
+#[derive(Debug, Clone)]
+pub struct QuantSpot {
+    pub spot: Spot,
+    pub grid_position: (f64, f64),
+    pub replaced: bool,
+}
+
+/// MATLAB quantification records of all spots in one image
+/// Spots are processed in parallel; records keep the order of `spots`
+pub fn quantify_records(image: &ImageData, spots: &[QuantSpot], params: &GridParams) -> Vec<QuantRecord> {
+    spots
+        .par_iter()
+        .map(|spot| quantify_record(image, spot, params))
+        .collect()
+}
+
+/// MATLAB quantification record of one spot
+pub fn quantify_record(image: &ImageData, quant_spot: &QuantSpot, params: &GridParams) -> QuantRecord {
+    let spot = &quant_spot.spot;
+    let (height, width) = image.data.dim();
+    let radius = spot.diameter / 2.0;
+    let bg_inner = radius * (1.0 + params.bg_offset);
+    let bg_outer = (params.spot_pitch - radius).max(bg_inner + 1.0);
+
+    let x_start = ((spot.grid_x - bg_outer).max(0.0) as usize).min(width);
+    let x_end = ((spot.grid_x + bg_outer).ceil().max(0.0) as usize + 1).min(width);
+    let y_start = ((spot.grid_y - bg_outer).max(0.0) as usize).min(height);
+    let y_end = ((spot.grid_y + bg_outer).ceil().max(0.0) as usize + 1).min(height);
+
+    let window = image.data.slice(s![y_start..y_end.max(y_start), x_start..x_end.max(x_start)]);
+    let mut signal = Vec::new();
+    let mut background = Vec::new();
+    for ((wy, wx), &value) in window.indexed_iter() {
+        let dx = (x_start + wx) as f64 - spot.grid_x;
+        let dy = (y_start + wy) as f64 - spot.grid_y;
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance <= radius {
+            signal.push(value as f64);
+        } else if distance >= bg_inner && distance <= bg_outer {
+            background.push(value as f64);
+        }
+    }
+
+    let saturated = signal.iter().filter(|&&v| v >= params.saturation_limit).count();
+    let signal_saturation = if signal.is_empty() {
+        f64::NAN
+    } else {
+        saturated as f64 / signal.len() as f64
+    };
+    let signal = SampleStatistics::new(signal);
+    let background = SampleStatistics::new(background);
+
+    let median_sigm_bg = signal.median - background.median;
+    let (gx, gy) = quant_spot.grid_position;
+    let offset = ((spot.grid_x - gx).powi(2) + (spot.grid_y - gy).powi(2)).sqrt();
+
+    QuantRecord {
+        spot_id: spot.id.clone(),
+        row: spot.row,
+        col: spot.col,
+        mean_sigm_bg: signal.mean - background.mean,
+        median_sigm_bg,
+        rse_median_sigm_bg: (signal.standard_error().powi(2) + background.standard_error().powi(2))
+            .sqrt()
+            / median_sigm_bg.abs(),
+        mean_signal: signal.mean,
+        median_signal: signal.median,
+        std_signal: signal.std,
+        sum_signal: signal.sum,
+        rse_signal: signal.relative_standard_error(),
+        mean_background: background.mean,
+        median_background: background.median,
+        std_background: background.std,
+        sum_background: background.sum,
+        rse_background: background.relative_standard_error(),
+        signal_saturation,
+        fraction_ignored: 0.0,
+        diameter: spot.diameter,
+        x_position: spot.grid_x,
+        y_position: spot.grid_y,
+        position_offset: offset / params.spot_pitch,
+        is_empty: spot.is_empty,
+        is_bad: spot.is_bad,
+        is_replaced: quant_spot.replaced,
+        image_name: image.name.clone(),
+    }
+}
+
+/// Mean, median, sample standard deviation and sum of raw pixel values
+struct SampleStatistics {
+    count: usize,
+    mean: f64,
+    median: f64,
+    std: f64,
+    sum: f64,
+}
+
+impl SampleStatistics {
+    fn new(mut values: Vec<f64>) -> Self {
+        let count = values.len();
+        if count == 0 {
+            return Self {
+                count,
+                mean: f64::NAN,
+                median: f64::NAN,
+                std: f64::NAN,
+                sum: 0.0,
+            };
+        }
+
+        let sum: f64 = values.iter().sum();
+        let mean = sum / count as f64;
+        // MATLAB std normalizes by n - 1
+        let std = if count > 1 {
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+
+        values.sort_by(|a, b| a.total_cmp(b));
+        let median = if count.is_multiple_of(2) {
+            (values[count / 2 - 1] + values[count / 2]) / 2.0
+        } else {
+            values[count / 2]
+        };
+
+        Self {
+            count,
+            mean,
+            median,
+            std,
+            sum,
+        }
+    }
+
+    fn standard_error(&self) -> f64 {
+        self.std / (self.count as f64).sqrt()
+    }
+
+    /// Standard error of the mean relative to the mean
+    fn relative_standard_error(&self) -> f64 {
+        self.standard_error() / self.mean
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::IntensityNormalization;
     use ndarray::Array2;
 
     #[test]
@@ -153,18 +489,97 @@ mod tests {
             grid_y: 50.0,
             diameter: 10.0,
             is_manual: false,
+            manual_source: None,
             is_bad: false,
             is_empty: false,
             rotation: 0.0,
         };
 
-        let normalized = normalize_image(&image.data);
-        let result = quantify_single_spot(&image, &normalized, &spot, "group1");
+        let result = quantify_single_spot(&image, &spot, "group1");
 
         assert_eq!(result.spot_id, "A1");
         assert_eq!(result.group_id, "group1");
         assert!(result.is_reference);
-        assert_eq!(result.is_bad, 0);
+        assert!(!result.is_bad);
+    }
+
+    #[test]
+    fn test_quantify_series_without_drift_keeps_positions() {
+        let image = ImageData::new(Array2::from_elem((40, 40), 10u16), "img".to_string());
+        let spot = Spot {
+            id: "A1".to_string(),
+            row: 1,
+            col: 1,
+            is_reference: false,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            x_fixed: 0.0,
+            y_fixed: 0.0,
+            grid_x: 12.0,
+            grid_y: 20.0,
+            diameter: 6.0,
+            is_manual: false,
+            manual_source: None,
+            is_bad: false,
+            is_empty: false,
+            rotation: 0.0,
+        };
+        let images = vec![image.clone(), image.clone()];
+
+        let (results, shifts) =
+            quantify_series(&images, &image, &[spot], "g", &GridParams::default()).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(shifts.len(), 2);
+        assert!(results.iter().all(|r| r.grid_x == 12.0 && r.grid_y == 20.0));
+    }
+
+    #[test]
+    fn test_quantify_series_reports_drift() {
+        // Irregular blobs, so the registration has a single answer
+        let blobs = [(14.0, 20.0), (40.0, 12.0), (30.0, 44.0), (50.0, 50.0), (22.0, 36.0)];
+        let render = |name: &str, dx: f64, dy: f64| {
+            let data = Array2::from_shape_fn((64, 64), |(y, x)| {
+                let peak = blobs
+                    .iter()
+                    .map(|&(bx, by)| (-((x as f64 - bx - dx).powi(2) + (y as f64 - by - dy).powi(2)) / 8.0).exp())
+                    .fold(0.0, f64::max);
+                (100.0 + 2000.0 * peak) as u16
+            });
+            ImageData::new(data, name.to_string())
+        };
+        let grid_image = render("t0", 0.0, 0.0);
+        let images = vec![grid_image.clone(), render("t1", 3.0, -2.0)];
+        let spot = Spot {
+            id: "A1".to_string(),
+            row: 1,
+            col: 1,
+            is_reference: false,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            x_fixed: 0.0,
+            y_fixed: 0.0,
+            grid_x: 30.0,
+            grid_y: 44.0,
+            diameter: 6.0,
+            is_manual: false,
+            manual_source: None,
+            is_bad: false,
+            is_empty: false,
+            rotation: 0.0,
+        };
+        let params = GridParams {
+            drift_correction: true,
+            ..GridParams::default()
+        };
+
+        let (results, _) = quantify_series(&images, &grid_image, &[spot], "g", &params).unwrap();
+
+        assert_eq!((results[0].drift_x, results[0].drift_y, results[0].drift_peak), (0.0, 0.0, 0.0));
+        let shifted = &results[1];
+        assert!((shifted.drift_x - 3.0).abs() < 0.5 && (shifted.drift_y + 2.0).abs() < 0.5, "{:?}", shifted);
+        assert!(shifted.drift_peak > 0.0);
+        assert!((shifted.grid_x - 33.0).abs() < 0.5 && (shifted.grid_y - 42.0).abs() < 0.5);
     }
 
     #[test]
@@ -193,15 +608,74 @@ mod tests {
             grid_y: 50.0,
             diameter: 8.0,
             is_manual: false,
+            manual_source: None,
             is_bad: false,
             is_empty: false,
             rotation: 0.0,
         };
 
-        let stats = compute_spot_statistics(&image, &spot).unwrap();
+        let params = GridParams {
+            intensity_normalization: IntensityNormalization::Percentile,
+            ..GridParams::default()
+        };
+        let stats = compute_spot_statistics(&image, &spot, &params).unwrap();
 
         assert!(stats.mean > 0.0);
         assert!(stats.sum > 0.0);
         assert!(stats.pixel_count > 0);
+
+        // Batch version normalizes once and keeps spot order
+        let mut dim = spot.clone();
+        dim.grid_x = 20.0;
+        let all = compute_spots_statistics(&image, &[spot, dim], &params);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].as_ref().unwrap().sum, stats.sum);
+        assert_eq!(all[1].as_ref().unwrap().max, 0.0);
+    }
+
+    #[test]
+    fn test_quantify_record_signal_and_background() {
+        let mut data = Array2::from_elem((60, 60), 100u16);
+        for y in 26..35 {
+            for x in 26..35 {
+                data[[y, x]] = 1100;
+            }
+        }
+        let image = ImageData::new(data, "img".to_string());
+        let spot = Spot {
+            id: "A1".to_string(),
+            row: 1,
+            col: 1,
+            is_reference: false,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            x_fixed: 0.0,
+            y_fixed: 0.0,
+            grid_x: 30.0,
+            grid_y: 30.0,
+            diameter: 8.0,
+            is_manual: false,
+            manual_source: None,
+            is_bad: false,
+            is_empty: false,
+            rotation: 0.0,
+        };
+        let quant_spot = QuantSpot {
+            spot,
+            grid_position: (30.0, 32.15),
+            replaced: false,
+        };
+        let params = GridParams {
+            saturation_limit: 1100.0,
+            ..GridParams::default()
+        };
+
+        let record = quantify_record(&image, &quant_spot, &params);
+        assert_eq!(record.median_signal, 1100.0);
+        assert_eq!(record.median_background, 100.0);
+        assert_eq!(record.median_sigm_bg, 1000.0);
+        assert_eq!(record.std_background, 0.0);
+        assert_eq!(record.signal_saturation, 1.0);
+        assert!((record.position_offset - 0.1).abs() < 1e-12);
     }
 }
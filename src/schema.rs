@@ -0,0 +1,493 @@
+use crate::config::{
+    FailurePolicy, GridDetectionMethod, GridModelType, GridQualityAction, IntensityNormalization,
+    OutputFormat, OutputOrder, SegmentationMethod,
+};
+use crate::error::{Error, Result};
+use crate::segmentation::SegmenterRegistry;
+use crate::sweep::sweep_values;
+use crate::types::{BatchConfig, GroupConfig, SweepConfig};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Prefixes of MATLAB parameter names (prpLargeDisk, grdSpotPitch, ...)
+const MATLAB_PREFIXES: [&str; 6] = ["prp", "grd", "seg", "sqc", "qnt", "dbg"];
+
+/// `grdUseImage` values implemented here
+const USE_IMAGE_OPTIONS: [&str; 2] = ["first", "last"];
+
+/// Other `grdUseImage` values of MATLAB, besides `<exposure>_<cycle>`
+const MATLAB_USE_IMAGE_OPTIONS: [&str; 2] = ["firstlast", "all"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,   // The batch cannot run with this value
+    Warning, // The value is ignored or replaced
+}
+
+/// A problem found in a batch configuration, located by JSON path
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigIssue {
+    /// e.g. `$.imageGroups[2].segEdgeSensitivity`
+    pub path: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{} at {}: {}", severity, self.path, self.message)
+    }
+}
+
+/// Collects issues for one configuration
+struct Issues(Vec<ConfigIssue>);
+
+impl Issues {
+    fn error(&mut self, path: &str, message: impl Into<String>) {
+        self.push(path, Severity::Error, message.into());
+    }
+
+    fn warning(&mut self, path: &str, message: impl Into<String>) {
+        self.push(path, Severity::Warning, message.into());
+    }
+
+    fn push(&mut self, path: &str, severity: Severity, message: String) {
+        self.0.push(ConfigIssue {
+            path: path.to_string(),
+            severity,
+            message,
+        });
+    }
+
+    /// Record an error if an optional enum-like value does not parse
+    fn parses<T: std::str::FromStr<Err = Error>>(&mut self, path: &str, value: Option<&str>) {
+        if let Some(Err(e)) = value.map(str::parse::<T>) {
+            self.error(path, e.to_string());
+        }
+    }
+}
+
+/// Check a batch configuration, returning every problem at once
+///
+/// Errors make the batch unusable; warnings point at values that are ignored
+/// or replaced by a default.
+pub fn validate_batch_config(config: &BatchConfig) -> Vec<ConfigIssue> {
+    let mut issues = Issues(Vec::new());
+
+    let sweep_mode = config.mode.eq_ignore_ascii_case("sweep");
+    let summary_file = config.sweep.as_ref().and_then(|sweep| sweep.summary_file.as_ref());
+    if config.output_file.is_empty() && !(sweep_mode && summary_file.is_some()) {
+        issues.error("$.outputFile", "outputFile is required");
+    }
+    if config.num_workers == 0 {
+        issues.warning("$.numWorkers", "0 workers, using 1");
+    }
+    issues.parses::<OutputFormat>("$.outputFormat", config.output_format.as_deref());
+    issues.parses::<OutputOrder>("$.outputOrder", config.output_order.as_deref());
+    issues.parses::<FailurePolicy>("$.onGroupError", config.on_group_error.as_deref());
+    if config.image_groups.is_empty() {
+        issues.warning("$.imageGroups", "no image groups");
+    }
+
+    let mut seen = HashSet::new();
+    for (index, group) in config.image_groups.iter().enumerate() {
+        let path = format!("$.imageGroups[{}]", index);
+        if !group.group_id.is_empty() && !seen.insert(group.group_id.as_str()) {
+            issues.error(
+                &format!("{}.groupId", path),
+                format!("duplicate groupId {:?}", group.group_id),
+            );
+        }
+        validate_group(&mut issues, &path, group);
+    }
+
+    match config.sweep {
+        Some(ref sweep) => validate_sweep(&mut issues, config, sweep),
+        None if sweep_mode => issues.error("$.sweep", "sweep parameters are required in sweep mode"),
+        None => {}
+    }
+
+    issues.0
+}
+
+fn validate_sweep(issues: &mut Issues, config: &BatchConfig, sweep: &SweepConfig) {
+    if sweep.parameters.is_empty() {
+        issues.error("$.sweep.parameters", "no parameters to sweep");
+    }
+
+    let batch_keys: HashSet<String> = serde_json::to_value(config)
+        .ok()
+        .and_then(|value| value.as_object().map(|batch| batch.keys().cloned().collect()))
+        .unwrap_or_default();
+    for (key, values) in &sweep.parameters {
+        let path = format!("$.sweep.parameters.{}", key);
+        if batch_keys.contains(key) || ["groupId", "imageslist"].contains(&key.as_str()) {
+            issues.error(&path, "only image group parameters can be swept");
+            continue;
+        }
+        let values = match sweep_values(values) {
+            Ok(values) => values,
+            Err(e) => {
+                issues.error(&path, e.to_string());
+                continue;
+            }
+        };
+        // Check each value as that parameter of the first group
+        let Some(group) = config.image_groups.first() else {
+            continue;
+        };
+        for value in values {
+            let group = match override_group(group, &[(key.clone(), value.clone())]) {
+                Ok(group) => group,
+                Err(e) => {
+                    issues.error(&path, format!("invalid value {}: {}", value, e));
+                    continue;
+                }
+            };
+            let field = format!("$.{}", key);
+            for issue in validate_group_config(&group) {
+                if issue.path == field && issue.severity == Severity::Error {
+                    issues.error(&path, format!("invalid value {}: {}", value, issue.message));
+                }
+            }
+        }
+    }
+}
+
+/// Check a single image group; paths are relative to the group (`$.segMethod`)
+pub fn validate_group_config(group: &GroupConfig) -> Vec<ConfigIssue> {
+    let mut issues = Issues(Vec::new());
+    validate_group(&mut issues, "$", group);
+    issues.0
+}
+
+fn validate_group(issues: &mut Issues, path: &str, group: &GroupConfig) {
+    let at = |key: &str| format!("{}.{}", path, key);
+
+    if group.group_id.is_empty() {
+        issues.error(&at("groupId"), "groupId is required");
+    }
+    if group.images_list.is_empty() {
+        issues.error(&at("imageslist"), "at least one image is required");
+    }
+    if group.array_layout_file.is_empty() {
+        issues.error(&at("arraylayoutfile"), "array layout file is required");
+    }
+
+    match group.edge_sensitivity[..] {
+        [low, high] if low > high => issues.error(
+            &at("segEdgeSensitivity"),
+            format!("low ({}) is above high ({})", low, high),
+        ),
+        [_, _] => {}
+        _ => issues.error(
+            &at("segEdgeSensitivity"),
+            format!("expected [low, high], got {} value(s)", group.edge_sensitivity.len()),
+        ),
+    }
+
+    if !(group.min_diameter > 0.0 && group.min_diameter < group.max_diameter) {
+        issues.error(
+            &at("sqcMinDiameter"),
+            format!(
+                "must be positive and below sqcMaxDiameter ({})",
+                group.max_diameter
+            ),
+        );
+    }
+    for (key, value) in [
+        ("sqcMaxPositionOffset", group.max_position_offset),
+        ("sqcMaxPositionOffsetRefs", group.max_position_offset_refs),
+        ("grdSearchDiameter", group.search_diameter),
+    ] {
+        if value <= 0.0 {
+            issues.error(&at(key), "must be positive");
+        }
+    }
+    for (key, value) in [
+        ("sqcMinSnr", group.min_snr),
+        ("segBgOffset", group.bg_offset),
+        ("prpLargeDisk", group.large_disk),
+        ("prpSmallDisk", group.small_disk),
+    ] {
+        if value < 0.0 {
+            issues.error(&at(key), "must not be negative");
+        }
+    }
+    if !(group.area_size > 0.0 && group.area_size <= 1.0) {
+        issues.error(&at("segAreaSize"), "must be in (0, 1]");
+    }
+    if group.spot_pitch < 0.0 {
+        issues.error(&at("grdSpotPitch"), "must not be negative (0 detects it)");
+    }
+    if !(group.spot_size > 0.0 && group.spot_size <= 1.0) {
+        issues.error(&at("grdSpotSize"), "must be in (0, 1]");
+    }
+    if group.rotation.is_empty() {
+        issues.error(&at("grdRotation"), "at least one rotation is required");
+    }
+    if group.saturation_limit < 0.0 {
+        issues.error(&at("qntSaturationLimit"), "must not be negative");
+    }
+
+    match group.seg_method.parse::<SegmentationMethod>() {
+        Ok(method) => {
+            let registry = SegmenterRegistry::shared();
+            if registry.get(method.name()).is_err() {
+                issues.error(
+                    &at("segMethod"),
+                    format!(
+                        "unknown segmentation method {:?} (registered: {})",
+                        group.seg_method,
+                        registry.names().join(", ")
+                    ),
+                );
+            }
+        }
+        Err(e) => issues.error(&at("segMethod"), e.to_string()),
+    }
+    issues.parses::<GridDetectionMethod>(
+        &at("grdDetectionMethod"),
+        group.grid_detection_method.as_deref(),
+    );
+    issues.parses::<GridModelType>(&at("grdModel"), group.grid_model.as_deref());
+    issues.parses::<GridQualityAction>(&at("grdQualityAction"), group.grid_quality_action.as_deref());
+    if !(0.0..=1.0).contains(&group.min_grid_quality) {
+        issues.error(&at("grdMinQuality"), "must be in [0, 1]");
+    }
+    issues.parses::<IntensityNormalization>(
+        &at("prpIntensityNormalization"),
+        group.intensity_normalization.as_deref(),
+    );
+
+    if let Some(bits) = group.bit_depth {
+        if !(1..=16).contains(&bits) {
+            issues.error(&at("prpBitDepth"), "must be between 1 and 16");
+        }
+    }
+    if let Some(p) = group.normalization_percentile {
+        if !(p > 0.0 && p <= 100.0) {
+            issues.error(&at("prpNormalizationPercentile"), "must be in (0, 100]");
+        }
+    }
+
+    if let Some(ref path) = group.manual_spots_file {
+        if !OutputFormat::from_path(path).is_some_and(OutputFormat::is_delimited) {
+            issues.error(&at("segManualSpotsFile"), "must be a .csv or .tsv file");
+        }
+    }
+
+    let use_image = group.use_image.to_lowercase();
+    if MATLAB_USE_IMAGE_OPTIONS.contains(&use_image.as_str()) || use_image.contains('_') {
+        issues.warning(
+            &at("grdUseImage"),
+            format!("MATLAB option {:?} is not supported, using the first image", group.use_image),
+        );
+    } else if !USE_IMAGE_OPTIONS.contains(&use_image.as_str()) {
+        issues.warning(
+            &at("grdUseImage"),
+            format!("unknown option {:?}, using the first image", group.use_image),
+        );
+    }
+
+    for key in group.extra.keys() {
+        // MATLAB parameter files use "_"-prefixed keys as comments
+        if key.starts_with('_') {
+            continue;
+        }
+        if MATLAB_PREFIXES.iter().any(|prefix| key.starts_with(prefix)) {
+            issues.warning(&at(key), "MATLAB parameter is not used by this implementation");
+        } else {
+            issues.warning(&at(key), "unknown parameter, ignored");
+        }
+    }
+}
+
+/// Validate and fail with all errors at once; returns the warnings otherwise
+pub fn check_batch_config(config: &BatchConfig) -> Result<Vec<ConfigIssue>> {
+    let (errors, warnings): (Vec<ConfigIssue>, Vec<ConfigIssue>) = validate_batch_config(config)
+        .into_iter()
+        .partition(|issue| issue.severity == Severity::Error);
+
+    if !errors.is_empty() {
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        return Err(Error::InvalidConfiguration(messages.join("; ")));
+    }
+
+    Ok(warnings)
+}
+
+/// Parse a `key=value` override; the value is read as JSON, or as a string if it is not JSON
+///
+/// `0.5`, `[0, 0.02]` and `"Edge"` keep their JSON types, `Edge` becomes the string "Edge".
+pub fn parse_override(text: &str) -> Result<(String, serde_json::Value)> {
+    let (key, value) = text.split_once('=').ok_or_else(|| {
+        Error::InvalidParameter(format!("Override {:?} is not of the form key=value", text))
+    })?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(Error::InvalidParameter(format!("Override {:?} has no key", text)));
+    }
+    let value = serde_json::from_str(value.trim())
+        .unwrap_or_else(|_| serde_json::Value::String(value.trim().to_string()));
+    Ok((key.to_string(), value))
+}
+
+/// Apply `key=value` overrides to a batch configuration
+///
+/// Batch-level keys (`numWorkers`, `outputFile`, ...) are set on the batch,
+/// any other key on every image group, under its MATLAB name.
+pub fn apply_overrides(config: &mut BatchConfig, overrides: &[(String, serde_json::Value)]) -> Result<()> {
+    if overrides.is_empty() {
+        return Ok(());
+    }
+
+    let mut value = serde_json::to_value(&*config)?;
+    let batch = value
+        .as_object_mut()
+        .expect("BatchConfig serializes to an object");
+    let batch_keys: HashSet<String> = batch.keys().cloned().collect();
+
+    for (key, override_value) in overrides {
+        if key == "imageGroups" {
+            return Err(Error::InvalidParameter("imageGroups cannot be overridden".to_string()));
+        }
+        if batch_keys.contains(key) {
+            batch.insert(key.clone(), override_value.clone());
+        } else if let Some(serde_json::Value::Array(groups)) = batch.get_mut("imageGroups") {
+            for group in groups.iter_mut().filter_map(|g| g.as_object_mut()) {
+                group.insert(key.clone(), override_value.clone());
+            }
+        }
+    }
+
+    *config = serde_json::from_value(value)?;
+    Ok(())
+}
+
+/// A copy of `group` with `key=value` overrides set under their MATLAB names
+pub fn override_group(group: &GroupConfig, overrides: &[(String, serde_json::Value)]) -> Result<GroupConfig> {
+    let mut value = serde_json::to_value(group)?;
+    let fields = value
+        .as_object_mut()
+        .expect("GroupConfig serializes to an object");
+    for (key, override_value) in overrides {
+        fields.insert(key.clone(), override_value.clone());
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// The configuration with all defaults filled in, as pretty-printed JSON
+pub fn effective_config(config: &BatchConfig) -> Result<String> {
+    Ok(serde_json::to_string_pretty(config)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimal_group_gets_matlab_defaults() {
+        let config: BatchConfig = serde_json::from_str(
+            r#"{"outputFile": "out.csv", "imageGroups": [
+                {"groupId": "1", "arraylayoutfile": "layout.txt", "imageslist": ["a.tif"],
+                 "grdRotation": 0, "dbgPrintOutput": "yes", "_comment": "ignored"}]}"#,
+        )
+        .unwrap();
+
+        let group = &config.image_groups[0];
+        assert_eq!(group.max_diameter, 0.85);
+        assert_eq!(group.edge_sensitivity, vec![0.0, 0.01]);
+        assert_eq!(group.rotation, vec![0.0]);
+        assert!(group.debug_print);
+        assert!(validate_batch_config(&config).is_empty());
+
+        // Defaults show up in the effective configuration
+        let effective = effective_config(&config).unwrap();
+        assert!(effective.contains("\"sqcMaxDiameter\": 0.85"));
+    }
+
+    #[test]
+    fn test_overrides_apply_to_batch_and_groups() {
+        let mut config: BatchConfig = serde_json::from_str(
+            r#"{"outputFile": "out.csv", "imageGroups": [{"groupId": "1"}, {"groupId": "2"}]}"#,
+        )
+        .unwrap();
+
+        let overrides: Vec<_> = ["numWorkers=3", "segBgOffset=0.5", "segEdgeSensitivity=[0, 0.02]", "segMethod=Hough"]
+            .iter()
+            .map(|text| parse_override(text).unwrap())
+            .collect();
+        apply_overrides(&mut config, &overrides).unwrap();
+
+        assert_eq!(config.num_workers, 3);
+        for group in &config.image_groups {
+            assert_eq!(group.bg_offset, 0.5);
+            assert_eq!(group.edge_sensitivity, vec![0.0, 0.02]);
+            assert_eq!(group.seg_method, "Hough");
+        }
+
+        assert!(parse_override("segBgOffset").is_err());
+        let bad = [parse_override("sqcMinDiameter=small").unwrap()];
+        assert!(apply_overrides(&mut config, &bad).is_err());
+    }
+
+    #[test]
+    fn test_all_problems_reported_with_paths() {
+        let config: BatchConfig = serde_json::from_str(
+            r#"{"imageGroups": [
+                {"groupId": "1", "arraylayoutfile": "l.txt", "imageslist": ["a.tif"],
+                 "segEdgeSensitivity": [0.01], "grdModel": "Spline", "qntOutlierMethod": "iqr",
+                 "grdUseImage": "FirstLast"},
+                {"groupId": "1", "imageslist": ["b.tif"]}]}"#,
+        )
+        .unwrap();
+
+        let issues = validate_batch_config(&config);
+        let paths: Vec<(&str, Severity)> =
+            issues.iter().map(|i| (i.path.as_str(), i.severity)).collect();
+        assert!(paths.contains(&("$.outputFile", Severity::Error)));
+        assert!(paths.contains(&("$.imageGroups[0].segEdgeSensitivity", Severity::Error)));
+        assert!(paths.contains(&("$.imageGroups[0].grdModel", Severity::Error)));
+        assert!(paths.contains(&("$.imageGroups[0].qntOutlierMethod", Severity::Warning)));
+        assert!(paths.contains(&("$.imageGroups[0].grdUseImage", Severity::Warning)));
+        assert!(paths.contains(&("$.imageGroups[1].groupId", Severity::Error)));
+        assert!(paths.contains(&("$.imageGroups[1].arraylayoutfile", Severity::Error)));
+
+        let err = check_batch_config(&config).unwrap_err().to_string();
+        assert!(err.contains("$.imageGroups[0].segEdgeSensitivity"));
+        assert!(err.contains("$.imageGroups[1].arraylayoutfile"));
+    }
+
+    #[test]
+    fn test_sweep_parameters_validated() {
+        let config: BatchConfig = serde_json::from_str(
+            r#"{"mode": "sweep", "imageGroups": [
+                {"groupId": "1", "arraylayoutfile": "l.txt", "imageslist": ["a.tif"]}],
+                "sweep": {"summaryFile": "sweep.csv", "parameters": {
+                    "grdSpotSize": {"from": 0.7, "to": 0.5, "step": 0.1},
+                    "sqcMinDiameter": [0.4, "small"],
+                    "segMethod": ["Edge", "Threshold"],
+                    "numWorkers": [1, 2]}}}"#,
+        )
+        .unwrap();
+
+        let issues = validate_batch_config(&config);
+        let paths: Vec<&str> = issues.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "$.sweep.parameters.grdSpotSize",
+                "$.sweep.parameters.numWorkers",
+                "$.sweep.parameters.segMethod",
+                "$.sweep.parameters.sqcMinDiameter",
+            ]
+        );
+    }
+}
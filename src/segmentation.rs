@@ -1,9 +1,12 @@
-use crate::config::{GridParams, SegmentationMethod};
+use crate::config::GridParams;
 use crate::error::{Error, Result};
-use crate::image_processing::{canny_edge_detection, compute_gradient, gaussian_blur, normalize_image, threshold, morphological_opening};
+use crate::image_processing::{canny_edge_detection, compute_gradient, gaussian_blur, normalize_intensity, threshold, morphological_opening, IntensityScale};
 use crate::types::{ImageData, Spot};
-use ndarray::Array2;
+use ndarray::{s, Array2, ArrayView2};
+use rayon::prelude::*;
+use std::collections::HashMap;
 use std::f64::consts::PI;
+use std::sync::{Arc, OnceLock, RwLock};
 
 /// Circle parameters (x, y, radius)
 #[derive(Debug, Clone, Copy)]
@@ -13,104 +16,296 @@ pub struct Circle {
     pub radius: f64,
 }
 
+/// Goodness of a circle fit
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FitQuality {
+    /// Number of edge pixels supporting the circle
+    pub support: usize,
+    /// RMS radial residual of the supporting pixels, if known
+    pub rms_residual: Option<f64>,
+    /// Accumulator votes for Hough-based segmenters (0 otherwise)
+    pub votes: usize,
+}
+
+impl FitQuality {
+    /// Support and radial RMS residual of `points` around `circle`
+    pub fn from_points(points: &[(f64, f64)], circle: &Circle) -> Self {
+        let rms_residual = if points.is_empty() {
+            None
+        } else {
+            let sum_sq: f64 = points
+                .iter()
+                .map(|&(x, y)| {
+                    let r = ((x - circle.x).powi(2) + (y - circle.y).powi(2)).sqrt() - circle.radius;
+                    r * r
+                })
+                .sum();
+            Some((sum_sq / points.len() as f64).sqrt())
+        };
+
+        Self {
+            support: points.len(),
+            rms_residual,
+            votes: 0,
+        }
+    }
+}
+
+/// Circle found by a segmenter together with its fit quality
+#[derive(Debug, Clone, Copy)]
+pub struct SpotFit {
+    pub circle: Circle,
+    pub quality: FitQuality,
+    /// No spot was found; `circle` is the default circle at the grid position
+    pub is_default: bool,
+}
+
+impl SpotFit {
+    pub fn new(circle: Circle, quality: FitQuality) -> Self {
+        Self {
+            circle,
+            quality,
+            is_default: false,
+        }
+    }
+
+    /// Default circle returned when no spot edge was found (MATLAB bFound = false)
+    pub fn fallback(circle: Circle) -> Self {
+        Self {
+            circle,
+            quality: FitQuality::default(),
+            is_default: true,
+        }
+    }
+}
+
+/// Rectangular window of the normalized image around a spot
+#[derive(Debug, Clone)]
+pub struct SpotRegion<'a> {
+    pub x_start: usize,
+    pub y_start: usize,
+    pub data: ArrayView2<'a, f64>,
+}
+
+/// Everything a segmenter needs to know about one spot
+pub struct SpotContext<'a> {
+    pub image: &'a ImageData,
+    /// Whole image normalized according to the intensity parameters
+    pub normalized: &'a Array2<f64>,
+    pub spot: &'a Spot,
+    pub params: &'a GridParams,
+    /// Grid positions of spots within 1.5 pitch of this one
+    pub neighbors: Vec<(f64, f64)>,
+}
+
+impl<'a> SpotContext<'a> {
+    /// Expected spot center (x, y) from the grid
+    pub fn position(&self) -> (f64, f64) {
+        (self.spot.grid_x, self.spot.grid_y)
+    }
+
+    /// Window of +/- `radius` pixels around the grid position, clipped to the image
+    pub fn region(&self, radius: f64) -> Option<SpotRegion<'a>> {
+        let (height, width) = self.normalized.dim();
+        let x_start = (self.spot.grid_x - radius).max(0.0) as usize;
+        let x_end = (self.spot.grid_x + radius).min(width as f64 - 1.0) as usize;
+        let y_start = (self.spot.grid_y - radius).max(0.0) as usize;
+        let y_end = (self.spot.grid_y + radius).min(height as f64 - 1.0) as usize;
+
+        if x_end <= x_start || y_end <= y_start {
+            return None;
+        }
+
+        Some(SpotRegion {
+            x_start,
+            y_start,
+            data: self.normalized.slice(s![y_start..=y_end, x_start..=x_end]),
+        })
+    }
+}
+
+/// Segmentation strategy for a single spot
+///
+/// Implementations return `Ok(None)` (or an error) when no circle can be
+/// determined; the spot is then flagged bad by `segment_spots`.
+pub trait SpotSegmenter: Send + Sync {
+    /// Name used to select the segmenter from `segMethod` (case-insensitive)
+    fn name(&self) -> &str;
+
+    fn segment(&self, ctx: &SpotContext<'_>) -> Result<Option<SpotFit>>;
+}
+
+/// MATLAB pg_seg_segment_by_edge
+pub struct EdgeSegmenter;
+
+impl SpotSegmenter for EdgeSegmenter {
+    fn name(&self) -> &str {
+        "edge"
+    }
+
+    fn segment(&self, ctx: &SpotContext<'_>) -> Result<Option<SpotFit>> {
+        segment_by_edge(ctx)
+    }
+}
+
+/// Simple gradient-threshold Hough transform
+pub struct HoughSegmenter;
+
+impl SpotSegmenter for HoughSegmenter {
+    fn name(&self) -> &str {
+        "hough"
+    }
+
+    fn segment(&self, ctx: &SpotContext<'_>) -> Result<Option<SpotFit>> {
+        segment_by_hough(ctx)
+    }
+}
+
+/// Hough transform with adaptive edge thresholds and least-squares refinement
+pub struct AdvancedHoughSegmenter;
+
+impl SpotSegmenter for AdvancedHoughSegmenter {
+    fn name(&self) -> &str {
+        "advanced"
+    }
+
+    fn segment(&self, ctx: &SpotContext<'_>) -> Result<Option<SpotFit>> {
+        crate::advanced_segmentation::hough_circle_detection(ctx)
+    }
+}
+
+/// Segmenters available by name
+pub struct SegmenterRegistry {
+    segmenters: RwLock<HashMap<String, Arc<dyn SpotSegmenter>>>,
+}
+
+impl Default for SegmenterRegistry {
+    fn default() -> Self {
+        Self::with_builtins()
+    }
+}
+
+impl SegmenterRegistry {
+    /// Registry without any segmenters
+    pub fn empty() -> Self {
+        Self {
+            segmenters: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Registry with the edge, hough and advanced segmenters
+    pub fn with_builtins() -> Self {
+        let registry = Self::empty();
+        registry.register(Arc::new(EdgeSegmenter));
+        registry.register(Arc::new(HoughSegmenter));
+        registry.register(Arc::new(AdvancedHoughSegmenter));
+        registry
+    }
+
+    /// Process-wide registry used by `segment_spots`
+    pub fn shared() -> &'static SegmenterRegistry {
+        static SHARED: OnceLock<SegmenterRegistry> = OnceLock::new();
+        SHARED.get_or_init(SegmenterRegistry::with_builtins)
+    }
+
+    /// Add a segmenter, replacing any existing one with the same name
+    pub fn register(&self, segmenter: Arc<dyn SpotSegmenter>) {
+        let name = segmenter.name().to_lowercase();
+        self.segmenters.write().unwrap().insert(name, segmenter);
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn SpotSegmenter>> {
+        self.segmenters
+            .read()
+            .unwrap()
+            .get(&name.to_lowercase())
+            .cloned()
+            .ok_or_else(|| {
+                Error::InvalidParameter(format!("Unknown segmentation method: {}", name))
+            })
+    }
+
+    /// Registered names, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.segmenters.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
 /// Find connected components in a binary image and return the largest one
 /// Implements flood-fill algorithm matching MATLAB's bwconncomp
+///
+/// `edges` is indexed `[[y, x]]` and its first pixel sits at image position
+/// `origin` (x, y). Only pixels in the image window `[x_start, x_end) x
+/// [y_start, y_end)` are considered; the component is returned as image (x, y).
 #[cfg_attr(test, allow(dead_code))]
 pub(crate) fn find_largest_connected_component(
-    edge_map: &Array2<bool>,
+    edges: &Array2<bool>,
+    origin: (usize, usize),
     x_start: usize,
     x_end: usize,
     y_start: usize,
     y_end: usize,
 ) -> Vec<(usize, usize)> {
-    if x_end <= x_start || y_end <= y_start {
+    // Window in `edges` coordinates
+    let (rows, cols) = edges.dim();
+    let col_start = x_start.saturating_sub(origin.0);
+    let col_end = x_end.saturating_sub(origin.0).min(cols);
+    let row_start = y_start.saturating_sub(origin.1);
+    let row_end = y_end.saturating_sub(origin.1).min(rows);
+    if col_end <= col_start || row_end <= row_start {
         return Vec::new();
     }
 
-    let height = x_end - x_start;
-    let width = y_end - y_start;
-    let mut visited = Array2::<bool>::default((height, width));
-    let mut components: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut visited = Array2::<bool>::default((rows, cols));
+    let mut largest: Vec<(usize, usize)> = Vec::new();
 
     // Flood fill to find all connected components
-    for local_x in 0..height {
-        for local_y in 0..width {
-            let global_x = x_start + local_x;
-            let global_y = y_start + local_y;
-
-            // Skip if already visited or not an edge pixel
-            if visited[[local_x, local_y]] || global_x >= edge_map.nrows() || global_y >= edge_map.ncols() {
-                continue;
-            }
-            if !edge_map[[global_x, global_y]] {
+    for row in row_start..row_end {
+        for col in col_start..col_end {
+            if visited[[row, col]] || !edges[[row, col]] {
                 continue;
             }
 
             // Start a new component with flood fill
             let mut component = Vec::new();
-            let mut stack = vec![(local_x, local_y, global_x, global_y)];
-
-            while let Some((lx, ly, gx, gy)) = stack.pop() {
-                if visited[[lx, ly]] {
-                    continue;
-                }
-                visited[[lx, ly]] = true;
-
-                if gx < edge_map.nrows() && gy < edge_map.ncols() && edge_map[[gx, gy]] {
-                    component.push((gx, gy));
-
-                    // Add 8-connected neighbors
-                    for dx in -1..=1 {
-                        for dy in -1..=1 {
-                            if dx == 0 && dy == 0 {
-                                continue;
-                            }
-
-                            let nx = lx as isize + dx;
-                            let ny = ly as isize + dy;
-                            let ngx = gx as isize + dx;
-                            let ngy = gy as isize + dy;
-
-                            if nx >= 0 && nx < height as isize && ny >= 0 && ny < width as isize {
-                                let nx = nx as usize;
-                                let ny = ny as usize;
-                                let ngx = ngx as usize;
-                                let ngy = ngy as usize;
-
-                                if !visited[[nx, ny]] && ngx < edge_map.nrows() && ngy < edge_map.ncols() {
-                                    stack.push((nx, ny, ngx, ngy));
-                                }
-                            }
+            let mut stack = vec![(row, col)];
+            visited[[row, col]] = true;
+
+            while let Some((r, c)) = stack.pop() {
+                component.push((origin.0 + c, origin.1 + r));
+
+                // Add 8-connected neighbors
+                for nr in r.saturating_sub(1).max(row_start)..(r + 2).min(row_end) {
+                    for nc in c.saturating_sub(1).max(col_start)..(c + 2).min(col_end) {
+                        if !visited[[nr, nc]] && edges[[nr, nc]] {
+                            visited[[nr, nc]] = true;
+                            stack.push((nr, nc));
                         }
                     }
                 }
             }
 
-            if !component.is_empty() {
-                components.push(component);
+            if component.len() > largest.len() {
+                largest = component;
             }
         }
     }
 
-    // Return the largest component
-    components.into_iter().max_by_key(|c| c.len()).unwrap_or_default()
+    largest
 }
 
 /// Segment spot using edge-based method matching MATLAB's pg_seg_segment_by_edge
-fn segment_by_edge(
-    image: &ImageData,
-    spot: &Spot,
-    params: &GridParams,
-) -> Result<Option<Circle>> {
-    let normalized = normalize_image(&image.data);
+fn segment_by_edge(ctx: &SpotContext<'_>) -> Result<Option<SpotFit>> {
+    let (image, params, normalized) = (ctx.image, ctx.params, ctx.normalized);
     let spot_pitch = params.spot_pitch;
 
     // Default radius for fallback
     let default_radius = 0.6 * spot_pitch / 2.0;
 
     // Get initial position
-    let cx = spot.grid_x;
-    let cy = spot.grid_y;
+    let (cx, cy) = ctx.position();
 
     // Define ROI bounds (2× spot pitch window, matching MATLAB lines 8-17)
     // Use .round() to ensure consistent rounding behavior with fractional coordinates
@@ -120,9 +315,6 @@ fn segment_by_edge(
     let x_rl = (cx + spot_pitch).min((image.width - 1) as f64).round() as usize;
     let y_rl = (cy + spot_pitch).min((image.height - 1) as f64).round() as usize;
 
-    eprintln!("ROI: cx={}, cy={}, image {}x{}, x_lu={}, x_rl={}, y_lu={}, y_rl={}",
-              cx, cy, image.width, image.height, x_lu, x_rl, y_lu, y_rl);
-
     if x_rl <= x_lu || y_rl <= y_lu {
         return Ok(None);
     }
@@ -135,11 +327,11 @@ fn segment_by_edge(
     // For spot_pitch ~21, ROI is ~44x44, which works but may hit edge cases
     // If too small, fall back to default
     if roi_width < 10 || roi_height < 10 {
-        return Ok(Some(Circle {
+        return Ok(Some(SpotFit::fallback(Circle {
             x: cx,
             y: cy,
             radius: default_radius,
-        }));
+        })));
     }
 
     let mut roi = Array2::zeros((roi_height, roi_width));
@@ -172,15 +364,6 @@ fn segment_by_edge(
     // Apply Canny edge detection with hysteresis thresholding
     let edges = canny_edge_detection(&roi, low_threshold, high_threshold, sigma);
 
-    // Create full-sized edge image like MATLAB (line 44-45)
-    // This avoids coordinate transformation issues
-    let mut edge_map = Array2::<bool>::default((image.height, image.width));
-    for y in 0..roi_height {
-        for x in 0..roi_width {
-            edge_map[[y_lu + y, x_lu + x]] = edges[[y, x]];
-        }
-    }
-
     // Compute parameters for iteration (MATLAB lines 47-49)
     let pix_area_size = params.area_size * spot_pitch;
     let pix_off = ((spot_pitch - 0.5 * pix_area_size).max(0.0)).round() as isize;
@@ -219,10 +402,12 @@ fn segment_by_edge(
         let _local_width = (x_init.1 - x_init.0) as usize;
         let _local_height = (y_init.1 - y_init.0) as usize;
 
-        // Find connected components in edge map (MATLAB lines 88, 93-96)
-        // Extract Ilocal from full-sized edge image using absolute coordinates
+        // Find connected components in edge map (MATLAB lines 88, 93-96);
+        // MATLAB pastes the ROI edges into a full-sized image, outside the
+        // ROI there are no edges so the ROI is searched directly
         let component = find_largest_connected_component(
-            &edge_map,
+            &edges,
+            (x_lu, y_lu),
             x_init.0 as usize,
             x_init.1 as usize,
             y_init.0 as usize,
@@ -247,7 +432,7 @@ fn segment_by_edge(
                 let delta = (dx * dx + dy * dy).sqrt();
 
                 current_midpoint = (circle.x, circle.y);
-                final_circle = Some(circle);
+                final_circle = Some(SpotFit::new(circle, FitQuality::from_points(&edge_pixels, &circle)));
 
                 // Converged? (MATLAB line 71)
                 if delta <= 2.0_f64.sqrt() {
@@ -274,11 +459,11 @@ fn segment_by_edge(
         Ok(final_circle)
     } else {
         // Use default radius for empty spots
-        Ok(Some(Circle {
+        Ok(Some(SpotFit::fallback(Circle {
             x: cx,
             y: cy,
             radius: default_radius,
-        }))
+        })))
     }
 }
 
@@ -288,7 +473,7 @@ fn segment_by_edge_old(
     spot: &Spot,
     params: &GridParams,
 ) -> Result<Option<Circle>> {
-    let normalized = normalize_image(&image.data);
+    let normalized = normalize_intensity(&image.data, params);
 
     // Extract region around spot
     let search_radius = params.spot_pitch;
@@ -345,50 +530,28 @@ fn segment_by_edge_old(
 }
 
 /// Segment spot using Hough transform
-fn segment_by_hough(
-    image: &ImageData,
-    spot: &Spot,
-    params: &GridParams,
-) -> Result<Option<Circle>> {
-    let normalized = normalize_image(&image.data);
-
-    let search_radius = params.spot_pitch;
-    let x_start = (spot.grid_x - search_radius).max(0.0) as usize;
-    let x_end = (spot.grid_x + search_radius)
-        .min(image.width as f64 - 1.0) as usize;
-    let y_start = (spot.grid_y - search_radius).max(0.0) as usize;
-    let y_end = (spot.grid_y + search_radius)
-        .min(image.height as f64 - 1.0) as usize;
-
-    if x_end <= x_start || y_end <= y_start {
-        return Ok(None);
-    }
-
-    let region_height = y_end - y_start + 1;
-    let region_width = x_end - x_start + 1;
-
-    let mut region = Array2::zeros((region_height, region_width));
-    for y in y_start..=y_end {
-        for x in x_start..=x_end {
-            region[[y - y_start, x - x_start]] = normalized[[y, x]];
-        }
-    }
+fn segment_by_hough(ctx: &SpotContext<'_>) -> Result<Option<SpotFit>> {
+    let params = ctx.params;
+    let region = match ctx.region(params.spot_pitch) {
+        Some(region) => region,
+        None => return Ok(None),
+    };
 
     // Compute gradient for edge detection
-    let smoothed = gaussian_blur(&region, 1.0);
+    let smoothed = gaussian_blur(&region.data.to_owned(), 1.0);
     let gradient = compute_gradient(&smoothed);
 
     // Hough circle detection
     let min_radius = (params.min_diameter * params.spot_pitch / 2.0) as usize;
     let max_radius = (params.max_diameter * params.spot_pitch / 2.0) as usize;
 
-    let circle = hough_circles(&gradient, min_radius, max_radius, 0.3)?;
+    let fit = hough_circles(&gradient, min_radius, max_radius, 0.3)?;
 
-    if let Some(mut c) = circle {
+    if let Some(mut fit) = fit {
         // Adjust coordinates back to image space
-        c.x += x_start as f64;
-        c.y += y_start as f64;
-        Ok(Some(c))
+        fit.circle.x += region.x_start as f64;
+        fit.circle.y += region.y_start as f64;
+        Ok(Some(fit))
     } else {
         Ok(None)
     }
@@ -401,7 +564,7 @@ fn hough_circles(
     min_radius: usize,
     max_radius: usize,
     threshold: f64,
-) -> Result<Option<Circle>> {
+) -> Result<Option<SpotFit>> {
     let (height, width) = gradient.dim();
 
     // Accumulator for circle centers
@@ -450,92 +613,141 @@ fn hough_circles(
     let cy = max_pos.0 as f64;
     let radius = ((min_radius + max_radius) / 2) as f64;
 
-    Ok(Some(Circle {
-        x: cx,
-        y: cy,
-        radius,
-    }))
+    let quality = FitQuality {
+        votes: max_val as usize,
+        ..Default::default()
+    };
+
+    Ok(Some(SpotFit::new(
+        Circle {
+            x: cx,
+            y: cy,
+            radius,
+        },
+        quality,
+    )))
 }
 
-/// Segment all spots in image
+/// Segment all spots in image with the segmenter named by `params.segmentation_method`
 pub fn segment_spots(
     image: &ImageData,
     spots: &mut [Spot],
     params: &GridParams,
 ) -> Result<()> {
-    for spot in spots.iter_mut() {
-        let circle_result = match params.segmentation_method {
-            SegmentationMethod::Edge => segment_by_edge(image, spot, params),
-            SegmentationMethod::Hough => segment_by_hough(image, spot, params),
-            SegmentationMethod::Advanced => {
-                // Use advanced Hough with adaptive thresholding
-                use crate::advanced_segmentation;
-                advanced_segmentation::hough_circle_detection(image, spot, params)
-                    .map(|opt_circle| opt_circle.map(|c| Circle {
-                        x: c.x,
-                        y: c.y,
-                        radius: c.radius,
-                    }))
-            }
-        };
-
-        match circle_result {
-            Ok(Some(c)) => {
-                spot.grid_x = c.x;
-                spot.grid_y = c.y;
-                spot.diameter = c.radius * 2.0;
+    segment_spots_with(SegmenterRegistry::shared(), image, spots, params).map(|_| ())
+}
 
-                // Check diameter bounds (MATLAB: sqcMinDiameter, sqcMaxDiameter)
-                // Diameter is relative to spot_pitch
-                let relative_diameter = spot.diameter / params.spot_pitch;
-                if relative_diameter < params.min_diameter || relative_diameter > params.max_diameter {
+/// Segment all spots using a segmenter looked up in `registry`
+///
+/// Returns the fit of every spot (in input order), `None` where segmentation failed.
+/// Manually corrected spots are left as they are and have no fit.
+pub fn segment_spots_with(
+    registry: &SegmenterRegistry,
+    image: &ImageData,
+    spots: &mut [Spot],
+    params: &GridParams,
+) -> Result<Vec<Option<SpotFit>>> {
+    let segmenter = registry.get(params.segmentation_method.name())?;
+
+    // Normalize once for all spots
+    let scale = IntensityScale::from_image(&image.data, params);
+    let normalized = scale.normalize(&image.data);
+    let empty_cutoff = scale.threshold(params.empty_threshold);
+
+    let positions: Vec<(f64, f64)> = spots.iter().map(|s| (s.grid_x, s.grid_y)).collect();
+
+    // Spots are independent; the indexed parallel collect keeps input order
+    let fits = spots
+        .par_iter_mut()
+        .enumerate()
+        .map(|(index, spot)| {
+            if spot.is_manual {
+                return None;
+            }
+            let fit_result = {
+                let ctx = SpotContext {
+                    image,
+                    normalized: &normalized,
+                    spot,
+                    params,
+                    neighbors: neighbor_positions(&positions, index, 1.5 * params.spot_pitch),
+                };
+                segmenter.segment(&ctx)
+            };
+
+            let fit = match fit_result {
+                Ok(Some(fit)) => {
+                    let c = fit.circle;
+                    spot.grid_x = c.x;
+                    spot.grid_y = c.y;
+                    spot.diameter = c.radius * 2.0;
+
+                    // Check diameter bounds (MATLAB: sqcMinDiameter, sqcMaxDiameter)
+                    // Diameter is relative to spot_pitch
+                    let relative_diameter = spot.diameter / params.spot_pitch;
+                    if relative_diameter < params.min_diameter || relative_diameter > params.max_diameter {
+                        spot.is_bad = true;
+                    } else {
+                        spot.is_bad = false;
+                    }
+                    Some(fit)
+                }
+                Ok(None) | Err(_) => {
+                    // Mark as bad if segmentation failed or returned None
                     spot.is_bad = true;
-                } else {
-                    spot.is_bad = false;
+                    None
                 }
-            }
-            Ok(None) | Err(_) => {
-                // Mark as bad if segmentation failed or returned None
-                spot.is_bad = true;
-            }
-        }
+            };
 
-        // Check if spot is empty (low intensity)
-        spot.is_empty = check_if_empty(image, spot, params);
-    }
+            // Check if spot is empty (low intensity)
+            spot.is_empty = check_if_empty(&normalized, spot, empty_cutoff);
+            fit
+        })
+        .collect();
 
-    Ok(())
+    Ok(fits)
+}
+
+/// Positions within `max_distance` of `positions[index]`, excluding itself
+fn neighbor_positions(positions: &[(f64, f64)], index: usize, max_distance: f64) -> Vec<(f64, f64)> {
+    let (x0, y0) = positions[index];
+    positions
+        .iter()
+        .enumerate()
+        .filter(|&(i, &(x, y))| {
+            i != index && ((x - x0).powi(2) + (y - y0).powi(2)).sqrt() <= max_distance
+        })
+        .map(|(_, &p)| p)
+        .collect()
 }
 
 /// Check if spot is empty based on intensity
-fn check_if_empty(image: &ImageData, spot: &Spot, params: &GridParams) -> bool {
+fn check_if_empty(normalized: &Array2<f64>, spot: &Spot, cutoff: f64) -> bool {
     if spot.is_bad {
         return true;
     }
 
-    let normalized = normalize_image(&image.data);
-
+    let (height, width) = normalized.dim();
     let radius = spot.diameter / 2.0;
     let x = spot.grid_x as usize;
     let y = spot.grid_y as usize;
 
-    // Sample intensity within spot
+    // Sample intensity within spot from a spot-local view
     let mut sum = 0.0;
     let mut count = 0;
 
-    let x_start = (x.saturating_sub(radius as usize)).max(0);
-    let x_end = ((x + radius as usize + 1).min(image.width));
-    let y_start = (y.saturating_sub(radius as usize)).max(0);
-    let y_end = ((y + radius as usize + 1).min(image.height));
-
-    for yi in y_start..y_end {
-        for xi in x_start..x_end {
-            let dx = xi as f64 - spot.grid_x;
-            let dy = yi as f64 - spot.grid_y;
-            if (dx * dx + dy * dy).sqrt() <= radius {
-                sum += normalized[[yi, xi]];
-                count += 1;
-            }
+    let x_end = (x + radius as usize + 1).min(width);
+    let y_end = (y + radius as usize + 1).min(height);
+    let x_start = x.saturating_sub(radius as usize).min(x_end);
+    let y_start = y.saturating_sub(radius as usize).min(y_end);
+
+    let window = normalized.slice(s![y_start..y_end, x_start..x_end]);
+    for ((wy, wx), &value) in window.indexed_iter() {
+        let dx = (x_start + wx) as f64 - spot.grid_x;
+        let dy = (y_start + wy) as f64 - spot.grid_y;
+        if (dx * dx + dy * dy).sqrt() <= radius {
+            sum += value;
+            count += 1;
         }
     }
 
@@ -546,7 +758,7 @@ fn check_if_empty(image: &ImageData, spot: &Spot, params: &GridParams) -> bool {
     let mean_intensity = sum / count as f64;
 
     // Spot is empty if mean intensity is below threshold
-    mean_intensity < 0.1
+    mean_intensity < cutoff
 }
 
 /// Fit circle to points using weighted least squares
@@ -759,6 +971,7 @@ fn calculate_chi_square(points: &[(f64, f64)], circle: &Circle, weights: &[f64])
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::SegmentationMethod;
 
     #[test]
     fn test_fit_circle_robust() {
@@ -795,4 +1008,94 @@ mod tests {
         assert!((circle.radius - radius).abs() < 1.0,
                 "Radius should be accurate, got {}, expected {}", circle.radius, radius);
     }
+
+    #[test]
+    fn test_largest_component_in_image_coordinates() {
+        // ROI of 20x10 pixels whose first pixel is image (100, 50)
+        let mut edges = Array2::<bool>::default((10, 20));
+        for col in 14..18 {
+            edges[[2, col]] = true;
+        }
+        edges[[7, 1]] = true;
+        edges[[8, 2]] = true;
+
+        let mut component = find_largest_connected_component(&edges, (100, 50), 90, 130, 40, 70);
+        component.sort();
+        assert_eq!(component, [(114, 52), (115, 52), (116, 52), (117, 52)]);
+
+        // The window keeps only the diagonal pair
+        let mut component = find_largest_connected_component(&edges, (100, 50), 100, 104, 50, 60);
+        component.sort();
+        assert_eq!(component, [(101, 57), (102, 58)]);
+        assert!(find_largest_connected_component(&edges, (100, 50), 0, 100, 0, 50).is_empty());
+    }
+
+    /// Places the circle one pixel right of the grid position
+    struct OffsetSegmenter;
+
+    impl SpotSegmenter for OffsetSegmenter {
+        fn name(&self) -> &str {
+            "Offset"
+        }
+
+        fn segment(&self, ctx: &SpotContext<'_>) -> Result<Option<SpotFit>> {
+            let (x, y) = ctx.position();
+            let circle = Circle { x: x + 1.0, y, radius: 7.0 };
+            Ok(Some(SpotFit::new(circle, FitQuality { support: ctx.neighbors.len(), ..Default::default() })))
+        }
+    }
+
+    fn test_spot(id: &str, x: f64, y: f64) -> Spot {
+        Spot {
+            id: id.to_string(),
+            row: 1,
+            col: 1,
+            is_reference: false,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            x_fixed: 0.0,
+            y_fixed: 0.0,
+            grid_x: x,
+            grid_y: y,
+            diameter: 0.0,
+            is_manual: false,
+            manual_source: None,
+            is_bad: false,
+            is_empty: false,
+            rotation: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_external_segmenter_from_registry() {
+        let registry = SegmenterRegistry::with_builtins();
+        registry.register(Arc::new(OffsetSegmenter));
+        assert_eq!(registry.names(), vec!["advanced", "edge", "hough", "offset"]);
+
+        let params = GridParams {
+            segmentation_method: "offset".parse().unwrap(),
+            ..Default::default()
+        };
+        let image = ImageData::new(Array2::from_elem((60, 60), 1000u16), "img".to_string());
+        let mut spots = vec![test_spot("A1", 20.0, 20.0), test_spot("A2", 41.5, 20.0)];
+
+        let fits = segment_spots_with(&registry, &image, &mut spots, &params).unwrap();
+
+        assert_eq!(spots[0].grid_x, 21.0);
+        assert_eq!(spots[1].diameter, 14.0);
+        assert_eq!(fits[0].unwrap().quality.support, 1);
+        assert!(!spots[0].is_bad);
+    }
+
+    #[test]
+    fn test_unknown_segmenter_is_an_error() {
+        let params = GridParams {
+            segmentation_method: SegmentationMethod::External("missing".to_string()),
+            ..Default::default()
+        };
+        let image = ImageData::new(Array2::zeros((10, 10)), "img".to_string());
+        let mut spots = vec![test_spot("A1", 5.0, 5.0)];
+
+        assert!(segment_spots(&image, &mut spots, &params).is_err());
+    }
 }
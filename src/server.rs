@@ -0,0 +1,561 @@
+use crate::batch::process_single_group;
+use crate::error::{Error, Result};
+use crate::io::{load_tiff_image, render_overlay};
+use crate::schema::{validate_group_config, ConfigIssue, Severity};
+use crate::types::{GroupConfig, SpotResult};
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+/// Largest accepted request body (uploaded images)
+const MAX_BODY_BYTES: usize = 256 * 1024 * 1024;
+
+/// Settings of `pamsoft_grid serve`
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address to listen on, e.g. `127.0.0.1:8080`
+    pub address: String,
+    /// Jobs processed at the same time
+    pub workers: usize,
+    /// Directory for images uploaded with `POST /uploads/<name>`
+    pub upload_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+}
+
+/// A submitted group and, once processed, its results
+struct Job {
+    config: GroupConfig,
+    status: JobStatus,
+    submitted: Instant,
+    elapsed_ms: Option<f64>,
+    results: Option<Arc<Vec<SpotResult>>>,
+    error: Option<String>,
+}
+
+/// Status of a job as returned by `GET /jobs/<id>`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobInfo {
+    pub job_id: usize,
+    pub group_id: String,
+    pub status: JobStatus,
+    pub elapsed_ms: Option<f64>,
+    pub spots: Option<usize>,
+    pub error: Option<String>,
+}
+
+/// Spot quality summary of a finished job (`GET /jobs/<id>/qc`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpotQc {
+    pub spots: usize,
+    pub references: usize,
+    pub bad: usize,
+    pub empty: usize,
+    pub manual: usize,
+    pub fraction_bad: f64,
+    pub fraction_empty: f64,
+    /// Mean diameter of good spots, in pixels
+    pub mean_diameter: f64,
+    /// Coefficient of variation of good spot diameters
+    pub diameter_cv: f64,
+}
+
+impl SpotQc {
+    /// Summary over the spots of one image; `null` in JSON where undefined
+    pub fn from_results(results: &[SpotResult]) -> Self {
+        let count = |keep: fn(&SpotResult) -> bool| results.iter().filter(|r| keep(r)).count();
+        let spots = results.len();
+        let bad = count(|r| r.is_bad);
+        let empty = count(|r| r.is_empty);
+
+        let diameters: Vec<f64> = results
+            .iter()
+            .filter(|r| !r.is_bad && !r.is_empty)
+            .map(|r| r.diameter)
+            .collect();
+        let n = diameters.len() as f64;
+        let mean_diameter = diameters.iter().sum::<f64>() / n;
+        let variance = diameters.iter().map(|d| (d - mean_diameter).powi(2)).sum::<f64>() / (n - 1.0);
+
+        Self {
+            spots,
+            references: count(|r| r.is_reference),
+            bad,
+            empty,
+            manual: count(|r| r.is_manual),
+            fraction_bad: bad as f64 / spots as f64,
+            fraction_empty: empty as f64 / spots as f64,
+            mean_diameter,
+            diameter_cv: variance.sqrt() / mean_diameter,
+        }
+    }
+}
+
+/// Job store shared by the connection handlers and the workers
+pub struct Server {
+    config: ServerConfig,
+    jobs: Mutex<Vec<Job>>,
+    queue: Mutex<Sender<usize>>,
+}
+
+/// A parsed HTTP request
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+/// An HTTP response
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(value).unwrap_or_default(),
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::json(status, &serde_json::json!({ "error": message.into() }))
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+impl Server {
+    /// Create the job store and start `config.workers` worker threads
+    pub fn start(config: ServerConfig) -> Result<Arc<Self>> {
+        std::fs::create_dir_all(&config.upload_dir)?;
+        let (sender, receiver) = mpsc::channel();
+        let server = Arc::new(Self {
+            jobs: Mutex::new(Vec::new()),
+            queue: Mutex::new(sender),
+            config,
+        });
+
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..server.config.workers.max(1) {
+            let server = Arc::clone(&server);
+            let receiver = Arc::clone(&receiver);
+            std::thread::spawn(move || server.work(&receiver));
+        }
+        Ok(server)
+    }
+
+    /// Accept connections until the listener fails, one thread per connection
+    pub fn serve(self: &Arc<Self>, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = Arc::clone(self);
+            std::thread::spawn(move || {
+                if let Err(e) = server.handle_connection(stream) {
+                    tracing::warn!("Connection failed: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Lock the job store; a panic elsewhere leaves the jobs themselves consistent
+    fn jobs(&self) -> MutexGuard<'_, Vec<Job>> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn work(&self, receiver: &Mutex<Receiver<usize>>) {
+        loop {
+            // Hold the lock only while waiting for the next job
+            let next = receiver.lock().unwrap().recv();
+            let Ok(index) = next else {
+                return;
+            };
+
+            let config = {
+                let mut jobs = self.jobs();
+                jobs[index].status = JobStatus::Running;
+                jobs[index].config.clone()
+            };
+            tracing::info!("Job {}: processing group {}", index + 1, config.group_id);
+            // A panicking group fails its job instead of taking the worker down
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| process_single_group(&config)))
+                .unwrap_or_else(|payload| {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    Err(Error::ProcessingError(format!("Processing panicked: {}", message)))
+                });
+
+            let mut jobs = self.jobs();
+            let job = &mut jobs[index];
+            job.elapsed_ms = Some(job.submitted.elapsed().as_secs_f64() * 1000.0);
+            match outcome {
+                Ok(results) => {
+                    job.status = JobStatus::Finished;
+                    job.results = Some(Arc::new(results));
+                }
+                Err(e) => {
+                    tracing::warn!("Job {} failed: {}", index + 1, e);
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> Result<()> {
+        let response = match read_request(&mut stream) {
+            Ok(request) => {
+                tracing::debug!("{} {}", request.method, request.path);
+                self.handle(&request)
+            }
+            Err(e) => Response::error(400, e.to_string()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            reason(response.status),
+            response.content_type,
+            response.body.len()
+        )?;
+        stream.write_all(&response.body)?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Route a request
+    pub fn handle(&self, request: &Request) -> Response {
+        let path = request.path.split('?').next().unwrap_or("");
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["health"]) => Response::json(
+                200,
+                &serde_json::json!({ "status": "ok", "version": crate::VERSION }),
+            ),
+            ("POST", ["uploads", name]) => self.upload(name, &request.body),
+            ("POST", ["jobs"]) => self.submit(&request.body),
+            ("GET", ["jobs"]) => {
+                let jobs = self.jobs();
+                let infos: Vec<JobInfo> = (0..jobs.len()).map(|i| job_info(i, &jobs[i])).collect();
+                Response::json(200, &infos)
+            }
+            ("GET", ["jobs", id, rest @ ..]) => {
+                let Some(index) = id.parse::<usize>().ok().and_then(|id| id.checked_sub(1)) else {
+                    return Response::error(404, format!("No job {}", id));
+                };
+                self.job_resource(index, rest)
+            }
+            (_, ["health"] | ["uploads", _] | ["jobs", ..]) => {
+                Response::error(405, format!("{} not allowed on {}", request.method, path))
+            }
+            _ => Response::error(404, format!("No resource {}", path)),
+        }
+    }
+
+    fn upload(&self, name: &str, body: &[u8]) -> Response {
+        let valid = !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+        if !valid {
+            return Response::error(400, format!("Invalid upload name {:?}", name));
+        }
+        let path = self.config.upload_dir.join(name);
+        match std::fs::write(&path, body) {
+            Ok(()) => Response::json(200, &serde_json::json!({ "path": path.to_string_lossy() })),
+            Err(e) => Response::error(500, e.to_string()),
+        }
+    }
+
+    fn submit(&self, body: &[u8]) -> Response {
+        let mut config: GroupConfig = match serde_json::from_slice(body) {
+            Ok(config) => config,
+            Err(e) => return Response::error(400, format!("Invalid group configuration: {}", e)),
+        };
+
+        let mut jobs = self.jobs();
+        let index = jobs.len();
+        if config.group_id.is_empty() {
+            config.group_id = (index + 1).to_string();
+        }
+        let errors: Vec<ConfigIssue> = validate_group_config(&config)
+            .into_iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .collect();
+        if !errors.is_empty() {
+            return Response::json(400, &serde_json::json!({ "issues": errors }));
+        }
+
+        jobs.push(Job {
+            config,
+            status: JobStatus::Queued,
+            submitted: Instant::now(),
+            elapsed_ms: None,
+            results: None,
+            error: None,
+        });
+        if self.queue.lock().unwrap().send(index).is_err() {
+            jobs[index].status = JobStatus::Failed;
+            jobs[index].error = Some("No workers running".to_string());
+        }
+        Response::json(202, &job_info(index, &jobs[index]))
+    }
+
+    fn job_resource(&self, index: usize, rest: &[&str]) -> Response {
+        let (info, config, results) = {
+            let jobs = self.jobs();
+            let Some(job) = jobs.get(index) else {
+                return Response::error(404, format!("No job {}", index + 1));
+            };
+            (job_info(index, job), job.config.clone(), job.results.clone())
+        };
+
+        if rest.is_empty() {
+            return Response::json(200, &info);
+        }
+        let Some(results) = results else {
+            return Response::error(409, format!("Job {} is {:?}", index + 1, info.status));
+        };
+
+        match rest {
+            ["spots"] => Response::json(200, &*results),
+            ["qc"] => Response::json(200, &SpotQc::from_results(grid_image_results(&results))),
+            ["overlay.png"] => match overlay_png(&config, grid_image_results(&results)) {
+                Ok(png) => Response {
+                    status: 200,
+                    content_type: "image/png",
+                    body: png,
+                },
+                Err(e) => Response::error(500, e.to_string()),
+            },
+            _ => Response::error(404, format!("No job resource {}", rest.join("/"))),
+        }
+    }
+}
+
+fn job_info(index: usize, job: &Job) -> JobInfo {
+    JobInfo {
+        job_id: index + 1,
+        group_id: job.config.group_id.clone(),
+        status: job.status,
+        elapsed_ms: job.elapsed_ms,
+        spots: job.results.as_ref().map(|results| grid_image_results(results).len()),
+        error: job.error.clone(),
+    }
+}
+
+/// Results on the image the grid was found on (series runs have one set per image)
+fn grid_image_results(results: &[SpotResult]) -> &[SpotResult] {
+    let Some(first) = results.first() else {
+        return results;
+    };
+    let end = results
+        .iter()
+        .position(|r| r.image_name != first.image_name)
+        .unwrap_or(results.len());
+    &results[..end]
+}
+
+/// PNG of the grid image with the spots drawn on top
+fn overlay_png(config: &GroupConfig, results: &[SpotResult]) -> Result<Vec<u8>> {
+    let name = results.first().map(|r| r.image_name.as_str()).unwrap_or("");
+    let path = config
+        .images_list
+        .iter()
+        .find(|path| Path::new(path).file_stem().and_then(|s| s.to_str()) == Some(name))
+        .ok_or_else(|| Error::FileNotFound(format!("image {} of the job", name)))?;
+
+    let overlay = render_overlay(&load_tiff_image(path)?, results);
+    let mut png = Cursor::new(Vec::new());
+    overlay.write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+/// Read one request; only `Content-Length` bodies are supported
+fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(Error::InvalidParameter(format!("Malformed request line {:?}", line.trim())));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| {
+                    Error::InvalidParameter(format!("Invalid Content-Length {:?}", value.trim()))
+                })?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(Error::InvalidParameter(format!(
+            "Request body of {} bytes exceeds {} bytes",
+            content_length, MAX_BODY_BYTES
+        )));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, body })
+}
+
+/// Listen on `config.address` and serve requests until the process exits
+pub fn run_server(config: ServerConfig) -> Result<()> {
+    let listener = TcpListener::bind(&config.address)?;
+    tracing::info!(
+        "Listening on http://{} with {} worker(s), uploads in {}",
+        listener.local_addr()?,
+        config.workers,
+        config.upload_dir.display()
+    );
+    Server::start(config)?.serve(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_server() -> Arc<Server> {
+        Server::start(ServerConfig {
+            address: "127.0.0.1:0".to_string(),
+            workers: 1,
+            upload_dir: std::env::temp_dir().join("pamsoft_server_uploads"),
+        })
+        .unwrap()
+    }
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_job_lifecycle_and_errors() {
+        let server = test_server();
+
+        let invalid = server.handle(&request("POST", "/jobs", r#"{"imageslist": []}"#));
+        assert_eq!(invalid.status, 400);
+        assert!(String::from_utf8(invalid.body).unwrap().contains("$.imageslist"));
+
+        // A missing image fails in the worker, not at submission
+        let submitted = server.handle(&request(
+            "POST",
+            "/jobs",
+            r#"{"imageslist": ["/nonexistent/a.tif"], "arraylayoutfile": "/nonexistent/layout.txt"}"#,
+        ));
+        assert_eq!(submitted.status, 202);
+
+        let mut status = String::new();
+        for _ in 0..100 {
+            let response = server.handle(&request("GET", "/jobs/1", ""));
+            status = String::from_utf8(response.body).unwrap();
+            if status.contains("failed") {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert!(status.contains(r#""status":"failed""#), "{}", status);
+        assert!(status.contains(r#""groupId":"1""#));
+
+        assert_eq!(server.handle(&request("GET", "/jobs/1/spots", "")).status, 409);
+        assert_eq!(server.handle(&request("GET", "/jobs/7", "")).status, 404);
+        assert_eq!(server.handle(&request("DELETE", "/jobs/1", "")).status, 405);
+        assert_eq!(server.handle(&request("POST", "/uploads/..%2Fx", "")).status, 400);
+    }
+
+    #[test]
+    fn test_requests_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = test_server();
+        std::thread::spawn(move || server.serve(listener));
+
+        let exchange = |raw: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(raw.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let length = format!("Content-Length: {}\r\n", body.len());
+            assert!(head.contains(&length), "{}", head);
+            (head.lines().next().unwrap().to_string(), body.to_string())
+        };
+
+        let (status, body) = exchange("GET /health HTTP/1.1\r\nHost: test\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(body.contains(r#""status":"ok""#));
+
+        let config = r#"{"imageslist": []}"#;
+        let (status, body) = exchange(&format!(
+            "POST /jobs HTTP/1.1\r\ncontent-length: {}\r\n\r\n{}",
+            config.len(),
+            config
+        ));
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert!(body.contains("$.imageslist"));
+
+        let (status, _) = exchange("garbage\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn test_qc_of_results() {
+        let result = |is_bad: bool, diameter: f64| SpotResult {
+            is_bad,
+            diameter,
+            ..SpotResult::example("1", "s")
+        };
+        let qc = SpotQc::from_results(&[result(false, 10.0), result(false, 14.0), result(true, 3.0)]);
+        assert_eq!(qc.bad, 1);
+        assert!((qc.fraction_bad - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(qc.mean_diameter, 12.0);
+        assert!((qc.diameter_cv - 8f64.sqrt() / 12.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::batch::group_params;
+use crate::config::{GridParams, RunMode};
+use crate::error::{Error, Result};
+use crate::grid::process_gridding;
+use crate::image_processing::preprocess_images;
+use crate::io::{
+    load_images, read_grid_output, read_layout_file, write_grid_output, write_quant_output,
+};
+use crate::quantification::{quantify_records, QuantRecord, QuantSpot};
+use crate::segmentation::segment_spots;
+use crate::types::{ImageData, SingleRunConfig, Spot};
+use rayon::prelude::*;
+
+/// Grid found by a "grid" run
+#[derive(Debug, Clone)]
+pub struct GriddingOutput {
+    pub spots: Vec<Spot>,
+    /// Images the grid was found on (grdImageNameUsed)
+    pub image_names: Vec<String>,
+}
+
+/// Run one MATLAB-style `pamsoft_grid` job and write its output file
+pub fn run_single(config: &SingleRunConfig) -> Result<()> {
+    if config.output_file.is_empty() {
+        return Err(Error::InvalidConfiguration("outputfile is required".to_string()));
+    }
+
+    match config.pg_mode.parse::<RunMode>()? {
+        RunMode::Grid => {
+            let output = run_gridding(config)?;
+            write_grid_output(&config.output_file, &output.spots, &output.image_names)?;
+            tracing::info!("Wrote {} grid positions to {}", output.spots.len(), config.output_file);
+        }
+        RunMode::Quantification => {
+            let records = run_quantification(config)?;
+            write_quant_output(&config.output_file, &records)?;
+            tracing::info!("Wrote {} quantification rows to {}", records.len(), config.output_file);
+        }
+    }
+
+    Ok(())
+}
+
+/// Load the images and layout of a run and build its parameters
+fn load_run(config: &SingleRunConfig) -> Result<(Vec<ImageData>, GridParams)> {
+    let images = load_images(&config.images_list)?;
+    if images.is_empty() {
+        return Err(Error::InvalidConfiguration("imageslist is empty".to_string()));
+    }
+    let params = group_params(&config.group_config(), &images)?;
+    Ok((images, params))
+}
+
+/// Find the grid on the `grdUseImage` image
+pub fn run_gridding(config: &SingleRunConfig) -> Result<GriddingOutput> {
+    let (images, params) = load_run(config)?;
+    let layout = match params.array_layout_file {
+        Some(ref layout_file) => read_layout_file(layout_file)?,
+        None => {
+            return Err(Error::InvalidConfiguration(
+                "arraylayoutfile is required".to_string(),
+            ))
+        }
+    };
+
+    let grid_image = preprocess_images(&images, None, &config.use_image)?;
+    let spots = process_gridding(std::slice::from_ref(&grid_image), &layout, &params)?;
+
+    Ok(GriddingOutput {
+        spots,
+        image_names: vec![grid_image.name.clone()],
+    })
+}
+
+/// Segment at the positions of `griddingoutputfile` and quantify every image
+///
+/// Spots are segmented once on the `grdUseImage` image; bad spots are replaced
+/// by the default circle at their grid position. Rows are ordered by image
+/// name, then by spot in grid file order, as in the MATLAB output.
+pub fn run_quantification(config: &SingleRunConfig) -> Result<Vec<QuantRecord>> {
+    let grid_file = config.gridding_output_file.as_deref().ok_or_else(|| {
+        Error::InvalidConfiguration(
+            "griddingoutputfile is required in quantification mode".to_string(),
+        )
+    })?;
+    let grid_spots = read_grid_output(grid_file)?;
+    let (mut images, params) = load_run(config)?;
+
+    let default_diameter = params.spot_size * params.spot_pitch;
+    let mut spots = grid_spots.clone();
+    for spot in &mut spots {
+        spot.diameter = default_diameter;
+    }
+
+    let segmentation_image = preprocess_images(&images, None, &config.use_image)?;
+    segment_spots(&segmentation_image, &mut spots, &params)?;
+
+    let quant_spots: Vec<QuantSpot> = grid_spots
+        .iter()
+        .zip(spots)
+        .map(|(grid, spot)| {
+            let replaced = spot.is_bad;
+            let spot = if replaced {
+                Spot {
+                    grid_x: grid.grid_x,
+                    grid_y: grid.grid_y,
+                    diameter: default_diameter,
+                    ..spot
+                }
+            } else {
+                spot
+            };
+            QuantSpot {
+                spot,
+                grid_position: (grid.grid_x, grid.grid_y),
+                replaced,
+            }
+        })
+        .collect();
+
+    images.sort_by(|a, b| a.name.cmp(&b.name));
+    let per_image: Vec<Vec<QuantRecord>> = images
+        .par_iter()
+        .map(|image| quantify_records(image, &quant_spots, &params))
+        .collect();
+
+    Ok(per_image.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::load_single_run_config;
+
+    #[test]
+    fn test_parse_matlab_param_files() {
+        let grid = load_single_run_config("test_params/input_params_gridding.json").unwrap();
+        assert_eq!(grid.pg_mode.parse::<RunMode>().unwrap(), RunMode::Grid);
+        assert_eq!(grid.use_image, "First");
+        assert!(grid.debug_print);
+        assert!(grid.show_viewer);
+        assert_eq!(grid.images_list.len(), 17);
+        // Omitted parameters take the MATLAB defaults
+        assert_eq!(grid.max_diameter, 0.85);
+        assert_eq!(grid.spot_size, 0.66);
+        assert_eq!(grid.gridding_output_file, None);
+
+        let quant = load_single_run_config("test_params/input_params_quantification.json").unwrap();
+        assert_eq!(quant.pg_mode.parse::<RunMode>().unwrap(), RunMode::Quantification);
+        assert!(quant.verbose);
+        assert!(quant.gridding_output_file.unwrap().ends_with("output_test_grid.txt"));
+
+        // MATLAB test inputs wrap the parameters in an array
+        let local = load_single_run_config("test/input/input_params_local.json").unwrap();
+        assert_eq!(local.use_image, "Last");
+    }
+
+    #[test]
+    fn test_quantification_requires_grid_file() {
+        let config = SingleRunConfig {
+            pg_mode: "quantification".to_string(),
+            output_file: "/tmp/unused_quant.txt".to_string(),
+            ..SingleRunConfig::default()
+        };
+        assert!(matches!(
+            run_single(&config),
+            Err(Error::InvalidConfiguration(_))
+        ));
+    }
+}
@@ -0,0 +1,388 @@
+use crate::batch::{load_group_images, locate_group_spots, LocatedSpots};
+use crate::error::{Error, Result};
+use crate::schema::override_group;
+use crate::types::{BatchConfig, GroupConfig, ImageData, SweepConfig, SweepValues};
+use rayon::prelude::*;
+use serde_json::Value;
+use std::path::Path;
+
+/// One parameter combination: `(MATLAB name, value)` pairs in name order
+pub type Combination = Vec<(String, Value)>;
+
+/// QC metrics of one parameter combination over all image groups
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepRow {
+    pub parameters: Combination,
+    pub groups: usize,
+    /// Groups whose gridding or segmentation failed
+    pub failed_groups: usize,
+    pub spots: usize,
+    pub fraction_bad: f64,
+    pub fraction_empty: f64,
+    /// Mean distance of good spots from their grid position, in spot pitches
+    pub mean_position_offset: f64,
+    /// Coefficient of variation of good spot diameters
+    pub diameter_cv: f64,
+}
+
+/// The values of a swept parameter
+pub fn sweep_values(values: &SweepValues) -> Result<Vec<Value>> {
+    match *values {
+        SweepValues::Range { from, to, step } => {
+            if !(step > 0.0 && to >= from) {
+                return Err(Error::InvalidParameter(format!(
+                    "Invalid sweep range from {} to {} step {}",
+                    from, to, step
+                )));
+            }
+            // Tolerance so that e.g. 0.1..0.3 step 0.1 includes 0.3
+            let count = ((to - from) / step + 1e-9).floor() as usize + 1;
+            Ok((0..count)
+                .map(|i| {
+                    let value = from + i as f64 * step;
+                    Value::from((value * 1e12).round() / 1e12)
+                })
+                .collect())
+        }
+        SweepValues::List(ref list) if list.is_empty() => Err(Error::InvalidParameter(
+            "Sweep value list is empty".to_string(),
+        )),
+        SweepValues::List(ref list) => Ok(list.clone()),
+    }
+}
+
+/// Cartesian product of all swept parameters, the last parameter varying fastest
+pub fn combinations(sweep: &SweepConfig) -> Result<Vec<Combination>> {
+    let mut combinations: Vec<Combination> = vec![Vec::new()];
+    for (key, values) in &sweep.parameters {
+        let values = sweep_values(values)?;
+        combinations = combinations
+            .iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut next = combination.clone();
+                    next.push((key.clone(), value.clone()));
+                    next
+                })
+            })
+            .collect();
+    }
+    Ok(combinations)
+}
+
+/// Running QC totals of one combination
+#[derive(Default)]
+struct QcTotals {
+    groups: usize,
+    failed_groups: usize,
+    spots: usize,
+    bad: usize,
+    empty: usize,
+    position_offsets: Vec<f64>,
+    diameters: Vec<f64>,
+}
+
+impl QcTotals {
+    fn add(&mut self, located: &LocatedSpots) {
+        self.groups += 1;
+        self.spots += located.spots.len();
+        for (spot, &(gx, gy)) in located.spots.iter().zip(&located.grid_positions) {
+            self.bad += spot.is_bad as usize;
+            self.empty += spot.is_empty as usize;
+            if !spot.is_bad && !spot.is_empty {
+                let offset = ((spot.grid_x - gx).powi(2) + (spot.grid_y - gy).powi(2)).sqrt();
+                self.position_offsets.push(offset / located.params.spot_pitch);
+                self.diameters.push(spot.diameter);
+            }
+        }
+    }
+
+    fn merge(&mut self, other: QcTotals) {
+        self.groups += other.groups;
+        self.failed_groups += other.failed_groups;
+        self.spots += other.spots;
+        self.bad += other.bad;
+        self.empty += other.empty;
+        self.position_offsets.extend(other.position_offsets);
+        self.diameters.extend(other.diameters);
+    }
+
+    fn row(self, parameters: Combination) -> SweepRow {
+        let fraction = |count: usize| {
+            if self.spots == 0 {
+                f64::NAN
+            } else {
+                count as f64 / self.spots as f64
+            }
+        };
+        let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+
+        let mean_diameter = mean(&self.diameters);
+        let diameter_cv = if self.diameters.len() < 2 {
+            f64::NAN
+        } else {
+            let variance = self
+                .diameters
+                .iter()
+                .map(|d| (d - mean_diameter).powi(2))
+                .sum::<f64>()
+                / (self.diameters.len() - 1) as f64;
+            variance.sqrt() / mean_diameter
+        };
+
+        SweepRow {
+            parameters,
+            groups: self.groups + self.failed_groups,
+            failed_groups: self.failed_groups,
+            spots: self.spots,
+            fraction_bad: fraction(self.bad),
+            fraction_empty: fraction(self.empty),
+            mean_position_offset: mean(&self.position_offsets),
+            diameter_cv,
+        }
+    }
+}
+
+/// Grid and segment every image group under every parameter combination
+///
+/// Images are loaded once per group; (combination, group) runs are spread
+/// over `numWorkers` threads. Quantification is skipped, the QC metrics only
+/// depend on the located spots. A group that fails under a combination is
+/// counted in `failed_groups`.
+pub fn run_sweep(config: &BatchConfig) -> Result<Vec<SweepRow>> {
+    let sweep = config.sweep.as_ref().ok_or_else(|| {
+        Error::InvalidConfiguration("sweep parameters are required in sweep mode".to_string())
+    })?;
+    let combinations = combinations(sweep)?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.num_workers.max(1))
+        .build()
+        .map_err(|e| Error::ProcessingError(format!("Failed to build thread pool: {}", e)))?;
+
+    tracing::info!(
+        "Sweeping {} parameter combination(s) over {} group(s)",
+        combinations.len(),
+        config.image_groups.len()
+    );
+
+    pool.install(|| {
+        let groups: Vec<(&GroupConfig, Vec<ImageData>)> = config
+            .image_groups
+            .par_iter()
+            .map(|group| Ok((group, load_group_images(group).map_err(|f| f.error)?)))
+            .collect::<Result<_>>()?;
+
+        // Reduce each run to its totals so the located spots can be dropped
+        let runs: Vec<(usize, QcTotals)> = (0..combinations.len())
+            .flat_map(|c| (0..groups.len()).map(move |g| (c, g)))
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(c, g)| {
+                let (group, images) = &groups[g];
+                let group = override_group(group, &combinations[c])?;
+                let mut run = QcTotals::default();
+                match locate_group_spots(&group, images) {
+                    Ok(located) => run.add(&located),
+                    Err(failure) => {
+                        tracing::warn!(
+                            "Group {} failed at {} with {}: {}",
+                            failure.group_id,
+                            failure.stage.as_str(),
+                            describe(&combinations[c]),
+                            failure.error
+                        );
+                        run.failed_groups += 1;
+                    }
+                }
+                Ok((c, run))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut totals: Vec<QcTotals> = combinations.iter().map(|_| QcTotals::default()).collect();
+        for (c, run) in runs {
+            totals[c].merge(run);
+        }
+
+        Ok(totals
+            .into_iter()
+            .zip(combinations.iter().cloned())
+            .map(|(totals, parameters)| totals.row(parameters))
+            .collect())
+    })
+}
+
+/// `key=value` list of a combination, for logging
+fn describe(combination: &Combination) -> String {
+    combination
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Write the QC summary as CSV, one column per swept parameter followed by the metrics
+///
+/// String values are written bare, other values as JSON; undefined metrics
+/// (no good spots) are left empty.
+pub fn write_sweep_summary<P: AsRef<Path>>(rows: &[SweepRow], path: P) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    let mut header: Vec<String> = rows
+        .first()
+        .map(|row| row.parameters.iter().map(|(key, _)| key.clone()).collect())
+        .unwrap_or_default();
+    header.extend(
+        [
+            "groups",
+            "failedGroups",
+            "spots",
+            "fractionBad",
+            "fractionEmpty",
+            "meanPositionOffset",
+            "diameterCv",
+        ]
+        .map(String::from),
+    );
+    writer.write_record(&header)?;
+
+    let metric = |value: f64| {
+        if value.is_finite() {
+            value.to_string()
+        } else {
+            String::new()
+        }
+    };
+    for row in rows {
+        let mut record: Vec<String> = row
+            .parameters
+            .iter()
+            .map(|(_, value)| match value {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            })
+            .collect();
+        record.extend([
+            row.groups.to_string(),
+            row.failed_groups.to_string(),
+            row.spots.to_string(),
+            metric(row.fraction_bad),
+            metric(row.fraction_empty),
+            metric(row.mean_position_offset),
+            metric(row.diameter_cv),
+        ]);
+        writer.write_record(&record)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GridParams;
+    use crate::grid_quality::GridQuality;
+    use crate::types::Spot;
+    use ndarray::Array2;
+
+    fn spot(grid_x: f64, diameter: f64, is_bad: bool, is_empty: bool) -> Spot {
+        Spot {
+            id: "A".to_string(),
+            row: 1,
+            col: 1,
+            is_reference: false,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            x_fixed: 0.0,
+            y_fixed: 0.0,
+            grid_x,
+            grid_y: 0.0,
+            diameter,
+            is_manual: false,
+            manual_source: None,
+            is_bad,
+            is_empty,
+            rotation: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_qc_summary_of_located_spots() {
+        let located = LocatedSpots {
+            params: GridParams {
+                spot_pitch: 20.0,
+                ..GridParams::default()
+            },
+            grid_image: ImageData::new(Array2::zeros((2, 2)), "img".to_string()),
+            grid_positions: vec![(0.0, 0.0); 4],
+            // One bad, one empty, two good spots 0.1 and 0 pitch off the grid
+            spots: vec![
+                spot(5.0, 3.0, true, false),
+                spot(0.0, 0.0, false, true),
+                spot(2.0, 10.0, false, false),
+                spot(0.0, 14.0, false, false),
+            ],
+            quality: GridQuality {
+                peak_zscore: None,
+                reference_fraction: f64::NAN,
+                residual_rms: 0.05,
+                score: 1.0,
+            },
+            model_fit: None,
+        };
+
+        let mut totals = QcTotals::default();
+        totals.add(&located);
+        totals.merge(QcTotals {
+            failed_groups: 1,
+            ..QcTotals::default()
+        });
+        let row = totals.row(vec![("grdSpotSize".to_string(), Value::from(0.6))]);
+        assert_eq!((row.groups, row.failed_groups, row.spots), (2, 1, 4));
+        assert_eq!((row.fraction_bad, row.fraction_empty), (0.25, 0.25));
+        assert!((row.mean_position_offset - 0.05).abs() < 1e-12);
+        assert!((row.diameter_cv - 8f64.sqrt() / 12.0).abs() < 1e-12);
+
+        let path = std::env::temp_dir().join("pamsoft_sweep_summary.csv");
+        write_sweep_summary(&[row], &path).unwrap();
+        let summary = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(
+            lines[0],
+            "grdSpotSize,groups,failedGroups,spots,fractionBad,fractionEmpty,meanPositionOffset,diameterCv"
+        );
+        assert!(lines[1].starts_with("0.6,2,1,4,0.25,0.25,0.05"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn test_combinations_of_ranges_and_lists() {
+        let sweep: SweepConfig = serde_json::from_str(
+            r#"{"parameters": {
+                "grdSpotSize": {"from": 0.5, "to": 0.7, "step": 0.1},
+                "segMethod": ["Edge", "Threshold"]}}"#,
+        )
+        .unwrap();
+
+        let combinations = combinations(&sweep).unwrap();
+        assert_eq!(combinations.len(), 6);
+        assert_eq!(
+            combinations[0],
+            vec![
+                ("grdSpotSize".to_string(), Value::from(0.5)),
+                ("segMethod".to_string(), Value::from("Edge")),
+            ]
+        );
+        // Range ends are included without floating point drift
+        assert_eq!(combinations[5][0].1, Value::from(0.7));
+        assert_eq!(combinations[5][1].1, Value::from("Threshold"));
+
+        let backwards = SweepValues::Range {
+            from: 1.0,
+            to: 0.0,
+            step: 0.1,
+        };
+        assert!(sweep_values(&backwards).is_err());
+        assert!(sweep_values(&SweepValues::List(Vec::new())).is_err());
+    }
+}
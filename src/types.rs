@@ -1,5 +1,7 @@
+use crate::config::{GridParams, IntensityThreshold};
 use ndarray::Array2;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
 
 /// Represents a single spot on the array
 #[derive(Debug, Clone)]
@@ -16,6 +18,8 @@ pub struct Spot {
     pub grid_y: f64,
     pub diameter: f64,
     pub is_manual: bool,
+    /// Origin of the manual override, e.g. `overrides.csv:12`
+    pub manual_source: Option<String>,
     pub is_bad: bool,
     pub is_empty: bool,
     pub rotation: f64,
@@ -53,20 +57,98 @@ pub struct SpotResult {
 
     pub diameter: f64,
 
-    #[serde(rename = "isManual")]
-    pub is_manual: i32,
+    #[serde(
+        rename = "isManual",
+        serialize_with = "serialize_flag",
+        deserialize_with = "deserialize_flag"
+    )]
+    pub is_manual: bool,
 
-    #[serde(rename = "segIsBad")]
-    pub is_bad: i32,
+    #[serde(
+        rename = "segIsBad",
+        serialize_with = "serialize_flag",
+        deserialize_with = "deserialize_flag"
+    )]
+    pub is_bad: bool,
 
-    #[serde(rename = "segIsEmpty")]
-    pub is_empty: i32,
+    #[serde(
+        rename = "segIsEmpty",
+        serialize_with = "serialize_flag",
+        deserialize_with = "deserialize_flag"
+    )]
+    pub is_empty: bool,
 
     #[serde(rename = "grdRotation")]
     pub rotation: f64,
 
+    /// Image the spot was quantified on: the `grdUseImage` image, or each
+    /// series image in series mode
     #[serde(rename = "grdImageNameUsed")]
     pub image_name: String,
+
+    /// Origin of the manual override, empty for segmented spots
+    #[serde(rename = "manualSource", default)]
+    pub manual_source: String,
+
+    /// Grid quality score of the group (see `grid_quality`)
+    #[serde(rename = "grdQuality", default)]
+    pub grid_quality: f64,
+
+    /// The group's grid quality is below `grdMinQuality`
+    #[serde(
+        rename = "grdNeedsReview",
+        default,
+        serialize_with = "serialize_flag",
+        deserialize_with = "deserialize_flag"
+    )]
+    pub needs_review: bool,
+
+    /// Drift of this image relative to the grid image in pixels, already
+    /// applied to gridX/gridY; 0 without `drift_correction`
+    #[serde(rename = "qntDriftX", default)]
+    pub drift_x: f64,
+
+    #[serde(rename = "qntDriftY", default)]
+    pub drift_y: f64,
+
+    /// Drift rotation in degrees, already included in grdRotation
+    #[serde(rename = "qntDriftRotation", default)]
+    pub drift_rotation: f64,
+
+    /// Phase correlation peak of the drift registration, 0 if the image was not registered
+    #[serde(rename = "qntDriftPeak", default)]
+    pub drift_peak: f64,
+}
+
+#[cfg(test)]
+impl SpotResult {
+    /// Segmented spot, for building test rows
+    pub(crate) fn example(group_id: &str, spot_id: &str) -> Self {
+        SpotResult {
+            group_id: group_id.to_string(),
+            spot_id: spot_id.to_string(),
+            is_reference: false,
+            row: 1.0,
+            col: 2.0,
+            x_fixed: 0.0,
+            y_fixed: 0.0,
+            grid_x: 10.5,
+            grid_y: 20.0,
+            diameter: 12.0,
+            is_manual: false,
+            is_bad: false,
+            is_empty: false,
+            rotation: 0.0,
+            image_name: "img".to_string(),
+            manual_source: String::new(),
+            grid_quality: 0.9,
+            needs_review: false,
+            drift_x: 0.0,
+            drift_y: 0.0,
+            drift_rotation: 0.0,
+            drift_peak: 0.0,
+        }
+    }
 }
 
 /// Image data container
@@ -117,7 +199,12 @@ impl ImageType {
 }
 
 /// Configuration for a single image group
+///
+/// Omitted parameters take the MATLAB defaults (`GridParams::default()`).
+/// Keys without a field here are kept in `extra`, so they can be reported by
+/// `schema::validate_batch_config` instead of being dropped silently.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GroupConfig {
     #[serde(rename = "groupId")]
     pub group_id: String,
@@ -128,60 +215,496 @@ pub struct GroupConfig {
     #[serde(rename = "sqcMaxDiameter")]
     pub max_diameter: f64,
 
-    #[serde(rename = "segEdgeSensitivity")]
+    #[serde(rename = "sqcMinSnr")]
+    pub min_snr: f64,
+
+    /// Maximum offset of regular spots from the grid, relative to the pitch
+    #[serde(rename = "sqcMaxPositionOffset")]
+    pub max_position_offset: f64,
+
+    /// Maximum offset of reference spots from the grid, relative to the pitch
+    #[serde(rename = "sqcMaxPositionOffsetRefs")]
+    pub max_position_offset_refs: f64,
+
+    /// Grid quality score (0-1) below which the group is reviewed or failed
+    #[serde(rename = "grdMinQuality")]
+    pub min_grid_quality: f64,
+
+    /// Below `grdMinQuality`: "Review" (default, flag the results) or "Fail"
+    #[serde(rename = "grdQualityAction")]
+    pub grid_quality_action: Option<String>,
+
+    /// [low, high]; a single number is read as a one-element list
+    #[serde(rename = "segEdgeSensitivity", deserialize_with = "deserialize_number_list")]
     pub edge_sensitivity: Vec<f64>,
 
+    /// Segmentation area around each spot, relative to the pitch
+    #[serde(rename = "segAreaSize")]
+    pub area_size: f64,
+
+    #[serde(rename = "segMinEdgePixels")]
+    pub min_edge_pixels: usize,
+
+    #[serde(rename = "segBgOffset")]
+    pub bg_offset: f64,
+
     #[serde(rename = "qntSeriesMode")]
     pub series_mode: i32,
 
-    #[serde(rename = "qntShowPamGridViewer")]
-    pub show_viewer: i32,
+    /// Register series images to the grid image before quantification
+    #[serde(rename = "qntDriftCorrection", deserialize_with = "deserialize_flag")]
+    pub drift_correction: bool,
+
+    /// Maximum rotation in degrees searched by drift registration
+    #[serde(rename = "qntDriftMaxRotation")]
+    pub drift_max_rotation: f64,
+
+    #[serde(rename = "qntShowPamGridViewer", deserialize_with = "deserialize_flag")]
+    pub show_viewer: bool,
 
+    /// Spot pitch in pixels, 0 to detect it from the image size
     #[serde(rename = "grdSpotPitch")]
     pub spot_pitch: f64,
 
     #[serde(rename = "grdSpotSize")]
     pub spot_size: f64,
 
-    #[serde(rename = "grdRotation")]
+    #[serde(rename = "grdSearchDiameter")]
+    pub search_diameter: f64,
+
+    /// Rotations tried in degrees; a single number is read as a one-element list
+    #[serde(rename = "grdRotation", deserialize_with = "deserialize_number_list")]
     pub rotation: Vec<f64>,
 
+    /// Grid detector: "Template" (default), "FFT", "Hybrid" or "Reference"
+    #[serde(rename = "grdDetectionMethod")]
+    pub grid_detection_method: Option<String>,
+
+    /// Grid model refitted to segmented spots: "Rigid" (default), "Affine" or "Polynomial"
+    #[serde(rename = "grdModel")]
+    pub grid_model: Option<String>,
+
     #[serde(rename = "qntSaturationLimit")]
     pub saturation_limit: f64,
 
+    /// Top-hat disk for grid preprocessing, relative to the pitch
+    #[serde(rename = "prpLargeDisk")]
+    pub large_disk: f64,
+
+    /// Opening disk for grid preprocessing, relative to the pitch
+    #[serde(rename = "prpSmallDisk")]
+    pub small_disk: f64,
+
+    /// Sensor bit depth (12 if omitted, 16 for 16-bit cameras)
+    #[serde(rename = "prpBitDepth")]
+    pub bit_depth: Option<u32>,
+
+    /// Intensity normalization: "Max" (default), "BitDepth" or "Percentile"
+    #[serde(rename = "prpIntensityNormalization")]
+    pub intensity_normalization: Option<String>,
+
+    /// Percentile used as reference with "Percentile" normalization
+    #[serde(rename = "prpNormalizationPercentile")]
+    pub normalization_percentile: Option<f64>,
+
+    /// Empty-spot cutoff, e.g. {"relative": 0.1} or {"absolute": 250}
+    #[serde(rename = "sqcEmptyThreshold")]
+    pub empty_threshold: Option<IntensityThreshold>,
+
+    /// Minimum peak accepted when refining grid positions
+    #[serde(rename = "grdRefineThreshold")]
+    pub refine_threshold: Option<IntensityThreshold>,
+
     #[serde(rename = "segMethod")]
     pub seg_method: String,
 
+    /// CSV/TSV of manual spot corrections applied after segmentation
+    #[serde(rename = "segManualSpotsFile")]
+    pub manual_spots_file: Option<String>,
+
+    /// JSON grid edit (see [`GridEdit`]) applied to the detected grid; an edited
+    /// grid is not refitted with `grdModel`
+    #[serde(rename = "grdEditFile")]
+    pub grid_edit_file: Option<String>,
+
     #[serde(rename = "grdUseImage")]
     pub use_image: String,
 
     #[serde(rename = "pgMode")]
     pub pg_mode: String,
 
-    #[serde(rename = "dbgShowPresenter")]
-    pub debug_show: i32,
+    #[serde(rename = "dbgShowPresenter", deserialize_with = "deserialize_flag")]
+    pub debug_show: bool,
+
+    #[serde(rename = "dbgPrintOutput", deserialize_with = "deserialize_flag")]
+    pub debug_print: bool,
 
     #[serde(rename = "arraylayoutfile")]
     pub array_layout_file: String,
 
     #[serde(rename = "imageslist")]
     pub images_list: Vec<String>,
+
+    /// Parameters not used by this implementation, kept as given
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl Default for GroupConfig {
+    fn default() -> Self {
+        let params = GridParams::default();
+        Self {
+            group_id: String::new(),
+            min_diameter: params.min_diameter,
+            max_diameter: params.max_diameter,
+            min_snr: params.min_snr,
+            max_position_offset: params.max_position_offset,
+            max_position_offset_refs: params.max_position_offset_refs,
+            min_grid_quality: params.min_grid_quality,
+            grid_quality_action: None,
+            edge_sensitivity: params.edge_sensitivity.to_vec(),
+            area_size: params.area_size,
+            min_edge_pixels: params.min_edge_pixels,
+            bg_offset: params.bg_offset,
+            series_mode: 0,
+            drift_correction: params.drift_correction,
+            drift_max_rotation: params.drift_max_rotation,
+            show_viewer: false,
+            spot_pitch: params.spot_pitch,
+            spot_size: params.spot_size,
+            search_diameter: params.search_diameter,
+            rotation: params.rotation_range,
+            grid_detection_method: None,
+            grid_model: None,
+            saturation_limit: params.saturation_limit,
+            large_disk: params.large_disk,
+            small_disk: params.small_disk,
+            bit_depth: None,
+            intensity_normalization: None,
+            normalization_percentile: None,
+            empty_threshold: None,
+            refine_threshold: None,
+            seg_method: "Edge".to_string(),
+            manual_spots_file: None,
+            grid_edit_file: None,
+            use_image: "Last".to_string(),
+            pg_mode: "grid".to_string(),
+            debug_show: false,
+            debug_print: false,
+            array_layout_file: String::new(),
+            images_list: Vec::new(),
+            extra: BTreeMap::new(),
+        }
+    }
 }
 
 /// Batch processing configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BatchConfig {
     pub mode: String,
 
     #[serde(rename = "numWorkers")]
     pub num_workers: usize,
 
+    /// "n/total: message" progress file, none if empty
     #[serde(rename = "progressFile")]
     pub progress_file: String,
 
     #[serde(rename = "outputFile")]
     pub output_file: String,
 
+    /// "CSV", "TSV", "Parquet" or "Arrow"; from the outputFile extension if omitted
+    #[serde(rename = "outputFormat")]
+    pub output_format: Option<String>,
+
+    /// "Input" (default) writes groups in configuration order, "Completion" as they finish
+    #[serde(rename = "outputOrder")]
+    pub output_order: Option<String>,
+
+    /// "FailFast" (default) or "Continue" when a group fails
+    #[serde(rename = "onGroupError")]
+    pub on_group_error: Option<String>,
+
+    /// CSV report of failed groups, `<outputFile>.errors.csv` if omitted
+    #[serde(rename = "errorReportFile")]
+    pub error_report_file: Option<String>,
+
+    /// JSON-lines progress event file (`-` for stdout)
+    #[serde(rename = "progressEventsFile")]
+    pub progress_events_file: Option<String>,
+
+    /// Parameter ranges for `"mode": "sweep"`
+    pub sweep: Option<SweepConfig>,
+
+    /// Image groups read from a Tercen input table, added to `imageGroups`
+    pub tercen: Option<TercenConfig>,
+
     #[serde(rename = "imageGroups")]
     pub image_groups: Vec<GroupConfig>,
 }
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            mode: "batch".to_string(),
+            num_workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            progress_file: String::new(),
+            output_file: String::new(),
+            output_format: None,
+            output_order: None,
+            on_group_error: None,
+            error_report_file: None,
+            progress_events_file: None,
+            sweep: None,
+            tercen: None,
+            image_groups: Vec::new(),
+        }
+    }
+}
+
+/// Tercen input table of a batch and the columns that define its image groups
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TercenConfig {
+    /// Table in Tercen JSON representation, one row per image
+    #[serde(rename = "inputTable")]
+    pub input_table: String,
+
+    #[serde(rename = "groupColumn")]
+    pub group_column: String,
+
+    /// Image paths or document ids (resolved against `documentDir`)
+    #[serde(rename = "imageColumn")]
+    pub image_column: String,
+
+    /// Array layout file per row; `parameters.arraylayoutfile` if the column is absent
+    #[serde(rename = "layoutColumn")]
+    pub layout_column: String,
+
+    #[serde(rename = "documentDir")]
+    pub document_dir: Option<String>,
+
+    /// Parameters of every group read from the table
+    pub parameters: GroupConfig,
+}
+
+impl Default for TercenConfig {
+    fn default() -> Self {
+        Self {
+            input_table: String::new(),
+            group_column: "groupId".to_string(),
+            image_column: "documentId".to_string(),
+            layout_column: "arraylayoutfile".to_string(),
+            document_dir: None,
+            parameters: GroupConfig::default(),
+        }
+    }
+}
+
+/// Values of one swept parameter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SweepValues {
+    /// `{"from": 0.5, "to": 0.9, "step": 0.1}`, both ends included
+    Range { from: f64, to: f64, step: f64 },
+    /// Explicit values, e.g. `["Edge", "Threshold"]` or `[[0, 0.01], [0, 0.02]]`
+    List(Vec<serde_json::Value>),
+}
+
+/// Parameter sweep over the image groups of a batch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SweepConfig {
+    /// Group parameters by MATLAB name; every combination is run
+    pub parameters: BTreeMap<String, SweepValues>,
+
+    /// QC summary table, `outputFile` if omitted
+    #[serde(rename = "summaryFile")]
+    pub summary_file: Option<String>,
+}
+
+/// Manual adjustment of a detected grid
+///
+/// `{"translate": [2, -1.5], "rotate": 0.5, "scalePitch": 1.01}`; omitted
+/// entries leave the grid unchanged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GridEdit {
+    /// Shift of the grid center in pixels (x, y)
+    pub translate: (f64, f64),
+
+    /// Rotation about the grid center in degrees
+    pub rotate: f64,
+
+    /// Factor applied to the spot pitch
+    #[serde(rename = "scalePitch")]
+    pub scale_pitch: f64,
+}
+
+impl Default for GridEdit {
+    fn default() -> Self {
+        Self {
+            translate: (0.0, 0.0),
+            rotate: 0.0,
+            scale_pitch: 1.0,
+        }
+    }
+}
+
+/// Parameters of a single MATLAB-style run (`pamsoft_grid --param-file`)
+///
+/// Mirrors input_params_gridding.json / input_params_quantification.json.
+/// Omitted parameters take the MATLAB defaults; unknown keys such as
+/// `_grdUseImageOptions` are ignored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SingleRunConfig {
+    /// "grid" or "quantification"
+    #[serde(rename = "pgMode")]
+    pub pg_mode: String,
+
+    #[serde(rename = "sqcMinDiameter")]
+    pub min_diameter: f64,
+
+    #[serde(rename = "sqcMaxDiameter")]
+    pub max_diameter: f64,
+
+    #[serde(rename = "segEdgeSensitivity", deserialize_with = "deserialize_number_list")]
+    pub edge_sensitivity: Vec<f64>,
+
+    #[serde(rename = "segMethod")]
+    pub seg_method: String,
+
+    #[serde(rename = "qntSeriesMode")]
+    pub series_mode: i32,
+
+    #[serde(rename = "qntShowPamGridViewer", deserialize_with = "deserialize_flag")]
+    pub show_viewer: bool,
+
+    #[serde(rename = "qntSaturationLimit")]
+    pub saturation_limit: f64,
+
+    #[serde(rename = "grdSpotPitch")]
+    pub spot_pitch: f64,
+
+    #[serde(rename = "grdSpotSize")]
+    pub spot_size: f64,
+
+    #[serde(rename = "grdRotation", deserialize_with = "deserialize_number_list")]
+    pub rotation: Vec<f64>,
+
+    /// Image used for gridding: "First" or "Last"; other MATLAB options use the first image
+    #[serde(rename = "grdUseImage")]
+    pub use_image: String,
+
+    #[serde(rename = "arraylayoutfile")]
+    pub array_layout_file: String,
+
+    #[serde(rename = "imageslist")]
+    pub images_list: Vec<String>,
+
+    /// Result file: the grid table in "grid" mode, the quantification table otherwise
+    #[serde(rename = "outputfile")]
+    pub output_file: String,
+
+    /// Grid table written by a previous "grid" run (quantification mode)
+    #[serde(rename = "griddingoutputfile")]
+    pub gridding_output_file: Option<String>,
+
+    #[serde(rename = "dbgPrintOutput", deserialize_with = "deserialize_flag")]
+    pub debug_print: bool,
+
+    #[serde(rename = "verbose", deserialize_with = "deserialize_flag")]
+    pub verbose: bool,
+}
+
+impl Default for SingleRunConfig {
+    fn default() -> Self {
+        let params = GridParams::default();
+        Self {
+            pg_mode: String::new(),
+            min_diameter: params.min_diameter,
+            max_diameter: params.max_diameter,
+            edge_sensitivity: params.edge_sensitivity.to_vec(),
+            seg_method: "Edge".to_string(),
+            series_mode: 0,
+            show_viewer: false,
+            saturation_limit: params.saturation_limit,
+            spot_pitch: params.spot_pitch,
+            spot_size: params.spot_size,
+            rotation: params.rotation_range,
+            use_image: "Last".to_string(),
+            array_layout_file: String::new(),
+            images_list: Vec::new(),
+            output_file: String::new(),
+            gridding_output_file: None,
+            debug_print: false,
+            verbose: false,
+        }
+    }
+}
+
+impl SingleRunConfig {
+    /// Equivalent batch group configuration, so both modes share parameter handling
+    pub fn group_config(&self) -> GroupConfig {
+        GroupConfig {
+            group_id: String::new(),
+            min_diameter: self.min_diameter,
+            max_diameter: self.max_diameter,
+            edge_sensitivity: self.edge_sensitivity.clone(),
+            series_mode: self.series_mode,
+            show_viewer: self.show_viewer,
+            spot_pitch: self.spot_pitch,
+            spot_size: self.spot_size,
+            rotation: self.rotation.clone(),
+            saturation_limit: self.saturation_limit,
+            seg_method: self.seg_method.clone(),
+            use_image: self.use_image.clone(),
+            pg_mode: self.pg_mode.clone(),
+            debug_print: self.debug_print,
+            array_layout_file: self.array_layout_file.clone(),
+            images_list: self.images_list.clone(),
+            ..GroupConfig::default()
+        }
+    }
+}
+
+/// Number or list of numbers, as MATLAB writes scalars without brackets
+pub fn deserialize_number_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<f64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberList {
+        One(f64),
+        Many(Vec<f64>),
+    }
+
+    Ok(match NumberList::deserialize(deserializer)? {
+        NumberList::One(value) => vec![value],
+        NumberList::Many(values) => values,
+    })
+}
+
+/// Flag written as `1`/`0`, as in the MATLAB result tables
+pub fn serialize_flag<S: Serializer>(value: &bool, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_u8(*value as u8)
+}
+
+/// MATLAB-style flag: `true`/`false`, `1`/`0` or "yes"/"no" (also "on"/"off", "true"/"false")
+pub fn deserialize_flag<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<bool, D::Error> {
+    use serde::de::Error;
+
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Bool(b) => Ok(b),
+        serde_json::Value::Number(n) => Ok(n.as_f64().is_some_and(|v| v != 0.0)),
+        serde_json::Value::String(s) => match s.trim().to_lowercase().as_str() {
+            "yes" | "y" | "on" | "true" | "1" => Ok(true),
+            "no" | "n" | "off" | "false" | "0" | "" => Ok(false),
+            other => Err(D::Error::custom(format!("invalid flag value: {:?}", other))),
+        },
+        serde_json::Value::Null => Ok(false),
+        other => Err(D::Error::custom(format!("invalid flag value: {}", other))),
+    }
+}
@@ -0,0 +1,66 @@
+/// Tests of the pamsoft_grid_batch command line
+
+use std::path::Path;
+use std::process::Command;
+
+/// Image group whose image cannot be loaded
+fn missing_image_group(group_id: &str, layout_file: &Path) -> serde_json::Value {
+    serde_json::json!({
+        "groupId": group_id,
+        "sqcMinDiameter": 0.45,
+        "sqcMaxDiameter": 0.85,
+        "segEdgeSensitivity": [0, 0.01],
+        "qntSeriesMode": 0,
+        "qntShowPamGridViewer": 0,
+        "grdSpotPitch": 21.5,
+        "grdSpotSize": 0.66,
+        "grdRotation": [0],
+        "qntSaturationLimit": 4095,
+        "segMethod": "Edge",
+        "grdUseImage": "First",
+        "pgMode": "grid",
+        "dbgShowPresenter": 0,
+        "arraylayoutfile": layout_file,
+        "imageslist": [format!("/nonexistent/{}.tif", group_id)]
+    })
+}
+
+#[test]
+fn test_progress_events_on_stdout_are_json_lines() {
+    let dir = std::env::temp_dir().join("pamsoft_cli_stdout_events");
+    std::fs::create_dir_all(&dir).unwrap();
+    let layout_file = dir.join("layout.txt");
+    std::fs::write(&layout_file, "Row\tCol\tID\n1\t1\tS1\n").unwrap();
+    let config = serde_json::json!({
+        "mode": "batch",
+        "numWorkers": 2,
+        "progressFile": dir.join("progress.txt"),
+        "outputFile": dir.join("results.csv"),
+        "onGroupError": "Continue",
+        "imageGroups": [
+            missing_image_group("a", &layout_file),
+            missing_image_group("b", &layout_file)
+        ]
+    });
+    let param_file = dir.join("batch.json");
+    std::fs::write(&param_file, config.to_string()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pamsoft_grid_batch"))
+        .arg("--param-file")
+        .arg(&param_file)
+        .args(["--progress-events", "-"])
+        .output()
+        .unwrap();
+
+    // Both groups fail, the run itself completes
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{:?}: {}", line, e)))
+        .collect();
+    assert_eq!(events.first().unwrap()["event"], "batch_started", "{}", stdout);
+    assert_eq!(events.last().unwrap()["event"], "batch_finished", "{}", stdout);
+    // Log lines still go to stderr
+    assert!(String::from_utf8_lossy(&output.stderr).contains("2 failed group(s)"));
+}
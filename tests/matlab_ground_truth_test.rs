@@ -120,6 +120,7 @@ fn load_initial_spots() -> Result<Vec<Spot>, Box<dyn std::error::Error>> {
             grid_y,
             diameter: 0.0,
             is_manual: false,
+            manual_source: None,
             is_bad: false,
             is_empty: false,
         });
@@ -262,6 +262,7 @@ mod integration_tests {
             grid_y: center_y as f64,
             diameter: 0.0,
             is_manual: false,
+            manual_source: None,
             is_bad: false,
             is_empty: false,
         };
@@ -335,6 +336,7 @@ mod integration_tests {
             grid_y: center_y as f64,
             diameter: 0.0,
             is_manual: false,
+            manual_source: None,
             is_bad: false,
             is_empty: false,
         };
@@ -401,6 +403,7 @@ mod integration_tests {
             grid_y: center_y as f64,
             diameter: 0.0,
             is_manual: false,
+            manual_source: None,
             is_bad: false,
             is_empty: false,
         };